@@ -0,0 +1,156 @@
+//! Flushing an [`Outbox`] from a service worker when connectivity returns, through the
+//! Background Sync API, so that events reach the server even after every tab is closed.
+
+use crate::{IndexDbAggregateError, Outbox};
+use cqrs_es::persist::SerializedEvent;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use js_sys::{Function, Promise, Reflect};
+use std::future::Future;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{ExtendableEvent, ServiceWorkerGlobalScope};
+
+type Push =
+    Box<dyn Fn(Vec<SerializedEvent>) -> LocalBoxFuture<'static, Result<(), IndexDbAggregateError>>>;
+
+struct Handler {
+    tag: String,
+    outbox: Outbox,
+    push: Push,
+}
+
+impl Handler {
+    async fn flush(&self) -> Result<usize, IndexDbAggregateError> {
+        self.outbox.flush(|events| (self.push)(events)).await
+    }
+}
+
+/// Flushes an [`Outbox`] whenever the browser fires a `sync` event of a given tag in the
+/// current service worker, for as long as it is kept alive.
+///
+/// The browser fires the event once the device is online after a page
+/// [registered](BackgroundSync::register) the tag, and retries it later if the flush fails.
+/// The repository itself works in a service worker as in a window, IndexedDB being reached
+/// through the global scope.
+///
+/// ```no_run
+/// use indexdb_es::{BackgroundSync, IndexDbEventRepository, Outbox};
+///
+/// // In the script of the service worker
+/// let outbox = Outbox::new(&IndexDbEventRepository::new(None, None), "server");
+/// let sync = BackgroundSync::listen("outbox", outbox, |events| async move {
+///     // Send `events` to the server
+///     Ok(())
+/// })
+/// .unwrap();
+/// std::mem::forget(sync);
+/// ```
+pub struct BackgroundSync {
+    handler: Rc<Handler>,
+    scope: ServiceWorkerGlobalScope,
+    on_sync: Closure<dyn FnMut(ExtendableEvent)>,
+}
+
+impl BackgroundSync {
+    /// Flushes `outbox` with `push` on every `sync` event tagged `tag`.
+    pub fn listen<F, Fut>(tag: &str, outbox: Outbox, push: F) -> Result<Self, IndexDbAggregateError>
+    where
+        F: Fn(Vec<SerializedEvent>) -> Fut + 'static,
+        Fut: Future<Output = Result<(), IndexDbAggregateError>> + 'static,
+    {
+        let scope: ServiceWorkerGlobalScope = js_sys::global().dyn_into().map_err(|_| {
+            IndexDbAggregateError::ConnectionError("not in a service worker".to_string())
+        })?;
+        let handler = Rc::new(Handler {
+            tag: tag.to_string(),
+            outbox,
+            push: Box::new(move |events| push(events).boxed_local()),
+        });
+        let on_sync = Closure::<dyn FnMut(ExtendableEvent)>::new({
+            let handler = handler.clone();
+            move |event: ExtendableEvent| {
+                let tag = Reflect::get(&event, &JsValue::from_str("tag")).ok();
+                if tag.and_then(|tag| tag.as_string()).as_deref() != Some(handler.tag.as_str()) {
+                    return;
+                }
+                let handler = handler.clone();
+                let flush = future_to_promise(async move {
+                    match handler.flush().await {
+                        Ok(pushed) => Ok(JsValue::from(pushed as u32)),
+                        Err(err) => Err(JsValue::from_str(&err.to_string())),
+                    }
+                });
+                // A rejected promise has the browser retry the sync later
+                let _ = event.wait_until(&flush);
+            }
+        });
+        scope
+            .add_event_listener_with_callback("sync", on_sync.as_ref().unchecked_ref())
+            .map_err(connection_error)?;
+
+        Ok(Self {
+            handler,
+            scope,
+            on_sync,
+        })
+    }
+
+    /// Flushes the outbox now, as a `sync` event would. Returns the number of events pushed.
+    pub async fn flush(&self) -> Result<usize, IndexDbAggregateError> {
+        self.handler.flush().await
+    }
+
+    /// Asks the browser to fire a `sync` event tagged `tag` in the service worker once the
+    /// device is online, from a page or from the service worker itself. Returns false where
+    /// Background Sync is not supported, in which case the outbox has to be flushed otherwise.
+    pub async fn register(tag: &str) -> Result<bool, IndexDbAggregateError> {
+        let global = js_sys::global();
+        let registration = match property(&global, "registration") {
+            Some(registration) => registration,
+            None => {
+                let Some(ready) = property(&global, "navigator")
+                    .and_then(|navigator| property(&navigator, "serviceWorker"))
+                    .and_then(|container| property(&container, "ready"))
+                else {
+                    return Ok(false);
+                };
+                let ready: Promise = ready.dyn_into().map_err(connection_error)?;
+                JsFuture::from(ready).await.map_err(connection_error)?
+            }
+        };
+        let Some(sync) = property(&registration, "sync") else {
+            return Ok(false);
+        };
+        let register: Function = Reflect::get(&sync, &JsValue::from_str("register"))
+            .and_then(JsCast::dyn_into)
+            .map_err(connection_error)?;
+        let registered: Promise = register
+            .call1(&sync, &JsValue::from_str(tag))
+            .and_then(JsCast::dyn_into)
+            .map_err(connection_error)?;
+        JsFuture::from(registered).await.map_err(connection_error)?;
+        Ok(true)
+    }
+}
+
+impl Drop for BackgroundSync {
+    fn drop(&mut self) {
+        let _ = self
+            .scope
+            .remove_event_listener_with_callback("sync", self.on_sync.as_ref().unchecked_ref());
+    }
+}
+
+/// The property `name` of `target`, if defined.
+fn property(target: &JsValue, name: &str) -> Option<JsValue> {
+    Reflect::get(target, &JsValue::from_str(name))
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+fn connection_error(err: JsValue) -> IndexDbAggregateError {
+    IndexDbAggregateError::ConnectionError(format!("{err:?}"))
+}
@@ -0,0 +1,131 @@
+use crate::event_repository::CATALOG_STORE;
+use crate::storage::{Database, Key, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{IndexDbAggregateError, IndexDbEventRepository};
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::Aggregate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A row of the aggregate catalog, describing one aggregate instance.
+///
+/// Timestamps are in milliseconds since the Unix epoch. `event_count` is the number of events
+/// appended to the stream, it is not lowered when the stream is truncated.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AggregateSummary {
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    pub last_sequence: usize,
+    pub event_count: usize,
+    pub created_at: f64,
+    pub updated_at: f64,
+}
+
+impl IndexDbEventRepository {
+    /// Lists the aggregate instances of type `A` ordered by id, starting after the id `after`.
+    pub async fn list_aggregates<A: Aggregate>(
+        &self,
+        after: Option<String>,
+        limit: u32,
+    ) -> Result<Vec<AggregateSummary>, IndexDbAggregateError> {
+        let aggregate_type = A::aggregate_type();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[CATALOG_STORE], TransactionMode::ReadOnly)?;
+            let store = transaction.object_store(CATALOG_STORE)?;
+            let range = catalog_range(&aggregate_type, after.as_deref());
+            store
+                .get_all(&range, Some(limit))
+                .await?
+                .into_iter()
+                .map(|value| Ok(serde_json::from_value(value)?))
+                .collect()
+        })
+        .await
+    }
+
+    /// Counts the aggregate instances of type `A`.
+    pub async fn count_aggregates<A: Aggregate>(&self) -> Result<u32, IndexDbAggregateError> {
+        let aggregate_type = A::aggregate_type();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[CATALOG_STORE], TransactionMode::ReadOnly)?;
+            let store = transaction.object_store(CATALOG_STORE)?;
+            store.count(&catalog_range(&aggregate_type, None)).await
+        })
+        .await
+    }
+}
+
+/// Selects the catalog rows of an aggregate type, optionally starting after an aggregate id.
+fn catalog_range(aggregate_type: &str, after: Option<&str>) -> KeyRange {
+    // Arrays sort after strings, so `[aggregate_type, []]` bounds every id of the type
+    let upper = Key::Array(vec![aggregate_type.into(), Key::Array(Vec::new())]);
+    match after {
+        Some(after) => KeyRange::bound(Key::aggregate(aggregate_type, after), upper, true, false),
+        None => KeyRange::bound(Key::aggregate(aggregate_type, ""), upper, false, false),
+    }
+}
+
+/// Records the `events` appended at `now` in the catalog, along with the number of events
+/// `removed` from the same streams by the operation.
+pub(crate) async fn update_catalog(
+    catalog: &impl ObjectStore,
+    events: &[SerializedEvent],
+    removed: usize,
+    now: f64,
+) -> Result<(), IndexDbAggregateError> {
+    let mut appended: BTreeMap<(&str, &str), (usize, usize)> = BTreeMap::new();
+    for event in events {
+        let entry = appended
+            .entry((&event.aggregate_type, &event.aggregate_id))
+            .or_default();
+        entry.0 += 1;
+        entry.1 = entry.1.max(event.sequence);
+    }
+
+    for ((aggregate_type, aggregate_id), (count, last_sequence)) in appended {
+        let existing = catalog
+            .get(&Key::aggregate(aggregate_type, aggregate_id))
+            .await?;
+        let summary = match existing {
+            Some(value) => {
+                let summary = serde_json::from_value::<AggregateSummary>(value)?;
+                AggregateSummary {
+                    last_sequence: summary.last_sequence.max(last_sequence),
+                    event_count: (summary.event_count + count).saturating_sub(removed),
+                    updated_at: now,
+                    ..summary
+                }
+            }
+            None => AggregateSummary {
+                aggregate_type: aggregate_type.to_string(),
+                aggregate_id: aggregate_id.to_string(),
+                last_sequence,
+                event_count: count,
+                created_at: now,
+                updated_at: now,
+            },
+        };
+        catalog.put(&serde_json::to_value(&summary)?).await?;
+    }
+    Ok(())
+}
+
+/// Sets the last sequence of an aggregate instance in the catalog, e.g. once a rebase rewrote
+/// its stream.
+pub(crate) async fn set_last_sequence(
+    catalog: &impl ObjectStore,
+    aggregate_type: &str,
+    aggregate_id: &str,
+    last_sequence: usize,
+) -> Result<(), IndexDbAggregateError> {
+    let key = Key::aggregate(aggregate_type, aggregate_id);
+    if let Some(value) = catalog.get(&key).await? {
+        let summary = AggregateSummary {
+            last_sequence,
+            ..serde_json::from_value::<AggregateSummary>(value)?
+        };
+        catalog.put(&serde_json::to_value(&summary)?).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+/// The source of the time recorded with each event and catalog row, in milliseconds since the
+/// Unix epoch. Tests can inject a deterministic clock with
+/// [`IndexDbEventRepository::with_clock`](crate::IndexDbEventRepository::with_clock).
+pub trait Clock: Send + Sync {
+    fn now(&self) -> f64;
+}
+
+/// The system clock, as reported by `Date.now()` in the browser.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> f64 {
+        js_sys::Date::now()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> f64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0.0, |elapsed| elapsed.as_millis() as f64)
+    }
+}
@@ -0,0 +1,100 @@
+use crate::event_repository::{
+    read_events, stream_range, CATALOG_STORE, SNAPSHOT_STORE, TOMBSTONE_STORE,
+};
+use crate::projection::unproject;
+use crate::storage::{Database, Key, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{IndexDbAggregateError, IndexDbEventRepository};
+use cqrs_es::Aggregate;
+use serde_json::json;
+
+/// Configures [`IndexDbEventRepository::delete_aggregate`].
+#[derive(Clone, Debug, Default)]
+pub struct DeleteOptions {
+    pub(crate) soft: bool,
+    pub(crate) view_stores: Vec<String>,
+}
+
+impl DeleteOptions {
+    /// Keeps the events and writes a tombstone instead: reading the stream or appending to it
+    /// then fails with an `AggregateDeleted` error.
+    pub fn soft(mut self) -> Self {
+        self.soft = true;
+        self
+    }
+
+    /// Also removes the view keyed by the aggregate id from the named view store.
+    pub fn with_view_store(mut self, store_name: impl Into<String>) -> Self {
+        self.view_stores.push(store_name.into());
+        self
+    }
+}
+
+impl IndexDbEventRepository {
+    /// Deletes an aggregate instance within a single transaction.
+    ///
+    /// By default all of its events, its snapshot and its catalog row, along with its views in
+    /// the stores selected in `options` and in the
+    /// [projected](IndexDbEventRepository::with_projection) view stores, are removed and the
+    /// aggregate id may be reused afterwards. A soft delete leaves the events and views in place and records a tombstone
+    /// instead.
+    pub async fn delete_aggregate<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        options: DeleteOptions,
+    ) -> Result<(), IndexDbAggregateError> {
+        let mut store_names = vec![
+            self.store_name.clone(),
+            CATALOG_STORE.to_string(),
+            SNAPSHOT_STORE.to_string(),
+            TOMBSTONE_STORE.to_string(),
+        ];
+        store_names.extend(options.view_stores.iter().cloned());
+        store_names.extend(self.projection_stores());
+        store_names.sort();
+        store_names.dedup();
+        let projections = self.projections.clone();
+        let store_name = self.store_name.clone();
+        let aggregate_type = A::aggregate_type();
+        let aggregate_id = aggregate_id.to_string();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&store_names, TransactionMode::ReadWrite)?;
+
+            let tombstones = transaction.object_store(TOMBSTONE_STORE)?;
+            if options.soft {
+                let tombstone = json!({
+                    "aggregate_type": aggregate_type,
+                    "aggregate_id": aggregate_id,
+                });
+                tombstones.put(&tombstone).await?;
+            } else {
+                let key = KeyRange::only(Key::aggregate(&aggregate_type, &aggregate_id));
+                tombstones.delete(&key).await?;
+                transaction
+                    .object_store(SNAPSHOT_STORE)?
+                    .delete(&key)
+                    .await?;
+                transaction
+                    .object_store(CATALOG_STORE)?
+                    .delete(&key)
+                    .await?;
+                let store = transaction.object_store(&store_name)?;
+                let stream = stream_range(&aggregate_type, &aggregate_id, 0);
+                if !projections.is_empty() {
+                    let history = read_events(&store, &stream).await?;
+                    unproject(&transaction, &projections, &history).await?;
+                }
+                store.delete(&stream).await?;
+                for view_store in &options.view_stores {
+                    transaction
+                        .object_store(view_store)?
+                        .delete(&KeyRange::only(aggregate_id.as_str().into()))
+                        .await?;
+                }
+            }
+
+            transaction.commit().await
+        })
+        .await
+    }
+}
@@ -1,11 +1,13 @@
 // use anyhow::Error;
 use cqrs_es::persist::PersistenceError;
 use cqrs_es::AggregateError;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum IndexDbAggregateError {
     OptimisticLock,
+    AggregateDeleted(String),
     ConnectionError(String),
     DeserializationError(String),
     UnknownError(String),
@@ -15,6 +17,9 @@ impl Display for IndexDbAggregateError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             IndexDbAggregateError::OptimisticLock => write!(f, "optimistic lock error"),
+            IndexDbAggregateError::AggregateDeleted(aggregate_id) => {
+                write!(f, "aggregate {} has been deleted", aggregate_id)
+            }
             IndexDbAggregateError::UnknownError(error) => write!(f, "{}", error),
             IndexDbAggregateError::DeserializationError(error) => write!(f, "{}", error),
             IndexDbAggregateError::ConnectionError(error) => write!(f, "{}", error),
@@ -38,6 +43,9 @@ impl<T: std::error::Error> From<IndexDbAggregateError> for AggregateError<T> {
     fn from(err: IndexDbAggregateError) -> Self {
         match err {
             IndexDbAggregateError::OptimisticLock => AggregateError::AggregateConflict,
+            IndexDbAggregateError::AggregateDeleted(_) => {
+                AggregateError::UnexpectedError(Box::new(err))
+            }
             IndexDbAggregateError::ConnectionError(_) => {
                 AggregateError::DatabaseConnectionError(Box::new(err))
             }
@@ -64,10 +72,68 @@ impl From<serde_json::Error> for IndexDbAggregateError {
     }
 }
 
+impl From<std::io::Error> for IndexDbAggregateError {
+    fn from(err: std::io::Error) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl From<idb::Error> for IndexDbAggregateError {
+    fn from(err: idb::Error) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl From<serde_wasm_bindgen::Error> for IndexDbAggregateError {
+    fn from(err: serde_wasm_bindgen::Error) -> Self {
+        IndexDbAggregateError::DeserializationError(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<redb::DatabaseError> for IndexDbAggregateError {
+    fn from(err: redb::DatabaseError) -> Self {
+        IndexDbAggregateError::ConnectionError(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<redb::TransactionError> for IndexDbAggregateError {
+    fn from(err: redb::TransactionError) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<redb::TableError> for IndexDbAggregateError {
+    fn from(err: redb::TableError) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<redb::StorageError> for IndexDbAggregateError {
+    fn from(err: redb::StorageError) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<redb::CommitError> for IndexDbAggregateError {
+    fn from(err: redb::CommitError) -> Self {
+        IndexDbAggregateError::UnknownError(err.to_string())
+    }
+}
+
 impl From<IndexDbAggregateError> for PersistenceError {
     fn from(err: IndexDbAggregateError) -> Self {
         match err {
             IndexDbAggregateError::OptimisticLock => PersistenceError::OptimisticLockError,
+            IndexDbAggregateError::AggregateDeleted(_) => {
+                PersistenceError::UnknownError(Box::new(err))
+            }
             IndexDbAggregateError::ConnectionError(_) => {
                 PersistenceError::ConnectionError(Box::new(err))
             }
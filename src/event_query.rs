@@ -0,0 +1,147 @@
+use crate::event_repository::{AGGREGATE_RECORDED_AT_INDEX, EVENT_TYPE_INDEX, RECORDED_AT_INDEX};
+use crate::storage::{Database, Index, Key, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{js_event::JsEvent, IndexDbAggregateError, IndexDbEventRepository};
+use cqrs_es::persist::SerializedEvent;
+use futures::stream::{self, Stream, StreamExt};
+
+/// A page of events read in global order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventPage {
+    pub events: Vec<SerializedEvent>,
+    /// The position to pass as `after` to read the next page, `None` once the last page is read.
+    pub next: Option<u64>,
+}
+
+/// An event along with the time it was recorded, in milliseconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedEvent {
+    pub recorded_at: f64,
+    pub event: SerializedEvent,
+}
+
+impl IndexDbEventRepository {
+    /// Returns the events of type `event_type` across all aggregates, in the order they were
+    /// written, starting after the position `after`.
+    pub async fn events_by_type(
+        &self,
+        event_type: &str,
+        after: Option<u64>,
+        limit: u32,
+    ) -> Result<EventPage, IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+        let event_type = event_type.to_string();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let index = transaction
+                .object_store(&store_name)?
+                .index(EVENT_TYPE_INDEX)?;
+            let range = KeyRange::bound(
+                Key::Array(vec![
+                    event_type.as_str().into(),
+                    (after.unwrap_or(0) as f64).into(),
+                ]),
+                Key::Array(vec![event_type.as_str().into(), f64::INFINITY.into()]),
+                true,
+                false,
+            );
+
+            let values = index.get_all(&range, Some(limit)).await?;
+            let mut events = Vec::with_capacity(values.len());
+            let mut last = None;
+            for value in values {
+                let event = serde_json::from_value::<JsEvent>(value)?;
+                last = event.position;
+                events.push(event.into());
+            }
+
+            let next = if events.len() == limit as usize {
+                last
+            } else {
+                None
+            };
+            Ok(EventPage { events, next })
+        })
+        .await
+    }
+
+    /// Streams the events of type `event_type` across all aggregates in the order they were
+    /// written, reading them `page_size` at a time. See [`IndexDbEventRepository::events_by_type`].
+    pub fn stream_events_by_type<'a>(
+        &'a self,
+        event_type: &str,
+        page_size: u32,
+    ) -> impl Stream<Item = Result<SerializedEvent, IndexDbAggregateError>> + 'a {
+        let event_type = event_type.to_string();
+
+        // The state is the position to read after, `None` once the last page has been read
+        stream::unfold(Some(None), move |after| {
+            let event_type = event_type.clone();
+            async move {
+                let page = self.events_by_type(&event_type, after?, page_size).await;
+                Some(match page {
+                    Ok(page) => (Ok(page.events), page.next.map(Some)),
+                    Err(err) => (Err(err), None),
+                })
+            }
+        })
+        .flat_map(|page| {
+            stream::iter(match page {
+                Ok(events) => events.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            })
+        })
+    }
+
+    /// Returns the events recorded from `from` (inclusive) until `until` (exclusive), in the
+    /// order they were recorded, optionally only those of `aggregate_type`.
+    ///
+    /// Events written before the store recorded timestamps (schema version 6) are not indexed
+    /// and thus not returned.
+    pub async fn events_between(
+        &self,
+        from: f64,
+        until: f64,
+        aggregate_type: Option<&str>,
+    ) -> Result<Vec<RecordedEvent>, IndexDbAggregateError> {
+        if from >= until {
+            return Ok(Vec::new());
+        }
+        let store_name = self.store_name.clone();
+        let aggregate_type = aggregate_type.map(str::to_string);
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let store = transaction.object_store(&store_name)?;
+            let (index, range) = match aggregate_type {
+                Some(aggregate_type) => (
+                    store.index(AGGREGATE_RECORDED_AT_INDEX)?,
+                    KeyRange::bound(
+                        Key::Array(vec![aggregate_type.as_str().into(), from.into()]),
+                        Key::Array(vec![aggregate_type.as_str().into(), until.into()]),
+                        false,
+                        true,
+                    ),
+                ),
+                None => (
+                    store.index(RECORDED_AT_INDEX)?,
+                    KeyRange::bound(from.into(), until.into(), false, true),
+                ),
+            };
+
+            index
+                .get_all(&range, None)
+                .await?
+                .into_iter()
+                .map(|value| {
+                    let event = serde_json::from_value::<JsEvent>(value)?;
+                    Ok(RecordedEvent {
+                        recorded_at: event.recorded_at.unwrap_or_default(),
+                        event: event.into(),
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+}
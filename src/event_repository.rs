@@ -1,21 +1,36 @@
-use crate::{js_event::JsEvent, IndexDbAggregateError};
+use crate::catalog::update_catalog;
+use crate::projection::{project, projection_schema, Projector};
+use crate::projection_runner::Replayer;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::memory_db_name;
+use crate::storage::{
+    log_db_name, run_in, Database, Db, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema,
+    Transaction, TransactionMode,
+};
+use crate::{js_event::JsEvent, Clock, IndexDbAggregateError, SystemClock};
 use async_trait::async_trait;
 use cqrs_es::persist::{
     PersistedEventRepository, PersistenceError, ReplayStream, SerializedEvent, SerializedSnapshot,
 };
 use cqrs_es::Aggregate;
-use futures::channel::oneshot::channel;
-use gloo_utils::format::JsValueSerdeExt;
-use idb::*;
-use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::spawn_local;
-// use futures::SinkExt;
+use futures::lock::Mutex;
 use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
 
-/// An event repository relying on a IndexDb database for persistence.
+/// An event repository relying on IndexedDB for persistence in the browser, and on an embedded
+/// redb database with the same stores and semantics everywhere else.
 pub struct IndexDbEventRepository {
-    db_name: String,
-    store_name: String,
+    pub(crate) db_name: String,
+    pub(crate) store_name: String,
+    pub(crate) snapshot_truncation: Option<usize>,
+    pub(crate) snapshot_size: Option<usize>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) metadata_indexes: Vec<String>,
+    pub(crate) projections: Vec<Arc<dyn Projector>>,
+    /// Whether the projections were caught up since the repository was created.
+    pub(crate) projections_caught_up: Mutex<bool>,
 }
 
 #[async_trait]
@@ -24,7 +39,9 @@ impl PersistedEventRepository for IndexDbEventRepository {
         &self,
         aggregate_id: &str,
     ) -> Result<Vec<SerializedEvent>, PersistenceError> {
-        self.select_events::<A>(aggregate_id).await
+        Ok(self
+            .select_events(&A::aggregate_type(), aggregate_id, 0)
+            .await?)
     }
 
     async fn get_last_events<A: Aggregate>(
@@ -32,14 +49,18 @@ impl PersistedEventRepository for IndexDbEventRepository {
         aggregate_id: &str,
         last_sequence: usize,
     ) -> Result<Vec<SerializedEvent>, PersistenceError> {
-        todo!()
+        Ok(self
+            .select_events(&A::aggregate_type(), aggregate_id, last_sequence + 1)
+            .await?)
     }
 
     async fn get_snapshot<A: Aggregate>(
         &self,
         aggregate_id: &str,
     ) -> Result<Option<SerializedSnapshot>, PersistenceError> {
-        todo!()
+        Ok(self
+            .select_snapshot(&A::aggregate_type(), aggregate_id)
+            .await?)
     }
 
     async fn persist<A: Aggregate>(
@@ -52,14 +73,14 @@ impl PersistedEventRepository for IndexDbEventRepository {
                 self.insert_events::<A>(events).await?;
             }
             Some((aggregate_id, aggregate, current_snapshot)) => {
-                // if current_snapshot == 1 {
-                //     self.insert::<A>(aggregate, aggregate_id, current_snapshot, events)
-                //         .await?;
-                // } else {
-                //     self.update::<A>(aggregate, aggregate_id, current_snapshot, events)
-                //         .await?;
-                // }
-                todo!()
+                self.insert_with_snapshot(
+                    &A::aggregate_type(),
+                    events,
+                    aggregate_id,
+                    aggregate,
+                    current_snapshot,
+                )
+                .await?;
             }
         };
         Ok(())
@@ -69,151 +90,544 @@ impl PersistedEventRepository for IndexDbEventRepository {
         &self,
         aggregate_id: &str,
     ) -> Result<ReplayStream, PersistenceError> {
-        todo!()
+        let events = self
+            .select_events(&A::aggregate_type(), aggregate_id, 0)
+            .await?;
+        replay_stream(events).await
     }
 
-    // TODO: aggregate id is unused here, `stream_events` function needs to be broken up
     async fn stream_all_events<A: Aggregate>(&self) -> Result<ReplayStream, PersistenceError> {
-        todo!()
+        let events = self.select_all_events(&A::aggregate_type()).await?;
+        replay_stream(events).await
+    }
+}
+
+/// A stream replaying `events`, buffered at once as the stores are read within transactions
+/// that cannot wait for the stream to be consumed. Events stored without metadata are replayed
+/// with empty metadata, as with the views.
+pub(crate) async fn replay_stream(
+    events: Vec<SerializedEvent>,
+) -> Result<ReplayStream, PersistenceError> {
+    let (mut feed, stream) = ReplayStream::new(events.len().max(1));
+    for mut event in events {
+        if event.metadata.is_null() {
+            event.metadata = Value::Object(Default::default());
+        }
+        feed.push(Ok(event)).await?;
     }
+    Ok(stream)
 }
 
 impl IndexDbEventRepository {
-    async fn select_events<A: Aggregate>(
+    /// Reads the events of an aggregate instance, starting at `from_sequence`.
+    pub(crate) async fn select_events(
         &self,
+        aggregate_type: &str,
         aggregate_id: &str,
-    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
-        let (sender, receiver) = channel::<Vec<SerializedEvent>>();
-
-        let db_name = self.db_name.clone();
+        from_sequence: usize,
+    ) -> Result<Vec<SerializedEvent>, IndexDbAggregateError> {
         let store_name = self.store_name.clone();
-        let aggregate_id = aggregate_id.to_string();
+        let aggregate = (aggregate_type.to_string(), aggregate_id.to_string());
+        let range = stream_range(&aggregate.0, &aggregate.1, from_sequence);
+
+        self.run(move |db| async move {
+            let transaction =
+                db.transaction(&[&store_name, TOMBSTONE_STORE], TransactionMode::ReadOnly)?;
+            check_tombstones(&transaction, &[aggregate]).await?;
+            let store = transaction.object_store(&store_name)?;
+            read_events(&store, &range).await
+        })
+        .await
+    }
+
+    /// Reads the events of every instance of `aggregate_type` in the order they were written,
+    /// leaving out the soft deleted instances. The events are read a page at a time along the
+    /// position index.
+    pub(crate) async fn select_all_events(
+        &self,
+        aggregate_type: &str,
+    ) -> Result<Vec<SerializedEvent>, IndexDbAggregateError> {
+        let mut events = Vec::new();
+        let mut after = 0;
+        loop {
+            let store_name = self.store_name.clone();
+            let aggregate_type = aggregate_type.to_string();
+            let (page, last, read) = self
+                .run(move |db| async move {
+                    let transaction =
+                        db.transaction(&[&store_name, TOMBSTONE_STORE], TransactionMode::ReadOnly)?;
+                    let range = KeyRange::lower_bound((after as f64).into(), true);
+                    let values = transaction
+                        .object_store(&store_name)?
+                        .index(POSITION_INDEX)?
+                        .get_all(&range, Some(STREAM_PAGE_SIZE))
+                        .await?;
+                    let read = values.len();
+                    let tombstones = transaction.object_store(TOMBSTONE_STORE)?;
+                    let mut deleted: HashMap<String, bool> = HashMap::new();
+                    let mut page = Vec::new();
+                    let mut last = after;
+                    for value in values {
+                        let event = serde_json::from_value::<JsEvent>(value)?;
+                        last = event.position.unwrap_or(last);
+                        if event.aggregate_type != aggregate_type {
+                            continue;
+                        }
+                        let is_deleted = match deleted.get(&event.aggregate_id) {
+                            Some(is_deleted) => *is_deleted,
+                            None => {
+                                let key = Key::aggregate(&aggregate_type, &event.aggregate_id);
+                                let is_deleted = tombstones.get(&key).await?.is_some();
+                                deleted.insert(event.aggregate_id.clone(), is_deleted);
+                                is_deleted
+                            }
+                        };
+                        if !is_deleted {
+                            page.push(SerializedEvent::from(event));
+                        }
+                    }
+                    Ok((page, last, read))
+                })
+                .await?;
+            events.extend(page);
+            after = last;
+            if read < STREAM_PAGE_SIZE as usize {
+                return Ok(events);
+            }
+        }
+    }
+
+    /// Runs `f` against an open connection to the database and hands its result back. See
+    /// [`run_in`].
+    pub(crate) fn run<T, F, Fut>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+    where
+        T: 'static,
+        F: FnOnce(Db) -> Fut + 'static,
+        Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+    {
+        run_in(self.db_name.clone(), self.schema(), f)
+    }
+
+    /// The stores and indexes this repository needs on top of the fixed schema.
+    pub(crate) fn schema(&self) -> Vec<StoreSchema> {
+        let metadata_indexes = self.metadata_indexes.iter().map(|key| {
+            let index_name = metadata_index_name(key);
+            let key_path = KeyPath::array(&[index_name.as_str(), "position"]);
+            (index_name, key_path)
+        });
+        let mut events = event_store_schema(&self.store_name);
+        events.indexes.extend(metadata_indexes);
+
+        let views = self
+            .projections
+            .iter()
+            .flat_map(|projection| projection_schema(projection.as_ref()));
+        std::iter::once(events).chain(views).collect()
+    }
 
-        spawn_local(async move {
-            let db = connect(&db_name).await;
+    /// Catches the [projected](IndexDbEventRepository::with_projection) views up with the
+    /// events appended without them, e.g. by another repository, rebuilding those whose schema
+    /// version changed. Returns the number of events read.
+    ///
+    /// The repository runs it on its own before its first write, so calling it is only needed
+    /// to serve up to date views before anything is written, typically once on startup.
+    pub async fn catch_up_projections(&self) -> Result<usize, IndexDbAggregateError> {
+        let mut caught_up = self.projections_caught_up.lock().await;
+        let mut read = 0;
+        for projection in &self.projections {
+            read += Replayer::new(self, projection.clone()).catch_up().await?;
+        }
+        *caught_up = true;
+        Ok(read)
+    }
 
-            // Create a transaction in readwrite mode
-            let transaction = db
-                .transaction(&[&store_name], TransactionMode::ReadWrite)
-                .unwrap();
+    /// Catches the projections up, unless they already were since the repository was created.
+    pub(crate) async fn ensure_projections_caught_up(&self) -> Result<(), IndexDbAggregateError> {
+        if self.projections.is_empty() || *self.projections_caught_up.lock().await {
+            return Ok(());
+        }
+        self.catch_up_projections().await.map(|_| ())
+    }
+}
 
-            // Get the object store
-            let store = transaction.object_store(&store_name).unwrap();
+/// Fails with `AggregateDeleted` if any of the `(aggregate_type, aggregate_id)` pairs has been
+/// soft deleted.
+pub(crate) async fn check_tombstones(
+    transaction: &impl Transaction,
+    aggregates: &[(String, String)],
+) -> Result<(), IndexDbAggregateError> {
+    let tombstones = transaction.object_store(TOMBSTONE_STORE)?;
+    for (aggregate_type, aggregate_id) in aggregates {
+        let key = Key::aggregate(aggregate_type, aggregate_id);
+        if tombstones.get(&key).await?.is_some() {
+            return Err(IndexDbAggregateError::AggregateDeleted(
+                aggregate_id.clone(),
+            ));
+        }
+    }
+    Ok(())
+}
 
-            let index = store.index("aggregate_id").unwrap();
+/// Selects the events of a single aggregate instance, starting at `from_sequence`.
+pub(crate) fn stream_range(
+    aggregate_type: &str,
+    aggregate_id: &str,
+    from_sequence: usize,
+) -> KeyRange {
+    KeyRange::bound(
+        Key::event(aggregate_type, aggregate_id, from_sequence as f64),
+        Key::event(aggregate_type, aggregate_id, f64::INFINITY),
+        false,
+        false,
+    )
+}
 
-            let values = index
-                .get_all(Some(Query::Key(aggregate_id.into())), None)
-                .await
-                .unwrap();
+/// Selects the events of a single aggregate instance that precede `before_sequence`.
+pub(crate) fn stream_range_before(
+    aggregate_type: &str,
+    aggregate_id: &str,
+    before_sequence: usize,
+) -> KeyRange {
+    KeyRange::bound(
+        Key::event(aggregate_type, aggregate_id, 0.0),
+        Key::event(aggregate_type, aggregate_id, before_sequence as f64),
+        false,
+        true,
+    )
+}
 
-            let events: Vec<SerializedEvent> = values
-                .into_iter()
-                .map(|val| serde_wasm_bindgen::from_value::<JsEvent>(val).unwrap())
-                .map(|js_event| js_event.into())
-                .collect();
+/// Returns the highest position handed out by the events store `store_name` so far, or 0 for
+/// an empty store. Positions are never handed out twice, even once the events holding them are
+/// deleted, so that the consumers checkpointing a position do not miss the events added next.
+pub(crate) async fn last_position(
+    transaction: &impl Transaction,
+    store_name: &str,
+) -> Result<u64, IndexDbAggregateError> {
+    let counter = transaction
+        .object_store(POSITION_STORE)?
+        .get(&Key::from(store_name))
+        .await?
+        .and_then(|counter| counter.get("position").and_then(Value::as_u64));
+    // Stores written before the counter was kept only have the positions of their events
+    let last = transaction
+        .object_store(store_name)?
+        .index(POSITION_INDEX)?
+        .last()
+        .await?
+        .and_then(|event| event.get("position").and_then(Value::as_u64));
+    Ok(counter.max(last).unwrap_or_default())
+}
 
-            sender.send(events).unwrap();
-        });
+/// Records `position` as the highest position handed out by the events store `store_name`.
+pub(crate) async fn set_last_position(
+    transaction: &impl Transaction,
+    store_name: &str,
+    position: u64,
+) -> Result<(), IndexDbAggregateError> {
+    let counter = serde_json::json!({ "store": store_name, "position": position });
+    transaction
+        .object_store(POSITION_STORE)?
+        .put(&counter)
+        .await
+}
+
+/// Adds `events` recorded at `recorded_at` to the events store `store_name` at the next global
+/// positions, failing with `OptimisticLock` if any of them already exists. The events are added
+/// in a single batch, and the transaction is rolled back on failure. Returns the position of
+/// the first event.
+pub(crate) async fn add_events(
+    transaction: &impl Transaction,
+    store_name: &str,
+    events: &[SerializedEvent],
+    recorded_at: f64,
+) -> Result<u64, IndexDbAggregateError> {
+    let first = last_position(transaction, store_name).await? + 1;
+    let records = events
+        .iter()
+        .zip(first..)
+        .map(|(event, position)| {
+            serde_json::to_value(JsEvent {
+                position: Some(position),
+                recorded_at: Some(recorded_at),
+                ..JsEvent::from(event.clone())
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    transaction
+        .object_store(store_name)?
+        .add_all(&records)
+        .await?;
+    if !events.is_empty() {
+        set_last_position(transaction, store_name, first + events.len() as u64 - 1).await?;
+    }
+    Ok(first)
+}
+
+/// Gives a position to the events of the store `store_name` written before the store tracked
+/// global positions (schema version 5), after the positions handed out so far and in primary
+/// key order. Backends run it over the events stores when they upgrade a database, so that the
+/// queries and consumers paging through the position index see every event.
+pub(crate) async fn backfill_positions(
+    transaction: &impl Transaction,
+    store_name: &str,
+) -> Result<(), IndexDbAggregateError> {
+    let store = transaction.object_store(store_name)?;
+    let total = store.count(&KeyRange::all()).await?;
+    if store.index(POSITION_INDEX)?.count(&KeyRange::all()).await? == total {
+        return Ok(());
+    }
 
-        match receiver.await {
-            Ok(result) => Ok(result),
-            Err(_) => Err(PersistenceError::OptimisticLockError),
+    let key_path = store.key_path()?.ok_or_else(|| {
+        IndexDbAggregateError::UnknownError(format!("no key path in store {store_name}"))
+    })?;
+    let mut next = last_position(transaction, store_name).await? + 1;
+    let mut range = KeyRange::all();
+    loop {
+        let page = store.get_all(&range, Some(STREAM_PAGE_SIZE)).await?;
+        let Some(last_key) = page.last().and_then(|record| key_path.extract(record)) else {
+            break;
+        };
+        let mut records = Vec::new();
+        for mut record in page {
+            if record.get("position").is_none_or(Value::is_null) {
+                record["position"] = next.into();
+                next += 1;
+                records.push(record);
+            }
         }
+        store.put_all(&records).await?;
+        range = KeyRange::lower_bound(last_key, true);
     }
+    set_last_position(transaction, store_name, next - 1).await
+}
+
+/// Whether `store` is an events store, whose events are given global positions.
+pub(crate) fn is_event_store(store: &StoreSchema) -> bool {
+    store.indexes.iter().any(|(name, _)| name == POSITION_INDEX)
+}
+
+/// Reads the events within `range`, in primary key order.
+pub(crate) async fn read_events(
+    store: &impl ObjectStore,
+    range: &KeyRange,
+) -> Result<Vec<SerializedEvent>, IndexDbAggregateError> {
+    let values = store.get_all(range, None).await?;
+    values
+        .into_iter()
+        .map(|value| {
+            let js_event = serde_json::from_value::<JsEvent>(value)?;
+            Ok(js_event.into())
+        })
+        .collect()
 }
 
 impl IndexDbEventRepository {
+    /// Creates a repository storing its events in the store `store_name`, `"events"` by
+    /// default, of the database `db_name`, `"cqrs"` by default. Outside the browser the
+    /// database is the file `{db_name}.redb`.
     pub fn new(db_name: Option<String>, store_name: Option<String>) -> Self {
         Self {
             db_name: db_name.unwrap_or("cqrs".to_string()),
             store_name: store_name.unwrap_or("events".to_string()),
+            snapshot_truncation: None,
+            snapshot_size: None,
+            clock: Arc::new(SystemClock),
+            metadata_indexes: Vec::new(),
+            projections: Vec::new(),
+            projections_caught_up: Mutex::new(false),
         }
     }
 
+    /// Creates a repository storing its events in the store `"events"` of the in-memory
+    /// database `db_name`, shared with the other repositories created in memory under the same
+    /// name. Nothing is written to disk and the content is lost when the process exits.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn in_memory(db_name: &str) -> Self {
+        Self::new(Some(memory_db_name(db_name)), None)
+    }
+
+    /// Creates a repository storing its events in the store `"events"` of the append-only log
+    /// `db_name`, instead of an IndexedDB database.
+    ///
+    /// Every transaction is appended to a single segment file along with its checksum, and
+    /// records are found through an offset index kept in memory and checkpointed to a second
+    /// file. In the browser both files live in the origin private file system and are written
+    /// through synchronous access handles, which are only available in a dedicated worker:
+    /// anywhere else the repository fails to connect. Outside the browser they are the files
+    /// `{db_name}.log` and `{db_name}.idx`.
+    pub fn opfs(db_name: &str) -> Self {
+        Self::new(Some(log_db_name(db_name)), None)
+    }
+
+    /// Declares an index on the metadata field at `key`, a dot separated path such as
+    /// `"user_id"`, which can then be searched with
+    /// [`IndexDbEventRepository::events_by_metadata`].
+    ///
+    /// Missing indexes are created, over the existing events too, the next time the database is
+    /// opened.
+    pub fn with_metadata_index(mut self, key: impl Into<String>) -> Self {
+        self.metadata_indexes.push(key.into());
+        self
+    }
+
+    /// Replaces the clock used to timestamp events, e.g. with a deterministic one in tests.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Deletes the events already covered by a snapshot each time one is persisted, keeping
+    /// the last `keep_last` of them. See [`IndexDbEventRepository::truncate_before`].
+    pub fn with_snapshot_truncation(mut self, keep_last: usize) -> Self {
+        self.snapshot_truncation = Some(keep_last);
+        self
+    }
+
+    /// Declares the snapshot size the `PersistedEventStore` was created with, as in
+    /// `PersistedEventStore::new_snapshot_store(repo, snapshot_size)`.
+    ///
+    /// cqrs-es takes snapshots at multiples of this size, so a snapshot persisted along with
+    /// several events may only cover the first of them. Knowing the size, the snapshot is
+    /// recorded at the sequence it actually covers and the events after it are replayed on
+    /// load. Without it, snapshots are assumed to cover all the events persisted with them, as
+    /// with `PersistedEventStore::new_aggregate_store`.
+    pub fn with_snapshot_size(mut self, snapshot_size: usize) -> Self {
+        self.snapshot_size = Some(snapshot_size.max(1));
+        self
+    }
+
     pub async fn insert_events<A: Aggregate>(
         &self,
         events: &[SerializedEvent],
     ) -> Result<(), IndexDbAggregateError> {
-        let (sender, receiver) = channel::<Result<(), IndexDbAggregateError>>();
+        self.write_events(events).await
+    }
 
-        let db_name = self.db_name.clone();
+    /// Appends events of any aggregate types, see [`IndexDbEventRepository::insert_events`].
+    pub(crate) async fn write_events(
+        &self,
+        events: &[SerializedEvent],
+    ) -> Result<(), IndexDbAggregateError> {
         let store_name = self.store_name.clone();
         let events = events.to_vec();
-
-        spawn_local(async move {
-            let db = connect(&db_name).await;
-            let events: Vec<JsValue> = events
-                .into_iter()
-                .map(|e| JsEvent::from(e.clone()))
-                .map(|e| JsValue::from_serde(&e).unwrap())
+        let recorded_at = self.clock.now();
+        let projections = self.projections.clone();
+        let mut store_names = vec![
+            store_name.clone(),
+            POSITION_STORE.to_string(),
+            CATALOG_STORE.to_string(),
+            TOMBSTONE_STORE.to_string(),
+        ];
+        store_names.extend(self.projection_stores());
+        self.ensure_projections_caught_up().await?;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&store_names, TransactionMode::ReadWrite)?;
+
+            // Deleted aggregate instances accept no new events
+            let aggregates: Vec<(String, String)> = events
+                .iter()
+                .map(|e| (e.aggregate_type.clone(), e.aggregate_id.clone()))
                 .collect();
+            check_tombstones(&transaction, &aggregates).await?;
+
+            // Add the events in order after the ones already stored, record them in the
+            // aggregate catalog and update the transactional views along with them
+            let first = add_events(&transaction, &store_name, &events, recorded_at).await?;
+            let catalog = transaction.object_store(CATALOG_STORE)?;
+            update_catalog(&catalog, &events, 0, recorded_at).await?;
+            project(&transaction, &projections, &events, first).await?;
+
+            transaction.commit().await
+        })
+        .await
+    }
+}
 
-            // Create a transaction in readwrite mode
-            let transaction = db
-                .transaction(&[&store_name], TransactionMode::ReadWrite)
-                .unwrap();
-
-            // Get the object store
-            let store = transaction.object_store(&store_name).unwrap();
-
-            let mut res: Result<(), IndexDbAggregateError> = Ok(());
-            // Add the values to the store
-            for event in events {
-                web_sys::console::log_1(&event);
-                if store.add(&event, None).await.is_err() {
-                    res = Err(IndexDbAggregateError::OptimisticLock);
-                }
-            }
+/// Version of the IndexedDB schema created when the database is first opened in the browser.
+pub const DB_VERSION: u32 = 7;
 
-            // Commit the transaction
-            if res.is_ok() {
-                transaction.commit().await.unwrap();
-            }
+/// Number of events read per transaction by the event streams.
+const STREAM_PAGE_SIZE: u32 = 500;
 
-            sender.send(res).unwrap();
-        });
+/// Index of the events store on their global position.
+pub(crate) const POSITION_INDEX: &str = "position";
 
-        match receiver.await {
-            Ok(result) => result,
-            Err(_) => Err(IndexDbAggregateError::OptimisticLock),
-        }
-    }
-}
+/// Index of the events store on `[event_type, position]`.
+pub(crate) const EVENT_TYPE_INDEX: &str = "event_type";
 
-async fn connect(name: &str) -> Database {
-    // Better error messages in debug mode
-    console_error_panic_hook::set_once();
+/// Index of the events store on the time they were recorded.
+pub(crate) const RECORDED_AT_INDEX: &str = "recorded_at";
 
-    // Get a factory instance from global scope
-    let factory = Factory::new().unwrap();
+/// Index of the events store on `[aggregate_type, recorded_at]`.
+pub(crate) const AGGREGATE_RECORDED_AT_INDEX: &str = "aggregate_type_recorded_at";
 
-    // Create an open request for the database
-    let mut open_request = factory.open(name, Some(1)).unwrap();
+/// Object store holding the highest global position handed out by each events store, keyed by
+/// the name of the events store.
+pub(crate) const POSITION_STORE: &str = "positions";
 
-    // Add an upgrade handler for database
-    open_request.on_upgrade_needed(|event| {
-        // Get database instance from event
-        let database = event.database().unwrap();
+/// Object store holding one [`AggregateSummary`](crate::AggregateSummary) per aggregate instance.
+pub(crate) const CATALOG_STORE: &str = "catalog";
 
-        // Prepare object store params
-        let mut store_params = ObjectStoreParams::new();
-        store_params.key_path(Some(KeyPath::new_array(vec![
-            "aggregate_type",
-            "aggregate_id",
-            "sequence",
-        ])));
+/// Object store holding the latest snapshot of each aggregate instance.
+pub(crate) const SNAPSHOT_STORE: &str = "snapshots";
 
-        // Create object store
-        let store = database
-            .create_object_store("events", store_params)
-            .unwrap();
+/// Object store holding the tombstones of soft deleted aggregate instances.
+pub(crate) const TOMBSTONE_STORE: &str = "tombstones";
 
-        // Create index on object store
-        store
-            .create_index("aggregate_id", KeyPath::new_single("aggregate_id"), None)
-            .unwrap();
-    });
+/// Object store holding the checkpoint of each [`ProjectionRunner`](crate::ProjectionRunner)
+/// and transactional projection, created along with the first of them.
+pub(crate) const CHECKPOINT_STORE: &str = "checkpoints";
+
+/// Name of the index on the metadata field at `key`, which is also the key path of the field.
+pub(crate) fn metadata_index_name(key: &str) -> String {
+    format!("metadata.{key}")
+}
 
-    open_request.await.unwrap()
+/// The stores every database has: the default `"events"` store, the position counters, the
+/// snapshots, the catalog and the tombstones.
+pub(crate) fn base_schema() -> Vec<StoreSchema> {
+    let aggregate_stores =
+        [SNAPSHOT_STORE, CATALOG_STORE, TOMBSTONE_STORE].map(|name| StoreSchema {
+            name: name.to_string(),
+            key_path: KeyPath::array(&["aggregate_type", "aggregate_id"]),
+            indexes: Vec::new(),
+        });
+    let positions = StoreSchema {
+        name: POSITION_STORE.to_string(),
+        key_path: KeyPath::single("store"),
+        indexes: Vec::new(),
+    };
+    [event_store_schema("events"), positions]
+        .into_iter()
+        .chain(aggregate_stores)
+        .collect()
+}
+
+/// An events store keyed by `[aggregate_type, aggregate_id, sequence]`, with the indexes the
+/// queries of the repository rely on.
+fn event_store_schema(name: &str) -> StoreSchema {
+    StoreSchema {
+        name: name.to_string(),
+        key_path: KeyPath::array(&["aggregate_type", "aggregate_id", "sequence"]),
+        indexes: vec![
+            ("aggregate_id".to_string(), KeyPath::single("aggregate_id")),
+            (POSITION_INDEX.to_string(), KeyPath::single("position")),
+            (
+                EVENT_TYPE_INDEX.to_string(),
+                KeyPath::array(&["event_type", "position"]),
+            ),
+            (
+                RECORDED_AT_INDEX.to_string(),
+                KeyPath::single("recorded_at"),
+            ),
+            (
+                AGGREGATE_RECORDED_AT_INDEX.to_string(),
+                KeyPath::array(&["aggregate_type", "recorded_at"]),
+            ),
+        ],
+    }
 }
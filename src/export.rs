@@ -0,0 +1,189 @@
+use crate::event_repository::DB_VERSION;
+use crate::storage::{Database, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{js_event::JsEvent, IndexDbAggregateError, IndexDbEventRepository};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+#[cfg(target_arch = "wasm32")]
+use {
+    js_sys::{Array, Uint8Array},
+    web_sys::{Blob, BlobPropertyBag},
+};
+
+/// The MIME type of an NDJSON dump.
+pub const NDJSON_MIME_TYPE: &str = "application/x-ndjson";
+
+/// Number of records read from a store at a time by [`IndexDbEventRepository::export`].
+const EXPORT_PAGE_SIZE: u32 = 500;
+
+/// Number of bytes of a dump buffered before they are handed to the browser as a blob part.
+#[cfg(target_arch = "wasm32")]
+const BLOB_CHUNK_SIZE: usize = 1 << 20;
+
+/// Selects the object stores written by [`IndexDbEventRepository::export`].
+///
+/// The event store is always exported; snapshot and view stores can be added by name.
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    stores: Vec<String>,
+}
+
+impl ExportOptions {
+    /// Also exports every record of the named object store.
+    pub fn with_store(mut self, store_name: impl Into<String>) -> Self {
+        self.stores.push(store_name.into());
+        self
+    }
+}
+
+/// The first line of an NDJSON dump.
+///
+/// Records follow the header section by section, in the order listed here. The first section
+/// always holds the events, one `JsEvent` per line; other sections hold the raw records of
+/// their object store.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportHeader {
+    pub schema_version: u32,
+    pub sections: Vec<ExportSection>,
+}
+
+/// The number of records exported from a single object store.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportSection {
+    pub store: String,
+    pub count: u32,
+}
+
+impl IndexDbEventRepository {
+    /// Writes the event store, and any store selected in `options`, to `writer` as
+    /// newline-delimited JSON preceded by an [`ExportHeader`].
+    ///
+    /// All stores are read within a single transaction, so the dump is a consistent view of
+    /// the database. Each store is read a page at a time along its keys, and every page is
+    /// written out before the next one is read. The writer is handed back once the export
+    /// completes.
+    pub async fn export<W>(
+        &self,
+        options: ExportOptions,
+        writer: W,
+    ) -> Result<(W, ExportHeader), IndexDbAggregateError>
+    where
+        W: Write + 'static,
+    {
+        let mut store_names = vec![self.store_name.clone()];
+        store_names.extend(options.stores);
+
+        self.run(move |db| async move {
+            let mut writer = writer;
+            let transaction = db.transaction(&store_names, TransactionMode::ReadOnly)?;
+
+            let mut sections = Vec::new();
+            for store_name in &store_names {
+                let count = transaction
+                    .object_store(store_name)?
+                    .count(&KeyRange::all())
+                    .await?;
+                sections.push(ExportSection {
+                    store: store_name.clone(),
+                    count,
+                });
+            }
+            let header = ExportHeader {
+                schema_version: DB_VERSION,
+                sections,
+            };
+            write_line(&mut writer, &header)?;
+
+            for (i, store_name) in store_names.iter().enumerate() {
+                let store = transaction.object_store(store_name)?;
+                let key_path = store.key_path()?.ok_or_else(|| {
+                    IndexDbAggregateError::UnknownError(format!(
+                        "no key path in store {store_name}"
+                    ))
+                })?;
+                let mut range = KeyRange::all();
+                loop {
+                    let page = store.get_all(&range, Some(EXPORT_PAGE_SIZE)).await?;
+                    let last_key = page.last().and_then(|record| key_path.extract(record));
+                    for record in page {
+                        if i == 0 {
+                            let event = serde_json::from_value::<JsEvent>(record)?;
+                            write_line(&mut writer, &event)?;
+                        } else {
+                            write_line(&mut writer, &record)?;
+                        }
+                    }
+                    match last_key {
+                        Some(last_key) => range = KeyRange::lower_bound(last_key, true),
+                        None => break,
+                    }
+                }
+            }
+
+            writer.flush()?;
+            Ok((writer, header))
+        })
+        .await
+    }
+
+    /// Exports the database as an NDJSON [`Blob`], e.g. to be downloaded or attached to a bug
+    /// report. See [`IndexDbEventRepository::export`].
+    ///
+    /// The dump is handed to the browser in chunks as it is written, each chunk as a blob of
+    /// its own, so that at most one chunk is buffered in memory at a time.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn export_blob(&self, options: ExportOptions) -> Result<Blob, IndexDbAggregateError> {
+        let writer = BlobWriter {
+            parts: Array::new(),
+            chunk: Vec::new(),
+        };
+        let (writer, _) = self.export(options, writer).await?;
+
+        let properties = BlobPropertyBag::new();
+        properties.set_type(NDJSON_MIME_TYPE);
+        Blob::new_with_blob_sequence_and_options(&writer.parts, &properties).map_err(blob_error)
+    }
+}
+
+/// Writes a dump as blob parts, one every [`BLOB_CHUNK_SIZE`] bytes.
+#[cfg(target_arch = "wasm32")]
+struct BlobWriter {
+    parts: Array,
+    chunk: Vec<u8>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Write for BlobWriter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.chunk.extend_from_slice(bytes);
+        if self.chunk.len() >= BLOB_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let bytes = Array::of1(&Uint8Array::from(self.chunk.as_slice()));
+        let part = Blob::new_with_u8_array_sequence(&bytes)
+            .map_err(|err| std::io::Error::other(blob_error(err).to_string()))?;
+        self.parts.push(&part);
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn blob_error(err: wasm_bindgen::JsValue) -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError(format!("unable to create blob: {:?}", err))
+}
+
+fn write_line<W: Write, T: Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> Result<(), IndexDbAggregateError> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
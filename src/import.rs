@@ -0,0 +1,346 @@
+use crate::catalog::update_catalog;
+use crate::event_repository::{
+    check_tombstones, last_position, set_last_position, CATALOG_STORE, DB_VERSION, POSITION_STORE,
+    TOMBSTONE_STORE,
+};
+use crate::projection::project;
+use crate::storage::{encode, Database, ObjectStore, Transaction, TransactionMode};
+use crate::{
+    js_event::JsEvent, ExportHeader, IndexDbAggregateError, IndexDbEventRepository,
+    ReplicationFilter,
+};
+use cqrs_es::persist::SerializedEvent;
+use futures::future::join_all;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// How [`IndexDbEventRepository::import`] handles records whose key already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictMode {
+    /// Aborts the import with an `OptimisticLock` error.
+    #[default]
+    Fail,
+    /// Keeps the existing record and skips the imported one.
+    Skip,
+    /// Accepts the imported record only if it is identical to the existing one, in which case
+    /// it is counted as skipped; differing records are rejected. Events are compared on their
+    /// aggregate, sequence, type, version, payload and metadata, whenever they were recorded.
+    OverwriteIdentical,
+}
+
+/// Configures [`IndexDbEventRepository::import`].
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    pub(crate) conflict_mode: ConflictMode,
+    pub(crate) batch_size: usize,
+    pub(crate) filter: ReplicationFilter,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            conflict_mode: ConflictMode::default(),
+            batch_size: 500,
+            filter: ReplicationFilter::default(),
+        }
+    }
+}
+
+impl ImportOptions {
+    /// Sets how records whose key already exists are handled.
+    pub fn with_conflict_mode(mut self, conflict_mode: ConflictMode) -> Self {
+        self.conflict_mode = conflict_mode;
+        self
+    }
+
+    /// Sets the number of records written per transaction.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Only imports the events passing `filter`, counting the others as skipped. The records
+    /// of the other stores are imported as they are.
+    pub fn with_filter(mut self, filter: ReplicationFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Reported after each batch written by [`IndexDbEventRepository::import`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImportProgress {
+    pub processed: usize,
+    pub total: usize,
+}
+
+/// The outcome of [`IndexDbEventRepository::import`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportResult {
+    pub inserted: usize,
+    pub skipped: usize,
+    pub rejected: usize,
+}
+
+impl ImportResult {
+    pub(crate) fn add(&mut self, other: ImportResult) {
+        self.inserted += other.inserted;
+        self.skipped += other.skipped;
+        self.rejected += other.rejected;
+    }
+}
+
+impl IndexDbEventRepository {
+    /// Restores an NDJSON dump written by [`IndexDbEventRepository::export`].
+    ///
+    /// Records that cannot be parsed, or events missing their aggregate type, id or sequence,
+    /// are rejected and the import carries on. Valid records are written in batches, one
+    /// transaction per batch, and `progress` is called after each of them. Events left out by
+    /// the [replication filter](ImportOptions::with_filter) are skipped. Conflicts on
+    /// existing keys are handled according to the configured [`ConflictMode`]; note that with
+    /// `ConflictMode::Fail` the batches written before the conflict are kept. New events of a
+    /// soft deleted aggregate instance fail their batch with an `AggregateDeleted` error.
+    pub async fn import<R, P>(
+        &self,
+        reader: R,
+        options: ImportOptions,
+        mut progress: P,
+    ) -> Result<ImportResult, IndexDbAggregateError>
+    where
+        R: BufRead,
+        P: FnMut(ImportProgress),
+    {
+        let mut lines = reader.lines();
+        let header: ExportHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => {
+                return Err(IndexDbAggregateError::DeserializationError(
+                    "missing NDJSON header".to_string(),
+                ))
+            }
+        };
+        if header.schema_version > DB_VERSION {
+            return Err(IndexDbAggregateError::UnknownError(format!(
+                "unsupported schema version {}",
+                header.schema_version
+            )));
+        }
+
+        let total = header.sections.iter().map(|s| s.count as usize).sum();
+        let mut result = ImportResult::default();
+        let mut processed = 0;
+        let mut batch = Vec::new();
+
+        for (i, section) in header.sections.iter().enumerate() {
+            // The first section always holds the events
+            let store_name = match i {
+                0 => self.store_name.clone(),
+                _ => section.store.clone(),
+            };
+            for _ in 0..section.count {
+                let line = lines.next().ok_or_else(|| {
+                    IndexDbAggregateError::DeserializationError(format!(
+                        "NDJSON dump ends before the {} records of `{}`",
+                        section.count, section.store
+                    ))
+                })??;
+                processed += 1;
+                match parse_record(&line, i == 0) {
+                    Some(record) => batch.push((store_name.clone(), record)),
+                    None => result.rejected += 1,
+                }
+
+                if batch.len() >= options.batch_size {
+                    let written = std::mem::take(&mut batch);
+                    result.add(self.import_batch(written, &options).await?);
+                    progress(ImportProgress { processed, total });
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            result.add(self.import_batch(batch, &options).await?);
+        }
+        progress(ImportProgress { processed, total });
+        Ok(result)
+    }
+
+    pub(crate) async fn import_batch(
+        &self,
+        batch: Vec<(String, Value)>,
+        options: &ImportOptions,
+    ) -> Result<ImportResult, IndexDbAggregateError> {
+        let conflict_mode = options.conflict_mode;
+        let filter = options.filter.clone();
+        let event_store = self.store_name.clone();
+        let now = self.clock.now();
+        let projections = self.projections.clone();
+        let mut store_names: Vec<String> = batch.iter().map(|(store, _)| store.clone()).collect();
+        if store_names.contains(&event_store) {
+            store_names.push(POSITION_STORE.to_string());
+            store_names.push(CATALOG_STORE.to_string());
+            store_names.push(TOMBSTONE_STORE.to_string());
+            store_names.extend(self.projection_stores());
+        }
+        store_names.sort();
+        store_names.dedup();
+        self.ensure_projections_caught_up().await?;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&store_names, TransactionMode::ReadWrite)?;
+            let mut result = ImportResult::default();
+            let mut inserted_events = Vec::new();
+            let mut first = None;
+            let mut position = None;
+            // The records to add, by store, and those among them with a key, by store and
+            // encoded key, as they are only added once the whole batch is checked
+            let mut added: Vec<(String, Vec<Value>)> = Vec::new();
+            let mut pending: HashMap<(String, Vec<u8>), Value> = HashMap::new();
+
+            // Look up the keys of the whole batch at once, every request being issued before
+            // any is waited for
+            let mut checked = Vec::new();
+            for (store_name, record) in batch {
+                let event = match store_name == event_store {
+                    true => Some(SerializedEvent::from(serde_json::from_value::<JsEvent>(
+                        record.clone(),
+                    )?)),
+                    false => None,
+                };
+                if event.as_ref().is_some_and(|event| !filter.matches(event)) {
+                    result.skipped += 1;
+                    continue;
+                }
+                let key = match transaction.object_store(&store_name)?.key_path()? {
+                    None => None,
+                    Some(key_path) => match key_path.extract(&record) {
+                        Some(key) => Some((store_name.clone(), encode(&key), key)),
+                        None => {
+                            result.rejected += 1;
+                            continue;
+                        }
+                    },
+                };
+                checked.push((store_name, record, event, key));
+            }
+            let lookups = checked.iter().map(|(store_name, _, _, key)| {
+                let store = transaction.object_store(store_name);
+                async move {
+                    match key {
+                        Some((_, _, key)) => store?.get(key).await,
+                        None => Ok(None),
+                    }
+                }
+            });
+            let stored = join_all(lookups).await;
+
+            for ((store_name, record, event, key), stored) in checked.into_iter().zip(stored) {
+                // A record added earlier in the batch takes precedence over the stored one
+                let existing = match &key {
+                    Some((store_name, encoded, _)) => {
+                        match pending.get(&(store_name.clone(), encoded.clone())) {
+                            Some(record) => Some(record.clone()),
+                            None => stored?,
+                        }
+                    }
+                    None => None,
+                };
+                match (existing, conflict_mode) {
+                    (None, _) => {
+                        let mut record = record;
+                        if let Some(event) = event {
+                            // Imported events are appended after the events already stored
+                            let next = match position {
+                                Some(position) => position + 1,
+                                None => last_position(&transaction, &store_name).await? + 1,
+                            };
+                            position = Some(next);
+                            first.get_or_insert(next);
+                            record["position"] = next.into();
+                            if record.get("recorded_at").is_none() {
+                                record["recorded_at"] = now.into();
+                            }
+                            inserted_events.push(event);
+                        }
+                        if let Some((store_name, encoded, _)) = key {
+                            pending.insert((store_name, encoded), record.clone());
+                        }
+                        match added.iter_mut().find(|(name, _)| *name == store_name) {
+                            Some((_, records)) => records.push(record),
+                            None => added.push((store_name, vec![record])),
+                        }
+                        result.inserted += 1;
+                    }
+                    (Some(_), ConflictMode::Fail) => {
+                        transaction.abort().await?;
+                        return Err(IndexDbAggregateError::OptimisticLock);
+                    }
+                    (Some(_), ConflictMode::Skip) => result.skipped += 1,
+                    (Some(existing), ConflictMode::OverwriteIdentical) => {
+                        // Events are compared on their identity and payload alone, leaving out
+                        // where and when each store recorded them
+                        let identical = match &event {
+                            Some(event) => {
+                                let existing = serde_json::from_value::<JsEvent>(existing)?;
+                                SerializedEvent::from(existing) == *event
+                            }
+                            None => existing == record,
+                        };
+                        if identical {
+                            result.skipped += 1;
+                        } else {
+                            result.rejected += 1;
+                        }
+                    }
+                }
+            }
+
+            // Deleted aggregate instances accept no new events, imported ones included
+            let aggregates: Vec<(String, String)> = inserted_events
+                .iter()
+                .map(|e| (e.aggregate_type.clone(), e.aggregate_id.clone()))
+                .collect();
+            check_tombstones(&transaction, &aggregates).await?;
+
+            for (store_name, records) in added {
+                transaction
+                    .object_store(&store_name)?
+                    .add_all(&records)
+                    .await?;
+            }
+            if let (Some(first), Some(position)) = (first, position) {
+                set_last_position(&transaction, &event_store, position).await?;
+                let catalog = transaction.object_store(CATALOG_STORE)?;
+                update_catalog(&catalog, &inserted_events, 0, now).await?;
+                project(&transaction, &projections, &inserted_events, first).await?;
+            }
+
+            transaction.commit().await?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+/// Parses and validates a single record, returning `None` if it must be rejected.
+fn parse_record(line: &str, is_event: bool) -> Option<Value> {
+    if !is_event {
+        return serde_json::from_str(line).ok();
+    }
+    let event: JsEvent = serde_json::from_str(line).ok()?;
+    validate_event(event)
+}
+
+/// Checks that an event is addressable, returning `None` if it must be rejected. The position
+/// of the event in its original store is dropped.
+pub(crate) fn validate_event(event: JsEvent) -> Option<Value> {
+    if event.aggregate_type.is_empty() || event.aggregate_id.is_empty() || event.sequence == 0 {
+        return None;
+    }
+    serde_json::to_value(JsEvent {
+        position: None,
+        ..event
+    })
+    .ok()
+}
@@ -0,0 +1,61 @@
+use serde_json::Value;
+
+/// A range of indexed values, as searched by
+/// [`IndexDbEventRepository::events_by_metadata`](crate::IndexDbEventRepository::events_by_metadata)
+/// and [`IndexDbViewRepository::find_views`](crate::IndexDbViewRepository::find_views).
+///
+/// Bounds are inclusive and compared with the IndexedDB key order: numbers sort before strings,
+/// which sort before arrays. Values of an index over several fields are arrays holding one value
+/// per field.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IndexRange {
+    pub(crate) lower: Option<Value>,
+    pub(crate) upper: Option<Value>,
+}
+
+impl IndexRange {
+    /// Every indexed value.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only the given value.
+    pub fn only(value: impl Into<Value>) -> Self {
+        let value = value.into();
+        Self {
+            lower: Some(value.clone()),
+            upper: Some(value),
+        }
+    }
+
+    /// The values from `lower` to `upper`.
+    pub fn between(lower: impl Into<Value>, upper: impl Into<Value>) -> Self {
+        Self {
+            lower: Some(lower.into()),
+            upper: Some(upper.into()),
+        }
+    }
+
+    /// The values from `lower` onwards.
+    pub fn at_least(lower: impl Into<Value>) -> Self {
+        Self {
+            lower: Some(lower.into()),
+            upper: None,
+        }
+    }
+
+    /// The values up to `upper`.
+    pub fn at_most(upper: impl Into<Value>) -> Self {
+        Self {
+            lower: None,
+            upper: Some(upper.into()),
+        }
+    }
+}
+
+/// Reads the value at a dot separated `path` of a record.
+pub(crate) fn lookup(record: &Value, path: &str) -> Option<Value> {
+    path.split('.')
+        .try_fold(record, |value, key| value.get(key))
+        .cloned()
+}
@@ -0,0 +1,120 @@
+use crate::import::validate_event;
+use crate::storage::{Database, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{
+    js_event::JsEvent, ImportOptions, ImportResult, IndexDbAggregateError, IndexDbEventRepository,
+};
+use cqrs_es::persist::SerializedEvent;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The columns of the `events` table created by postgres-es and mysql-es, in table order.
+pub const EVENT_TABLE_COLUMNS: [&str; 7] = [
+    "aggregate_type",
+    "aggregate_id",
+    "sequence",
+    "event_type",
+    "event_version",
+    "payload",
+    "metadata",
+];
+
+/// A row of the `events` table used by postgres-es and mysql-es.
+///
+/// Rows serialize with the same field names as the records of an NDJSON export, so a JSON dump
+/// of the server table can also be restored with [`IndexDbEventRepository::import`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EventRow {
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    pub sequence: i64,
+    pub event_type: String,
+    pub event_version: String,
+    pub payload: Value,
+    pub metadata: Value,
+}
+
+impl From<SerializedEvent> for EventRow {
+    fn from(event: SerializedEvent) -> Self {
+        EventRow {
+            aggregate_type: event.aggregate_type,
+            aggregate_id: event.aggregate_id,
+            sequence: event.sequence as i64,
+            event_type: event.event_type,
+            event_version: event.event_version,
+            payload: event.payload,
+            metadata: event.metadata,
+        }
+    }
+}
+
+impl TryFrom<EventRow> for SerializedEvent {
+    type Error = IndexDbAggregateError;
+
+    fn try_from(row: EventRow) -> Result<Self, Self::Error> {
+        let sequence = usize::try_from(row.sequence).map_err(|_| {
+            IndexDbAggregateError::DeserializationError(format!(
+                "invalid sequence {} for aggregate {}",
+                row.sequence, row.aggregate_id
+            ))
+        })?;
+        Ok(SerializedEvent {
+            aggregate_id: row.aggregate_id,
+            sequence,
+            aggregate_type: row.aggregate_type,
+            event_type: row.event_type,
+            event_version: row.event_version,
+            payload: row.payload,
+            metadata: row.metadata,
+        })
+    }
+}
+
+impl IndexDbEventRepository {
+    /// Reads every event in the store as a row of the server-side `events` table.
+    pub async fn export_rows(&self) -> Result<Vec<EventRow>, IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let store = transaction.object_store(&store_name)?;
+
+            store
+                .get_all(&KeyRange::all(), None)
+                .await?
+                .into_iter()
+                .map(|value| {
+                    let event = serde_json::from_value::<JsEvent>(value)?;
+                    Ok(EventRow::from(SerializedEvent::from(event)))
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Writes rows read from the server-side `events` table into the event store.
+    ///
+    /// Rows are validated, filtered and written in batches like the records of an NDJSON
+    /// import; rows with a negative sequence are rejected.
+    pub async fn import_rows(
+        &self,
+        rows: &[EventRow],
+        options: ImportOptions,
+    ) -> Result<ImportResult, IndexDbAggregateError> {
+        let mut result = ImportResult::default();
+        let mut records = Vec::new();
+        for row in rows {
+            match SerializedEvent::try_from(row.clone())
+                .ok()
+                .and_then(|event| validate_event(event.into()))
+            {
+                Some(record) => records.push((self.store_name.clone(), record)),
+                None => result.rejected += 1,
+            }
+        }
+
+        for batch in records.chunks(options.batch_size) {
+            result.add(self.import_batch(batch.to_vec(), &options).await?);
+        }
+        Ok(result)
+    }
+}
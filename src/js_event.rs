@@ -1,4 +1,4 @@
-use cqrs_es::persist::SerializedEvent;
+use cqrs_es::persist::{SerializedEvent, SerializedSnapshot};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -11,18 +11,24 @@ pub struct JsEvent {
     pub event_version: String,
     pub payload: Value,
     pub metadata: Value,
+    /// Position of the event in the whole store, assigned when it is written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+    /// Time at which the event was written, in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<f64>,
 }
 
-impl Into<SerializedEvent> for JsEvent {
-    fn into(self) -> SerializedEvent {
+impl From<JsEvent> for SerializedEvent {
+    fn from(value: JsEvent) -> SerializedEvent {
         SerializedEvent {
-            aggregate_id: self.aggregate_id,
-            sequence: self.sequence,
-            aggregate_type: self.aggregate_type,
-            event_type: self.event_type,
-            event_version: self.event_version,
-            payload: self.payload,
-            metadata: self.metadata,
+            aggregate_id: value.aggregate_id,
+            sequence: value.sequence,
+            aggregate_type: value.aggregate_type,
+            event_type: value.event_type,
+            event_version: value.event_version,
+            payload: value.payload,
+            metadata: value.metadata,
         }
     }
 }
@@ -37,6 +43,28 @@ impl From<SerializedEvent> for JsEvent {
             event_version: value.event_version,
             payload: value.payload,
             metadata: value.metadata,
+            position: None,
+            recorded_at: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct JsSnapshot {
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    pub last_sequence: usize,
+    pub current_snapshot: usize,
+    pub payload: Value,
+}
+
+impl From<JsSnapshot> for SerializedSnapshot {
+    fn from(value: JsSnapshot) -> SerializedSnapshot {
+        SerializedSnapshot {
+            aggregate_id: value.aggregate_id,
+            aggregate: value.payload,
+            current_sequence: value.last_sequence,
+            current_snapshot: value.current_snapshot,
         }
     }
 }
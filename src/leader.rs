@@ -0,0 +1,472 @@
+//! Electing one tab to run the background jobs, such as projection catch-up, sync or
+//! compaction, among all the tabs of an origin.
+
+use crate::{IndexDbAggregateError, IndexDbEventRepository, Outbox, ProjectionRunner};
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::{Aggregate, View};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::future::{AbortHandle, Abortable, LocalBoxFuture};
+use futures::FutureExt;
+use gloo_utils::format::JsValueSerdeExt;
+use js_sys::{Function, Promise, Reflect};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::rc::{Rc, Weak};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{AbortController, BroadcastChannel, EventTarget, MessageEvent};
+
+/// How often the leader tells the other tabs it is alive, in milliseconds.
+const HEARTBEAT_INTERVAL: i32 = 1000;
+/// How long without a heartbeat before a tab takes the lead by default, in milliseconds. Well
+/// above the minute browsers may throttle the timers of hidden tabs to, so that a hidden leader
+/// keeps the lead.
+pub const HEARTBEAT_TIMEOUT: u32 = 70_000;
+/// How long a new tab waits for the current leader to make itself known, in milliseconds.
+const HEARTBEAT_GRACE: f64 = 3000.0;
+
+/// A job run by the leader, restarted whenever the tab takes the lead again.
+struct Job {
+    start: Box<dyn Fn() -> LocalBoxFuture<'static, ()>>,
+    running: RefCell<Option<AbortHandle>>,
+}
+
+/// What the election shares with its callbacks.
+struct State {
+    leading: Cell<bool>,
+    watchers: RefCell<Vec<UnboundedSender<bool>>>,
+    jobs: RefCell<Vec<Rc<Job>>>,
+}
+
+impl State {
+    fn set_leading(&self, leading: bool) {
+        if self.leading.replace(leading) == leading {
+            return;
+        }
+        self.watchers
+            .borrow_mut()
+            .retain(|watcher| watcher.unbounded_send(leading).is_ok());
+        for job in self.jobs.borrow().iter() {
+            if leading {
+                start(job);
+            } else if let Some(running) = job.running.take() {
+                running.abort();
+            }
+        }
+    }
+}
+
+fn start(job: &Rc<Job>) {
+    let (handle, registration) = AbortHandle::new_pair();
+    if let Some(previous) = job.running.replace(Some(handle)) {
+        previous.abort();
+    }
+    spawn_local(Abortable::new((job.start)(), registration).map(|_| ()));
+}
+
+/// How the tabs agree on a leader.
+enum Backend {
+    /// Holding an exclusive Web Lock, released on drop or when the tab closes. The request of
+    /// a tab still waiting for the lock is aborted on drop.
+    Locks {
+        release: Rc<RefCell<Option<Function>>>,
+        request: AbortController,
+    },
+    /// Sending heartbeats over a `BroadcastChannel`, taken over by another tab when they stop
+    /// or the leader resigns, on drop or when its page is hidden for good.
+    Heartbeat {
+        id: f64,
+        channel: BroadcastChannel,
+        interval: JsValue,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_tick: Closure<dyn FnMut()>,
+        on_page_hide: Closure<dyn FnMut()>,
+    },
+}
+
+/// A heartbeat message.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Heartbeat {
+    Alive { id: f64 },
+    Resign { id: f64 },
+}
+
+/// The election of a leader among the tabs sharing `name`, so that the background jobs run in
+/// one tab only.
+///
+/// The leader holds an exclusive [Web Lock](https://developer.mozilla.org/docs/Web/API/Web_Locks_API),
+/// which the browser hands over to a waiting tab as soon as the leader closes. Where Web Locks
+/// are not supported, the leader sends heartbeats over a `BroadcastChannel` instead, and the
+/// other tabs take over once they stop for [`HEARTBEAT_TIMEOUT`], or as soon as the leader
+/// resigns when it is dropped or its page is hidden; should two tabs lead at once, the one with
+/// the lowest id stays.
+///
+/// The projection runners, outboxes and other periodic jobs such as compaction are run by the
+/// leader only, every given number of milliseconds.
+///
+/// ```no_run
+/// use indexdb_es::{
+///     IndexDbEventRepository, IndexDbViewRepository, LeaderElection, Outbox, ProjectionRunner,
+/// };
+/// # use cqrs_es::{Aggregate, View};
+/// # fn run<V: View<A> + 'static, A: Aggregate + 'static>(views: IndexDbViewRepository<V, A>) {
+/// let event_repo = IndexDbEventRepository::new(None, None);
+/// let election = LeaderElection::new("my-app")
+///     .unwrap()
+///     .with_projection_runner(ProjectionRunner::new(&event_repo, views), 5_000)
+///     .with_outbox(Outbox::new(&event_repo, "server"), 30_000, |events| async move {
+///         // Send `events` to the server
+///         Ok(())
+///     });
+/// # }
+/// ```
+pub struct LeaderElection {
+    state: Rc<State>,
+    backend: Backend,
+}
+
+impl LeaderElection {
+    /// Joins the election of `name`, using Web Locks where supported and heartbeats otherwise.
+    pub fn new(name: &str) -> Result<Self, IndexDbAggregateError> {
+        match lock_manager() {
+            Some(locks) => Self::with_locks(name, &locks),
+            None => Self::heartbeat(name),
+        }
+    }
+
+    /// Joins the election of `name` by heartbeats, whether Web Locks are supported or not,
+    /// taking the lead after [`HEARTBEAT_TIMEOUT`] without a heartbeat.
+    pub fn heartbeat(name: &str) -> Result<Self, IndexDbAggregateError> {
+        Self::heartbeat_with_timeout(name, HEARTBEAT_TIMEOUT)
+    }
+
+    /// Joins the election of `name` by heartbeats, taking the lead after `timeout` milliseconds
+    /// without a heartbeat. Below a minute, a leader in a hidden tab whose timers are throttled
+    /// may lose the lead to another tab, and take it back once it sends a heartbeat again.
+    pub fn heartbeat_with_timeout(name: &str, timeout: u32) -> Result<Self, IndexDbAggregateError> {
+        let state = new_state();
+        let id = js_sys::Math::random();
+        let timeout = f64::from(timeout);
+        let channel = BroadcastChannel::new(&format!("indexdb-es-leader:{name}"))
+            .map_err(connection_error)?;
+        // A new tab lets the current leader make itself known before taking the lead
+        let grace = HEARTBEAT_GRACE.min(timeout);
+        let last_heartbeat = Rc::new(Cell::new(js_sys::Date::now() - timeout + grace));
+
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+            let state = Rc::downgrade(&state);
+            let last_heartbeat = last_heartbeat.clone();
+            move |event: MessageEvent| {
+                let (Some(state), Ok(heartbeat)) =
+                    (state.upgrade(), event.data().into_serde::<Heartbeat>())
+                else {
+                    return;
+                };
+                match heartbeat {
+                    Heartbeat::Alive { id: other } => {
+                        last_heartbeat.set(js_sys::Date::now());
+                        if other < id {
+                            state.set_leading(false);
+                        }
+                    }
+                    Heartbeat::Resign { .. } => last_heartbeat.set(0.0),
+                }
+            }
+        });
+        channel.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let on_tick = Closure::<dyn FnMut()>::new({
+            let state = Rc::downgrade(&state);
+            let channel = channel.clone();
+            move || {
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                if !state.leading.get() && js_sys::Date::now() - last_heartbeat.get() > timeout {
+                    state.set_leading(true);
+                }
+                if state.leading.get() {
+                    post(&channel, &Heartbeat::Alive { id });
+                }
+            }
+        });
+        let interval = call_global(
+            "setInterval",
+            &[on_tick.as_ref().clone(), JsValue::from(HEARTBEAT_INTERVAL)],
+        )?;
+
+        // `Drop` does not run when the tab closes, so the leader resigns as its page goes away
+        // rather than leaving the others to wait for the timeout
+        let on_page_hide = Closure::<dyn FnMut()>::new({
+            let state = Rc::downgrade(&state);
+            let channel = channel.clone();
+            move || {
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                if state.leading.get() {
+                    state.set_leading(false);
+                    post(&channel, &Heartbeat::Resign { id });
+                }
+            }
+        });
+        js_sys::global()
+            .unchecked_into::<EventTarget>()
+            .add_event_listener_with_callback("pagehide", on_page_hide.as_ref().unchecked_ref())
+            .map_err(connection_error)?;
+
+        Ok(Self {
+            state,
+            backend: Backend::Heartbeat {
+                id,
+                channel,
+                interval,
+                _on_message: on_message,
+                _on_tick: on_tick,
+                on_page_hide,
+            },
+        })
+    }
+
+    fn with_locks(name: &str, locks: &JsValue) -> Result<Self, IndexDbAggregateError> {
+        let state = new_state();
+        let release = Rc::new(RefCell::new(None));
+        // Called once the lock is granted, possibly after the election was dropped: the lock
+        // is held until the returned promise resolves
+        let granted = Closure::once_into_js({
+            let state: Weak<State> = Rc::downgrade(&state);
+            let release = release.clone();
+            move |_lock: JsValue| -> Promise {
+                let Some(state) = state.upgrade() else {
+                    return Promise::resolve(&JsValue::UNDEFINED);
+                };
+                let held = Promise::new(&mut |resolve, _| {
+                    *release.borrow_mut() = Some(resolve);
+                });
+                state.set_leading(true);
+                held
+            }
+        });
+        // Aborted on drop, should the lock not be granted by then
+        let controller = AbortController::new().map_err(connection_error)?;
+        let options = js_sys::Object::new();
+        Reflect::set(&options, &JsValue::from_str("signal"), &controller.signal())
+            .map_err(connection_error)?;
+        let request: Function = Reflect::get(locks, &JsValue::from_str("request"))
+            .and_then(JsCast::dyn_into)
+            .map_err(connection_error)?;
+        let requested = request
+            .apply(
+                locks,
+                &js_sys::Array::of3(
+                    &JsValue::from_str(&format!("indexdb-es:{name}")),
+                    &options,
+                    &granted,
+                ),
+            )
+            .map_err(connection_error)?;
+        // The request rejects once aborted, which is expected
+        if let Ok(requested) = requested.dyn_into::<Promise>() {
+            spawn_local(async move {
+                let _ = JsFuture::from(requested).await;
+            });
+        }
+
+        Ok(Self {
+            state,
+            backend: Backend::Locks {
+                release,
+                request: controller,
+            },
+        })
+    }
+
+    /// Whether this tab currently leads.
+    pub fn leading(&self) -> bool {
+        self.state.leading.get()
+    }
+
+    /// A stream of whether this tab leads: the current value first, then every change, until
+    /// the election is dropped.
+    pub fn is_leader(&self) -> UnboundedReceiver<bool> {
+        let (sender, receiver) = unbounded();
+        let _ = sender.unbounded_send(self.state.leading.get());
+        self.state.watchers.borrow_mut().push(sender);
+        receiver
+    }
+
+    /// Runs the future returned by `job` whenever this tab takes the lead, and aborts it when
+    /// the tab loses it.
+    pub fn spawn_while_leader<F, Fut>(&self, job: F)
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let job = Rc::new(Job {
+            start: Box::new(move || job().boxed_local()),
+            running: RefCell::new(None),
+        });
+        if self.state.leading.get() {
+            start(&job);
+        }
+        self.state.jobs.borrow_mut().push(job);
+    }
+
+    /// Runs `job` every `interval` milliseconds while this tab leads, starting as soon as it
+    /// takes the lead. Suits jobs such as compacting the store with
+    /// [`truncate_before`](crate::IndexDbEventRepository::truncate_before).
+    pub fn with_job<F, Fut>(self, interval: u32, job: F) -> Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let job = Rc::new(job);
+        self.spawn_while_leader(move || {
+            let job = job.clone();
+            async move {
+                loop {
+                    job().await;
+                    sleep(interval).await;
+                }
+            }
+        });
+        self
+    }
+
+    /// Catches `runner` up every `interval` milliseconds while this tab leads. Failures are
+    /// logged to the console and retried at the next run.
+    pub fn with_projection_runner<V, A>(self, runner: ProjectionRunner<V, A>, interval: u32) -> Self
+    where
+        V: View<A> + 'static,
+        A: Aggregate + 'static,
+    {
+        let runner = Rc::new(runner);
+        self.with_job(interval, move || {
+            let runner = runner.clone();
+            async move {
+                if let Err(err) = runner.catch_up().await {
+                    log_error(&format!("projection catch-up failed: {err}"));
+                }
+            }
+        })
+    }
+
+    /// Flushes `outbox` with `push` every `interval` milliseconds while this tab leads.
+    /// Failures are logged to the console and retried at the next run.
+    pub fn with_outbox<F, Fut>(self, outbox: Outbox, interval: u32, push: F) -> Self
+    where
+        F: Fn(Vec<SerializedEvent>) -> Fut + 'static,
+        Fut: Future<Output = Result<(), IndexDbAggregateError>> + 'static,
+    {
+        let outbox = Rc::new(outbox);
+        let push = Rc::new(push);
+        self.with_job(interval, move || {
+            let outbox = outbox.clone();
+            let push = push.clone();
+            async move {
+                if let Err(err) = outbox.flush(|events| push(events)).await {
+                    log_error(&format!("outbox flush failed: {err}"));
+                }
+            }
+        })
+    }
+
+    /// Compacts the log of `repository` every `interval` milliseconds while this tab leads,
+    /// see [`IndexDbEventRepository::compact`]. Failures are logged to the console and retried
+    /// at the next run.
+    pub fn with_compaction(self, repository: IndexDbEventRepository, interval: u32) -> Self {
+        let repository = Rc::new(repository);
+        self.with_job(interval, move || {
+            let repository = repository.clone();
+            async move {
+                if let Err(err) = repository.compact().await {
+                    log_error(&format!("compaction failed: {err}"));
+                }
+            }
+        })
+    }
+}
+
+impl Drop for LeaderElection {
+    fn drop(&mut self) {
+        self.state.set_leading(false);
+        match &self.backend {
+            Backend::Locks { release, request } => match release.take() {
+                Some(release) => {
+                    let _ = release.call0(&JsValue::UNDEFINED);
+                }
+                None => request.abort(),
+            },
+            Backend::Heartbeat {
+                id,
+                channel,
+                interval,
+                on_page_hide,
+                ..
+            } => {
+                let _ = call_global("clearInterval", std::slice::from_ref(interval));
+                let _ = js_sys::global()
+                    .unchecked_into::<EventTarget>()
+                    .remove_event_listener_with_callback(
+                        "pagehide",
+                        on_page_hide.as_ref().unchecked_ref(),
+                    );
+                post(channel, &Heartbeat::Resign { id: *id });
+                channel.set_onmessage(None);
+                channel.close();
+            }
+        }
+    }
+}
+
+fn new_state() -> Rc<State> {
+    Rc::new(State {
+        leading: Cell::new(false),
+        watchers: RefCell::new(Vec::new()),
+        jobs: RefCell::new(Vec::new()),
+    })
+}
+
+/// The `navigator.locks` of the current window or worker, if supported.
+fn lock_manager() -> Option<JsValue> {
+    let navigator = Reflect::get(&js_sys::global(), &JsValue::from_str("navigator")).ok()?;
+    let locks = Reflect::get(&navigator, &JsValue::from_str("locks")).ok()?;
+    (!locks.is_undefined()).then_some(locks)
+}
+
+/// Calls the global function `name`, available in windows and workers alike.
+fn call_global(name: &str, args: &[JsValue]) -> Result<JsValue, IndexDbAggregateError> {
+    let global = js_sys::global();
+    let function: Function = Reflect::get(&global, &JsValue::from_str(name))
+        .and_then(JsCast::dyn_into)
+        .map_err(connection_error)?;
+    let args = args.iter().collect::<js_sys::Array>();
+    function.apply(&global, &args).map_err(connection_error)
+}
+
+/// Resolves after `duration` milliseconds.
+async fn sleep(duration: u32) {
+    let mut resolved = None;
+    let timer = Promise::new(&mut |resolve, _| resolved = Some(resolve));
+    if let Some(resolve) = resolved {
+        let _ = call_global("setTimeout", &[resolve.into(), JsValue::from(duration)]);
+    }
+    let _ = JsFuture::from(timer).await;
+}
+
+fn log_error(message: &str) {
+    web_sys::console::error_1(&JsValue::from_str(message));
+}
+
+fn post(channel: &BroadcastChannel, heartbeat: &Heartbeat) {
+    if let Ok(message) = JsValue::from_serde(heartbeat) {
+        let _ = channel.post_message(&message);
+    }
+}
+
+fn connection_error(err: JsValue) -> IndexDbAggregateError {
+    IndexDbAggregateError::ConnectionError(format!("{err:?}"))
+}
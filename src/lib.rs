@@ -1,12 +1,58 @@
+#[cfg(target_arch = "wasm32")]
+pub use crate::background_sync::*;
+pub use crate::catalog::*;
+pub use crate::clock::*;
 pub use crate::cqrs::*;
+pub use crate::delete::*;
 pub use crate::error::*;
+pub use crate::event_query::*;
 pub use crate::event_repository::*;
+pub use crate::export::*;
+pub use crate::import::*;
+pub use crate::index_range::*;
+pub use crate::interop::*;
+#[cfg(target_arch = "wasm32")]
+pub use crate::leader::*;
+pub use crate::metadata_index::*;
+pub use crate::outbox::*;
+pub use crate::projection_runner::*;
+pub use crate::rebase::*;
+pub use crate::replication::*;
+#[cfg(target_arch = "wasm32")]
+pub use crate::shared_worker::*;
 pub use crate::types::*;
 pub use crate::view_repository::*;
+#[cfg(target_arch = "wasm32")]
+pub use crate::worker::*;
 
+#[cfg(target_arch = "wasm32")]
+mod background_sync;
+mod catalog;
+mod clock;
 mod cqrs;
+mod delete;
 mod error;
+mod event_query;
 mod event_repository;
+mod export;
+mod import;
+mod index_range;
+mod interop;
 mod js_event;
+#[cfg(target_arch = "wasm32")]
+mod leader;
+mod metadata_index;
+mod outbox;
+mod projection;
+mod projection_runner;
+mod rebase;
+mod replication;
+#[cfg(target_arch = "wasm32")]
+mod shared_worker;
+mod snapshot;
+mod storage;
+mod truncate;
 mod types;
 mod view_repository;
+#[cfg(target_arch = "wasm32")]
+mod worker;
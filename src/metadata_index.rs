@@ -0,0 +1,78 @@
+use crate::event_repository::metadata_index_name;
+use crate::index_range::lookup;
+use crate::storage::{Database, Index, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::{js_event::JsEvent, IndexDbAggregateError, IndexDbEventRepository, IndexRange};
+use cqrs_es::persist::SerializedEvent;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The last entry of a page of a metadata index, to resume reading after it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MetadataCursor {
+    pub value: Value,
+    pub position: u64,
+}
+
+/// A page of events read from a metadata index, ordered by metadata value then global order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetadataPage {
+    pub events: Vec<SerializedEvent>,
+    /// The cursor to pass as `after` to read the next page, `None` once the last page is read.
+    pub next: Option<MetadataCursor>,
+}
+
+impl IndexDbEventRepository {
+    /// Returns the events whose metadata field at `key` falls within `range`, starting after the
+    /// entry `after`.
+    ///
+    /// `key` must have been declared with [`IndexDbEventRepository::with_metadata_index`].
+    /// Events without the field, or whose value is not a valid IndexedDB key such as a boolean
+    /// or an object, are not indexed.
+    pub async fn events_by_metadata(
+        &self,
+        key: &str,
+        range: IndexRange,
+        after: Option<MetadataCursor>,
+        limit: u32,
+    ) -> Result<MetadataPage, IndexDbAggregateError> {
+        if !self.metadata_indexes.iter().any(|declared| declared == key) {
+            return Err(IndexDbAggregateError::UnknownError(format!(
+                "no index declared on metadata key {key}"
+            )));
+        }
+        let store_name = self.store_name.clone();
+        let key = key.to_string();
+        let range = KeyRange::index(
+            &range,
+            1,
+            after
+                .as_ref()
+                .map(|after| (&after.value, (after.position as f64).into())),
+        )?;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let index = transaction
+                .object_store(&store_name)?
+                .index(&metadata_index_name(&key))?;
+
+            let values = index.get_all(&range, Some(limit)).await?;
+            let mut events = Vec::with_capacity(values.len());
+            let mut last = None;
+            for value in values {
+                let event = serde_json::from_value::<JsEvent>(value)?;
+                last = lookup(&event.metadata, &key).zip(event.position);
+                events.push(event.into());
+            }
+
+            let next = match last {
+                Some((value, position)) if events.len() == limit as usize => {
+                    Some(MetadataCursor { value, position })
+                }
+                _ => None,
+            };
+            Ok(MetadataPage { events, next })
+        })
+        .await
+    }
+}
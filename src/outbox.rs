@@ -0,0 +1,224 @@
+use crate::event_repository::{CHECKPOINT_STORE, POSITION_INDEX};
+use crate::storage::{
+    run_in, Database, Db, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema, Transaction,
+    TransactionMode,
+};
+use crate::{js_event::JsEvent, IndexDbAggregateError, IndexDbEventRepository, ReplicationFilter};
+use cqrs_es::persist::SerializedEvent;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// The position of the last event pushed by an outbox, along with the filter it was pushed
+/// with. Stored next to the checkpoints of the projection runners, hence the key field.
+#[derive(Serialize, Deserialize)]
+struct OutboxCheckpoint {
+    projection: String,
+    position: u64,
+    filter: ReplicationFilter,
+}
+
+/// The events appended locally that still have to be pushed to a remote store, in global
+/// order.
+///
+/// [`Outbox::flush`] hands them to a push function batch by batch, moving the checkpoint past
+/// each batch once it is pushed. A failed push leaves the checkpoint where it was, so events are
+/// pushed at least once and the remote store must ignore those it already has. Only the events
+/// passing the [replication filter](Outbox::with_filter) are pushed; changing the filter starts
+/// over from the first event.
+///
+/// ```no_run
+/// use indexdb_es::{IndexDbEventRepository, Outbox};
+///
+/// # async fn sync() -> Result<(), indexdb_es::IndexDbAggregateError> {
+/// let outbox = Outbox::new(&IndexDbEventRepository::new(None, None), "server");
+/// let pushed = outbox
+///     .flush(|events| async move {
+///         // Send `events` to the server
+///         Ok(())
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Outbox {
+    db_name: String,
+    store_name: String,
+    schema: Vec<StoreSchema>,
+    name: String,
+    filter: ReplicationFilter,
+    batch_size: u32,
+}
+
+impl Outbox {
+    /// Creates the outbox `name` of the events of `event_repository`.
+    pub fn new(event_repository: &IndexDbEventRepository, name: &str) -> Self {
+        let mut schema = event_repository.schema();
+        schema.push(StoreSchema {
+            name: CHECKPOINT_STORE.to_string(),
+            key_path: KeyPath::single("projection"),
+            indexes: Vec::new(),
+        });
+        Self {
+            db_name: event_repository.db_name.clone(),
+            store_name: event_repository.store_name.clone(),
+            schema,
+            name: name.to_string(),
+            filter: ReplicationFilter::default(),
+            batch_size: 500,
+        }
+    }
+
+    /// Only pushes the events passing `filter`.
+    pub fn with_filter(mut self, filter: ReplicationFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets the number of events read per batch, 500 by default.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Returns the position of the last event pushed, 0 if none was or if the filter changed
+    /// since.
+    pub async fn checkpoint(&self) -> Result<u64, IndexDbAggregateError> {
+        let key = self.key();
+        let filter = self.filter.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[CHECKPOINT_STORE], TransactionMode::ReadOnly)?;
+            let value = transaction
+                .object_store(CHECKPOINT_STORE)?
+                .get(&Key::from(key.as_str()))
+                .await?;
+            match value {
+                Some(value) => {
+                    let checkpoint = serde_json::from_value::<OutboxCheckpoint>(value)?;
+                    Ok(if checkpoint.filter == filter {
+                        checkpoint.position
+                    } else {
+                        0
+                    })
+                }
+                None => Ok(0),
+            }
+        })
+        .await
+    }
+
+    /// Counts the events after the checkpoint, those the filter leaves out included.
+    pub async fn pending(&self) -> Result<u32, IndexDbAggregateError> {
+        let after = self.checkpoint().await?;
+        let store_name = self.store_name.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let range = KeyRange::lower_bound((after as f64).into(), true);
+            transaction
+                .object_store(&store_name)?
+                .index(POSITION_INDEX)?
+                .count(&range)
+                .await
+        })
+        .await
+    }
+
+    /// Pushes the events after the checkpoint with `push`, one batch at a time, until none is
+    /// left or a push fails. Returns the number of events pushed.
+    pub async fn flush<F, Fut>(&self, mut push: F) -> Result<usize, IndexDbAggregateError>
+    where
+        F: FnMut(Vec<SerializedEvent>) -> Fut,
+        Fut: Future<Output = Result<(), IndexDbAggregateError>>,
+    {
+        let mut after = self.checkpoint().await?;
+        let mut pushed = 0;
+        loop {
+            let (events, last) = self.read_batch(after).await?;
+            if events.is_empty() {
+                return Ok(pushed);
+            }
+            let read = events.len();
+            let events = self.filter.apply(&events);
+            if !events.is_empty() {
+                pushed += events.len();
+                push(events).await?;
+            }
+            self.write_checkpoint(last).await?;
+            after = last;
+            if read < self.batch_size as usize {
+                return Ok(pushed);
+            }
+        }
+    }
+
+    /// Reads the next batch of events after the position `after`, along with the position of
+    /// the last of them.
+    async fn read_batch(
+        &self,
+        after: u64,
+    ) -> Result<(Vec<SerializedEvent>, u64), IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+        let batch_size = self.batch_size;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let range = KeyRange::lower_bound((after as f64).into(), true);
+            let values = transaction
+                .object_store(&store_name)?
+                .index(POSITION_INDEX)?
+                .get_all(&range, Some(batch_size))
+                .await?;
+
+            let mut events = Vec::with_capacity(values.len());
+            let mut last = after;
+            for value in values {
+                let event = serde_json::from_value::<JsEvent>(value)?;
+                last = event.position.unwrap_or(last);
+                events.push(event.into());
+            }
+            Ok((events, last))
+        })
+        .await
+    }
+
+    /// Moves the checkpoint to `position`, unless a concurrent flush already moved it further.
+    async fn write_checkpoint(&self, position: u64) -> Result<(), IndexDbAggregateError> {
+        let key = self.key();
+        let filter = self.filter.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[CHECKPOINT_STORE], TransactionMode::ReadWrite)?;
+            let store = transaction.object_store(CHECKPOINT_STORE)?;
+            if let Some(value) = store.get(&Key::from(key.as_str())).await? {
+                let current = serde_json::from_value::<OutboxCheckpoint>(value)?;
+                if current.filter == filter && current.position >= position {
+                    return transaction.commit().await;
+                }
+            }
+            let checkpoint = OutboxCheckpoint {
+                projection: key,
+                position,
+                filter,
+            };
+            store.put(&serde_json::to_value(&checkpoint)?).await?;
+            transaction.commit().await
+        })
+        .await
+    }
+
+    /// The key of the checkpoint, kept apart from those of the view stores.
+    fn key(&self) -> String {
+        format!("outbox:{}", self.name)
+    }
+
+    /// Runs `f` against an open connection to the database and hands its result back.
+    fn run<T, F, Fut>(&self, f: F) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+    where
+        T: 'static,
+        F: FnOnce(Db) -> Fut + 'static,
+        Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+    {
+        run_in(self.db_name.clone(), self.schema.clone(), f)
+    }
+}
@@ -0,0 +1,263 @@
+use crate::event_repository::CHECKPOINT_STORE;
+use crate::projection_runner::{staging_name, Checkpoint};
+use crate::storage::{Key, KeyPath, KeyRange, ObjectStore, StoreSchema, Transaction};
+use crate::view_repository::ViewRecord;
+use crate::{IndexDbAggregateError, IndexDbEventRepository, IndexDbViewRepository};
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::{Aggregate, EventEnvelope, View};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// A view store updated within the transaction that appends the events it is built from.
+pub(crate) trait Projector: Send + Sync {
+    /// The view store and its indexes.
+    fn schema(&self) -> StoreSchema;
+
+    /// The version of the views, recorded along with their checkpoint.
+    fn schema_version(&self) -> u32;
+
+    /// The id of the view updated by `event`, `None` if the event does not concern the view.
+    fn view_id(&self, event: &SerializedEvent) -> Option<String>;
+
+    /// Applies `event` to the serialized view, `None` if the view does not exist yet.
+    fn apply(
+        &self,
+        view: Option<Value>,
+        event: &SerializedEvent,
+    ) -> Result<Value, IndexDbAggregateError>;
+}
+
+impl<V, A> Projector for IndexDbViewRepository<V, A>
+where
+    V: View<A> + 'static,
+    A: Aggregate + 'static,
+{
+    fn schema(&self) -> StoreSchema {
+        self.schema().remove(0)
+    }
+
+    fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    fn view_id(&self, event: &SerializedEvent) -> Option<String> {
+        // Views are keyed by aggregate id, as with `GenericQuery`
+        (event.aggregate_type == A::aggregate_type()).then(|| event.aggregate_id.clone())
+    }
+
+    fn apply(
+        &self,
+        view: Option<Value>,
+        event: &SerializedEvent,
+    ) -> Result<Value, IndexDbAggregateError> {
+        let mut view: V = match view {
+            Some(view) => serde_json::from_value(view)?,
+            None => V::default(),
+        };
+        let envelope = EventEnvelope::<A> {
+            aggregate_id: event.aggregate_id.clone(),
+            sequence: event.sequence,
+            payload: serde_json::from_value(event.payload.clone())?,
+            metadata: match &event.metadata {
+                Value::Null => HashMap::new(),
+                metadata => serde_json::from_value(metadata.clone())?,
+            },
+        };
+        view.update(&envelope);
+        Ok(serde_json::to_value(&view)?)
+    }
+}
+
+impl IndexDbEventRepository {
+    /// Updates the views of `view_repository` in the same transaction as the events they are
+    /// built from, so that the events and the views can never diverge.
+    ///
+    /// Each event of the view's aggregate type updates the view keyed by its aggregate id.
+    /// Should a view fail to update, the events are not committed either. The view must not also
+    /// be registered as a query of the `CqrsFramework`, or the events would be applied twice.
+    ///
+    /// Imported events update the views like appended ones. A rebase rewrites the history of
+    /// an aggregate instance, so its view is rebuilt from the events left in the store.
+    ///
+    /// The views share their checkpoint with a [`ProjectionRunner`](crate::ProjectionRunner)
+    /// of the same view store, which can run alongside without applying any event twice. The
+    /// views are only updated while they are caught up with the store: before its first write
+    /// the repository [catches them up](IndexDbEventRepository::catch_up_projections), which
+    /// also rebuilds them when their schema version changed.
+    pub fn with_projection<V, A>(mut self, view_repository: IndexDbViewRepository<V, A>) -> Self
+    where
+        V: View<A> + 'static,
+        A: Aggregate + 'static,
+    {
+        self.projections.push(Arc::new(view_repository));
+        self
+    }
+
+    /// The names of the view stores updated with the events, along with their checkpoints.
+    pub(crate) fn projection_stores(&self) -> Vec<String> {
+        let mut store_names: Vec<String> = self
+            .projections
+            .iter()
+            .map(|projection| projection.schema().name)
+            .collect();
+        if !store_names.is_empty() {
+            store_names.push(CHECKPOINT_STORE.to_string());
+        }
+        store_names
+    }
+}
+
+/// The view store of `projection`, the store its views are rebuilt in and the checkpoints.
+pub(crate) fn projection_schema(projection: &dyn Projector) -> Vec<StoreSchema> {
+    let views = projection.schema();
+    let staging = StoreSchema {
+        name: staging_name(&views.name),
+        key_path: KeyPath::single("view_id"),
+        indexes: Vec::new(),
+    };
+    let checkpoints = StoreSchema {
+        name: CHECKPOINT_STORE.to_string(),
+        key_path: KeyPath::single("projection"),
+        indexes: Vec::new(),
+    };
+    vec![views, staging, checkpoints]
+}
+
+/// Applies `events`, just added from the position `first`, to the views of `projections`
+/// within `transaction`, skipping the views that are not caught up.
+pub(crate) async fn project(
+    transaction: &impl Transaction,
+    projections: &[Arc<dyn Projector>],
+    events: &[SerializedEvent],
+    first: u64,
+) -> Result<(), IndexDbAggregateError> {
+    for projection in projections {
+        if advance(transaction, projection.as_ref(), first, events.len()).await? {
+            project_into(
+                transaction,
+                &projection.schema().name,
+                projection.as_ref(),
+                events,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds the views of `projections` updated by `history` from these events alone,
+/// discarding their current state, e.g. once the history of an aggregate instance was
+/// rewritten. The `added` events of the history were just added from the position `first`.
+pub(crate) async fn reproject(
+    transaction: &impl Transaction,
+    projections: &[Arc<dyn Projector>],
+    history: &[SerializedEvent],
+    first: u64,
+    added: usize,
+) -> Result<(), IndexDbAggregateError> {
+    for projection in projections {
+        if !advance(transaction, projection.as_ref(), first, added).await? {
+            continue;
+        }
+        delete_views(transaction, projection.as_ref(), history).await?;
+        let store_name = projection.schema().name;
+        project_into(transaction, &store_name, projection.as_ref(), history).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the views of `projections` built from the `history` of an aggregate instance, once
+/// the instance itself is deleted.
+pub(crate) async fn unproject(
+    transaction: &impl Transaction,
+    projections: &[Arc<dyn Projector>],
+    history: &[SerializedEvent],
+) -> Result<(), IndexDbAggregateError> {
+    for projection in projections {
+        delete_views(transaction, projection.as_ref(), history).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the views of `projection` that `events` update.
+async fn delete_views(
+    transaction: &impl Transaction,
+    projection: &dyn Projector,
+    events: &[SerializedEvent],
+) -> Result<(), IndexDbAggregateError> {
+    let view_ids: BTreeSet<String> = events
+        .iter()
+        .filter_map(|event| projection.view_id(event))
+        .collect();
+    let store = transaction.object_store(&projection.schema().name)?;
+    for view_id in view_ids {
+        store
+            .delete(&KeyRange::only(Key::from(view_id.as_str())))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Moves the checkpoint of `projection` past the `count` events added from the position
+/// `first`, returning false if the views are not caught up with the events before them, in
+/// which case they are left to a catch-up.
+async fn advance(
+    transaction: &impl Transaction,
+    projection: &dyn Projector,
+    first: u64,
+    count: usize,
+) -> Result<bool, IndexDbAggregateError> {
+    let store_name = projection.schema().name;
+    let checkpoints = transaction.object_store(CHECKPOINT_STORE)?;
+    let position = match checkpoints.get(&Key::from(store_name.as_str())).await? {
+        Some(value) => {
+            let checkpoint = serde_json::from_value::<Checkpoint>(value)?;
+            if checkpoint.schema_version != projection.schema_version() {
+                return Ok(false);
+            }
+            checkpoint.position
+        }
+        None => 0,
+    };
+    if position + 1 < first {
+        return Ok(false);
+    }
+    let checkpoint = Checkpoint {
+        projection: store_name,
+        position: position.max(first + count as u64 - 1),
+        schema_version: projection.schema_version(),
+    };
+    checkpoints.put(&serde_json::to_value(&checkpoint)?).await?;
+    Ok(true)
+}
+
+/// Applies `events` to the views of `projection` held in the store `store_name`.
+pub(crate) async fn project_into(
+    transaction: &impl Transaction,
+    store_name: &str,
+    projection: &dyn Projector,
+    events: &[SerializedEvent],
+) -> Result<(), IndexDbAggregateError> {
+    let store = transaction.object_store(store_name)?;
+    for event in events {
+        let view_id = match projection.view_id(event) {
+            Some(view_id) => view_id,
+            None => continue,
+        };
+        let (view, version) = match store.get(&Key::from(view_id.as_str())).await? {
+            Some(value) => {
+                let record = serde_json::from_value::<ViewRecord>(value)?;
+                (Some(record.payload), record.version)
+            }
+            None => (None, 0),
+        };
+        let record = ViewRecord {
+            view_id,
+            version: version + 1,
+            payload: projection.apply(view, event)?,
+        };
+        store.put(&serde_json::to_value(&record)?).await?;
+    }
+    Ok(())
+}
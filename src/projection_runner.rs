@@ -0,0 +1,365 @@
+use crate::event_repository::{CHECKPOINT_STORE, POSITION_INDEX};
+use crate::projection::{project_into, projection_schema, Projector};
+use crate::storage::{
+    run_in, Database, Db, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema, Transaction,
+    TransactionMode,
+};
+use crate::{
+    js_event::JsEvent, IndexDbAggregateError, IndexDbEventRepository, IndexDbViewRepository,
+};
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::{Aggregate, View};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+
+/// Reported after each batch of events replayed by [`ProjectionRunner::rebuild`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub processed: usize,
+    pub total: usize,
+}
+
+/// The position of the last event applied to a view store, along with the schema version of
+/// the views it holds.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) projection: String,
+    pub(crate) position: u64,
+    #[serde(default)]
+    pub(crate) schema_version: u32,
+}
+
+/// Keeps the views of an [`IndexDbViewRepository`] up to date with the event store by
+/// replaying the events appended since its checkpoint, e.g. on startup.
+///
+/// Events are read in global order and applied in batches; each batch is applied to the views
+/// in the same transaction that moves the checkpoint forward.
+///
+/// The checkpoint also records the [schema version](IndexDbViewRepository::with_schema_version)
+/// of the views. When it differs from the declared one, [`ProjectionRunner::catch_up`] rebuilds
+/// the views first.
+///
+/// The checkpoint is shared with the
+/// [transactional projection](IndexDbEventRepository::with_projection) of the same view store,
+/// if any: each event is applied by whichever gets to it first.
+pub struct ProjectionRunner<V, A> {
+    replayer: Replayer,
+    view_repository: Arc<IndexDbViewRepository<V, A>>,
+}
+
+impl<V, A> ProjectionRunner<V, A>
+where
+    V: View<A> + 'static,
+    A: Aggregate + 'static,
+{
+    /// Creates a runner projecting the events of `event_repository` into `view_repository`,
+    /// whose view name also names the checkpoint.
+    pub fn new(
+        event_repository: &IndexDbEventRepository,
+        view_repository: IndexDbViewRepository<V, A>,
+    ) -> Self {
+        let view_repository = Arc::new(view_repository);
+        Self {
+            replayer: Replayer::new(event_repository, view_repository.clone()),
+            view_repository,
+        }
+    }
+
+    /// Sets the number of events applied per transaction, 500 by default.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.replayer.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// The repository holding the projected views.
+    pub fn view_repository(&self) -> &IndexDbViewRepository<V, A> {
+        &self.view_repository
+    }
+
+    /// Returns the position of the last event applied to the views, 0 if none was.
+    pub async fn checkpoint(&self) -> Result<u64, IndexDbAggregateError> {
+        Ok(self
+            .replayer
+            .read_checkpoint(&self.replayer.view_name)
+            .await?
+            .map_or(0, |checkpoint| checkpoint.position))
+    }
+
+    /// Applies the events appended since the checkpoint to the views, rebuilding them instead
+    /// if they have no checkpoint yet or their schema version changed. Returns the number of
+    /// events read.
+    pub async fn catch_up(&self) -> Result<usize, IndexDbAggregateError> {
+        self.replayer.catch_up().await
+    }
+
+    /// Rebuilds the views by replaying every event of the store, reporting progress after each
+    /// batch. Returns the number of events read.
+    ///
+    /// The views are rebuilt in a separate store, then swapped in at once: until the rebuild
+    /// finishes, the current views keep being served.
+    pub async fn rebuild<P>(&self, progress: P) -> Result<usize, IndexDbAggregateError>
+    where
+        P: FnMut(ReplayProgress),
+    {
+        self.replayer.rebuild(progress).await
+    }
+}
+
+/// Replays the events of the store into the views of a projection, for the runners and the
+/// catch-up of the transactional projections alike.
+pub(crate) struct Replayer {
+    db_name: String,
+    store_name: String,
+    schema: Vec<StoreSchema>,
+    view_name: String,
+    projection: Arc<dyn Projector>,
+    batch_size: u32,
+}
+
+impl Replayer {
+    pub(crate) fn new(
+        event_repository: &IndexDbEventRepository,
+        projection: Arc<dyn Projector>,
+    ) -> Self {
+        let mut schema = event_repository.schema();
+        schema.extend(projection_schema(projection.as_ref()));
+        Self {
+            db_name: event_repository.db_name.clone(),
+            store_name: event_repository.store_name.clone(),
+            schema,
+            view_name: projection.schema().name,
+            projection,
+            batch_size: 500,
+        }
+    }
+
+    /// Applies the events appended since the checkpoint to the views, rebuilding them first if
+    /// they have no checkpoint or their schema version changed. Returns the number of events
+    /// read.
+    pub(crate) async fn catch_up(&self) -> Result<usize, IndexDbAggregateError> {
+        let checkpoint = self.read_checkpoint(&self.view_name).await?;
+        match checkpoint {
+            Some(checkpoint) if checkpoint.schema_version == self.projection.schema_version() => {
+                self.replay(&self.view_name, |_| {}).await
+            }
+            _ => self.rebuild(|_| {}).await,
+        }
+    }
+
+    /// Rebuilds the views if the schema version recorded with their checkpoint, 0 without one,
+    /// differs from the declared one. Returns the number of events read.
+    pub(crate) async fn upgrade(&self) -> Result<usize, IndexDbAggregateError> {
+        let recorded = self
+            .read_checkpoint(&self.view_name)
+            .await?
+            .map_or(0, |checkpoint| checkpoint.schema_version);
+        match recorded == self.projection.schema_version() {
+            true => Ok(0),
+            false => self.rebuild(|_| {}).await,
+        }
+    }
+
+    /// Rebuilds the views in the staging store, swaps them in and applies the events appended
+    /// meanwhile. Returns the number of events read.
+    pub(crate) async fn rebuild<P>(&self, progress: P) -> Result<usize, IndexDbAggregateError>
+    where
+        P: FnMut(ReplayProgress),
+    {
+        let view_name = self.view_name.clone();
+        let staging = staging_name(&view_name);
+        let schema_version = self.projection.schema_version();
+        let batch_size = self.batch_size;
+
+        // Start over from an empty staging store
+        let cleared = staging.clone();
+        self.run(move |db| async move {
+            let transaction = db.transaction(
+                &[cleared.as_str(), CHECKPOINT_STORE],
+                TransactionMode::ReadWrite,
+            )?;
+            transaction.object_store(&cleared)?.clear().await?;
+            transaction
+                .object_store(CHECKPOINT_STORE)?
+                .delete(&KeyRange::only(cleared.as_str().into()))
+                .await?;
+            transaction.commit().await
+        })
+        .await?;
+
+        let read = self.replay(&staging, progress).await?;
+
+        // Swap the rebuilt views in, along with their checkpoint, copying them a batch at a time
+        self.run(move |db| async move {
+            let transaction = db.transaction(
+                &[view_name.as_str(), staging.as_str(), CHECKPOINT_STORE],
+                TransactionMode::ReadWrite,
+            )?;
+            let staging_store = transaction.object_store(&staging)?;
+            let store = transaction.object_store(&view_name)?;
+            store.clear().await?;
+            let key_path = KeyPath::single("view_id");
+            let mut range = KeyRange::all();
+            loop {
+                let views = staging_store.get_all(&range, Some(batch_size)).await?;
+                store.put_all(&views).await?;
+                match views.last().and_then(|view| key_path.extract(view)) {
+                    Some(last) if views.len() == batch_size as usize => {
+                        range = KeyRange::lower_bound(last, true);
+                    }
+                    _ => break,
+                }
+            }
+            staging_store.clear().await?;
+
+            let checkpoints = transaction.object_store(CHECKPOINT_STORE)?;
+            let position = match checkpoints.get(&Key::from(staging.as_str())).await? {
+                Some(value) => serde_json::from_value::<Checkpoint>(value)?.position,
+                None => 0,
+            };
+            checkpoints
+                .delete(&KeyRange::only(staging.as_str().into()))
+                .await?;
+            let checkpoint = Checkpoint {
+                projection: view_name,
+                position,
+                schema_version,
+            };
+            checkpoints.put(&serde_json::to_value(&checkpoint)?).await?;
+            transaction.commit().await
+        })
+        .await?;
+
+        Ok(read + self.replay(&self.view_name, |_| {}).await?)
+    }
+
+    /// Reads the checkpoint of the view store `store_name`.
+    async fn read_checkpoint(
+        &self,
+        store_name: &str,
+    ) -> Result<Option<Checkpoint>, IndexDbAggregateError> {
+        let store_name = store_name.to_string();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[CHECKPOINT_STORE], TransactionMode::ReadOnly)?;
+            let value = transaction
+                .object_store(CHECKPOINT_STORE)?
+                .get(&Key::from(store_name.as_str()))
+                .await?;
+            match value {
+                Some(value) => Ok(Some(serde_json::from_value::<Checkpoint>(value)?)),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    /// Applies the events after the checkpoint of the view store `target` to its views, one
+    /// batch per transaction.
+    async fn replay<P>(&self, target: &str, mut progress: P) -> Result<usize, IndexDbAggregateError>
+    where
+        P: FnMut(ReplayProgress),
+    {
+        let after = self
+            .read_checkpoint(target)
+            .await?
+            .map_or(0, |checkpoint| checkpoint.position);
+        let total = self.count_after(after).await? as usize;
+        let mut processed = 0;
+        loop {
+            let read = self.apply_batch(target).await?;
+            processed += read;
+            if read > 0 {
+                progress(ReplayProgress {
+                    processed,
+                    total: total.max(processed),
+                });
+            }
+            if read < self.batch_size as usize {
+                return Ok(processed);
+            }
+        }
+    }
+
+    /// Counts the events after the position `after`.
+    async fn count_after(&self, after: u64) -> Result<u32, IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadOnly)?;
+            let range = KeyRange::lower_bound((after as f64).into(), true);
+            transaction
+                .object_store(&store_name)?
+                .index(POSITION_INDEX)?
+                .count(&range)
+                .await
+        })
+        .await
+    }
+
+    /// Applies the next batch of events after the checkpoint of the view store `target` and
+    /// moves the checkpoint to the last of them. The checkpoint is read in the same
+    /// transaction, as the transactional projections move it too. Returns the number of events
+    /// read.
+    async fn apply_batch(&self, target: &str) -> Result<usize, IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+        let view_name = target.to_string();
+        let projection = self.projection.clone();
+        let batch_size = self.batch_size;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(
+                &[store_name.as_str(), view_name.as_str(), CHECKPOINT_STORE],
+                TransactionMode::ReadWrite,
+            )?;
+            let checkpoints = transaction.object_store(CHECKPOINT_STORE)?;
+            let after = match checkpoints.get(&Key::from(view_name.as_str())).await? {
+                Some(value) => serde_json::from_value::<Checkpoint>(value)?.position,
+                None => 0,
+            };
+            let range = KeyRange::lower_bound((after as f64).into(), true);
+            let values = transaction
+                .object_store(&store_name)?
+                .index(POSITION_INDEX)?
+                .get_all(&range, Some(batch_size))
+                .await?;
+
+            let mut events: Vec<SerializedEvent> = Vec::with_capacity(values.len());
+            let mut last = after;
+            for value in values {
+                let event = serde_json::from_value::<JsEvent>(value)?;
+                last = event.position.unwrap_or(last);
+                events.push(event.into());
+            }
+            if events.is_empty() {
+                return Ok(0);
+            }
+
+            project_into(&transaction, &view_name, projection.as_ref(), &events).await?;
+            let checkpoint = Checkpoint {
+                projection: view_name,
+                position: last,
+                schema_version: projection.schema_version(),
+            };
+            checkpoints.put(&serde_json::to_value(&checkpoint)?).await?;
+            transaction.commit().await?;
+            Ok(events.len())
+        })
+        .await
+    }
+
+    /// Runs `f` against an open connection to the database and hands its result back.
+    fn run<T, F, Fut>(&self, f: F) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+    where
+        T: 'static,
+        F: FnOnce(Db) -> Fut + 'static,
+        Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+    {
+        run_in(self.db_name.clone(), self.schema.clone(), f)
+    }
+}
+
+/// Name of the store the views of `view_name` are rebuilt in.
+pub(crate) fn staging_name(view_name: &str) -> String {
+    format!("{view_name}.rebuild")
+}
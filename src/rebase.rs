@@ -0,0 +1,170 @@
+use crate::catalog::{set_last_sequence, update_catalog};
+use crate::event_repository::{
+    add_events, check_tombstones, read_events, stream_range, CATALOG_STORE, POSITION_STORE,
+    TOMBSTONE_STORE,
+};
+use crate::projection::reproject;
+use crate::storage::{Database, ObjectStore, Transaction, TransactionMode};
+use crate::{IndexDbAggregateError, IndexDbEventRepository, ReplicationFilter};
+use cqrs_es::persist::SerializedEvent;
+use cqrs_es::Aggregate;
+use std::future::Future;
+
+impl IndexDbEventRepository {
+    /// Rebases the local events of an aggregate instance on top of `remote_events`, the
+    /// authoritative history confirmed by the server, which must all belong to that instance.
+    ///
+    /// Every local event at or after the first remote sequence is moved aside and the remote
+    /// events are inserted in its place. `resolver` then receives the displaced events along
+    /// with the last sequence of the new history, and the events it resolves to are appended on
+    /// top. The rewrite happens in a single transaction, along with the rebuild of the
+    /// [projected](IndexDbEventRepository::with_projection) views of the aggregate instance: if
+    /// any resulting event collides with an existing one, an `OptimisticLock` error is returned
+    /// and the store is left untouched. A soft deleted aggregate instance cannot be rebased and
+    /// fails with an `AggregateDeleted` error.
+    ///
+    /// The resolver is asynchronous and runs between the read of the displaced events and the
+    /// rewrite, so it may re-execute the original commands against the rebased aggregate, or
+    /// simply renumber the events, see [`renumber_events`]. Should the stream change while it
+    /// runs, the rebase fails with an `OptimisticLock` error.
+    ///
+    /// Returns the events appended by the resolver.
+    pub async fn rebase<A, F, Fut>(
+        &self,
+        aggregate_id: &str,
+        remote_events: &[SerializedEvent],
+        resolver: F,
+    ) -> Result<Vec<SerializedEvent>, IndexDbAggregateError>
+    where
+        A: Aggregate,
+        F: FnOnce(Vec<SerializedEvent>, usize) -> Fut,
+        Fut: Future<Output = Vec<SerializedEvent>>,
+    {
+        self.rebase_with_filter::<A, F, Fut>(
+            aggregate_id,
+            remote_events,
+            &ReplicationFilter::default(),
+            resolver,
+        )
+        .await
+    }
+
+    /// Rebases like [`IndexDbEventRepository::rebase`], on top of the remote events passing
+    /// `filter` only. Nothing happens if the filter leaves them all out.
+    pub async fn rebase_with_filter<A, F, Fut>(
+        &self,
+        aggregate_id: &str,
+        remote_events: &[SerializedEvent],
+        filter: &ReplicationFilter,
+        resolver: F,
+    ) -> Result<Vec<SerializedEvent>, IndexDbAggregateError>
+    where
+        A: Aggregate,
+        F: FnOnce(Vec<SerializedEvent>, usize) -> Fut,
+        Fut: Future<Output = Vec<SerializedEvent>>,
+    {
+        let aggregate_type = A::aggregate_type();
+        check_stream(&aggregate_type, aggregate_id, remote_events)?;
+        let remote_events = filter.apply(remote_events);
+        let first_remote = match remote_events.iter().map(|e| e.sequence).min() {
+            Some(sequence) => sequence,
+            None => return Ok(Vec::new()),
+        };
+        let last_remote = remote_events.iter().map(|e| e.sequence).max().unwrap_or(0);
+        self.ensure_projections_caught_up().await?;
+
+        // The events to move aside, read beforehand as the resolver cannot run within a
+        // transaction
+        let displaced = self
+            .select_events(&aggregate_type, aggregate_id, first_remote)
+            .await?;
+        let rebased = resolver(displaced.clone(), last_remote).await;
+        check_stream(&aggregate_type, aggregate_id, &rebased)?;
+
+        let store_name = self.store_name.clone();
+        let aggregate_id = aggregate_id.to_string();
+        let now = self.clock.now();
+        let projections = self.projections.clone();
+        let mut store_names = vec![
+            store_name.clone(),
+            POSITION_STORE.to_string(),
+            CATALOG_STORE.to_string(),
+            TOMBSTONE_STORE.to_string(),
+        ];
+        store_names.extend(self.projection_stores());
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&store_names, TransactionMode::ReadWrite)?;
+            check_tombstones(
+                &transaction,
+                &[(aggregate_type.clone(), aggregate_id.clone())],
+            )
+            .await?;
+            let store = transaction.object_store(&store_name)?;
+
+            // Move the conflicting local events aside, unless they changed in the meantime
+            let local = stream_range(&aggregate_type, &aggregate_id, first_remote);
+            let current = read_events(&store, &local).await?;
+            if current != displaced {
+                transaction.abort().await?;
+                return Err(IndexDbAggregateError::OptimisticLock);
+            }
+            store.delete(&local).await?;
+
+            // Insert the authoritative history, then the resolved local events on top of it
+            let first = add_events(&transaction, &store_name, &remote_events, now).await?;
+            add_events(&transaction, &store_name, &rebased, now).await?;
+
+            // The catalog only ever extends a stream, where a rebase may shorten it
+            let history =
+                read_events(&store, &stream_range(&aggregate_type, &aggregate_id, 0)).await?;
+            let catalog = transaction.object_store(CATALOG_STORE)?;
+            update_catalog(&catalog, &remote_events, displaced.len(), now).await?;
+            update_catalog(&catalog, &rebased, 0, now).await?;
+            let last_sequence = history.last().map_or(0, |e| e.sequence);
+            set_last_sequence(&catalog, &aggregate_type, &aggregate_id, last_sequence).await?;
+
+            // The views built from the displaced events are rebuilt from the new history
+            let added = remote_events.len() + rebased.len();
+            reproject(&transaction, &projections, &history, first, added).await?;
+
+            transaction.commit().await?;
+            Ok(rebased)
+        })
+        .await
+    }
+}
+
+/// Fails unless every event belongs to the aggregate instance being rebased.
+fn check_stream(
+    aggregate_type: &str,
+    aggregate_id: &str,
+    events: &[SerializedEvent],
+) -> Result<(), IndexDbAggregateError> {
+    match events
+        .iter()
+        .find(|e| e.aggregate_type != aggregate_type || e.aggregate_id != aggregate_id)
+    {
+        Some(event) => Err(IndexDbAggregateError::UnknownError(format!(
+            "event {} of {} {} does not belong to {aggregate_type} {aggregate_id}",
+            event.sequence, event.aggregate_type, event.aggregate_id
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// A rebase resolver that keeps the displaced local events unchanged, only renumbering their
+/// sequences so that they follow `last_sequence`.
+pub async fn renumber_events(
+    events: Vec<SerializedEvent>,
+    last_sequence: usize,
+) -> Vec<SerializedEvent> {
+    events
+        .into_iter()
+        .enumerate()
+        .map(|(i, event)| SerializedEvent {
+            sequence: last_sequence + i + 1,
+            ..event
+        })
+        .collect()
+}
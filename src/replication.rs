@@ -0,0 +1,82 @@
+use cqrs_es::persist::SerializedEvent;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Selects which events are allowed to leave the device or to be imported from a remote store.
+///
+/// Each criterion left empty matches every event; a criterion with values matches an event if
+/// any of its values does. An event is replicated only when all criteria match.
+///
+/// The filter applies to the events pushed by an [`Outbox`](crate::Outbox::with_filter) and to
+/// those pulled by an [import](crate::ImportOptions::with_filter), NDJSON or rows, or a
+/// [rebase](crate::IndexDbEventRepository::rebase_with_filter).
+///
+/// The filter is serializable so that it can be stored next to a sync checkpoint: a stored
+/// filter that differs from the configured one means the checkpoint no longer describes what
+/// was replicated and a backfill is needed.
+///
+/// ```
+/// use indexdb_es::ReplicationFilter;
+///
+/// let filter = ReplicationFilter::default()
+///     .with_aggregate_type("Order")
+///     .with_aggregate_id_prefix("shop-1/");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplicationFilter {
+    aggregate_types: Vec<String>,
+    aggregate_id_prefixes: Vec<String>,
+    event_types: Vec<String>,
+    metadata: Vec<(String, Value)>,
+}
+
+impl ReplicationFilter {
+    /// Only replicates events of the given aggregate type.
+    pub fn with_aggregate_type(mut self, aggregate_type: impl Into<String>) -> Self {
+        self.aggregate_types.push(aggregate_type.into());
+        self
+    }
+
+    /// Only replicates events of aggregate instances whose id starts with `prefix`.
+    pub fn with_aggregate_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.aggregate_id_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Only replicates events of the given event type.
+    pub fn with_event_type(mut self, event_type: impl Into<String>) -> Self {
+        self.event_types.push(event_type.into());
+        self
+    }
+
+    /// Only replicates events whose metadata holds `value` under `key`.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.metadata.push((key.into(), value));
+        self
+    }
+
+    /// Returns true if the event passes the filter.
+    pub fn matches(&self, event: &SerializedEvent) -> bool {
+        matches_any(&self.aggregate_types, |t| t == &event.aggregate_type)
+            && matches_any(&self.aggregate_id_prefixes, |p| {
+                event.aggregate_id.starts_with(p.as_str())
+            })
+            && matches_any(&self.event_types, |t| t == &event.event_type)
+            && matches_any(&self.metadata, |(key, value)| {
+                event.metadata.get(key) == Some(value)
+            })
+    }
+
+    /// Keeps only the events passing the filter.
+    pub fn apply(&self, events: &[SerializedEvent]) -> Vec<SerializedEvent> {
+        events
+            .iter()
+            .filter(|event| self.matches(event))
+            .cloned()
+            .collect()
+    }
+}
+
+fn matches_any<T>(criterion: &[T], predicate: impl Fn(&T) -> bool) -> bool {
+    criterion.is_empty() || criterion.iter().any(predicate)
+}
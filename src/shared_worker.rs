@@ -0,0 +1,313 @@
+//! A single writer for every tab of an origin: a shared worker owns the repository, keeps the
+//! projections up to date and runs the sync loops, while the tabs forward their calls to it.
+
+use crate::worker::{listen, MessageEndpoint, OnMessage};
+use crate::{
+    IndexDbAggregateError, IndexDbEventRepository, ProjectionRunner, WorkerEventRepository,
+};
+use async_trait::async_trait;
+use cqrs_es::persist::{
+    PersistedEventRepository, PersistenceError, ReplayStream, SerializedEvent, SerializedSnapshot,
+};
+use cqrs_es::{Aggregate, View};
+use futures::future::{AbortHandle, Abortable, LocalBoxFuture};
+use futures::FutureExt;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::rc::{Rc, Weak};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{MessageEvent, MessagePort, SharedWorker, SharedWorkerGlobalScope};
+
+type CatchUp = Rc<dyn Fn() -> LocalBoxFuture<'static, Result<usize, IndexDbAggregateError>>>;
+
+/// What the host shares with the handlers of its ports.
+struct Shared {
+    repository: Rc<IndexDbEventRepository>,
+    projections: RefCell<Vec<CatchUp>>,
+    catching_up: Cell<bool>,
+    behind: Cell<bool>,
+    ports: RefCell<Vec<(MessageEndpoint, OnMessage)>>,
+}
+
+/// Serves an [`IndexDbEventRepository`] to the [`SharedEventRepository`] of every tab connected
+/// to the current shared worker, so that the event store has a single writer.
+///
+/// The host also catches its [projection runners](SharedWorkerHost::with_projection_runner) up
+/// after every write, one at a time, and runs the
+/// [sync loops](SharedWorkerHost::with_sync_loop) for as long as it is alive. Tabs read the
+/// projected views with an `IndexDbViewRepository` of their own.
+///
+/// ```no_run
+/// use indexdb_es::{IndexDbEventRepository, SharedWorkerHost};
+///
+/// // In the entry point of the shared worker
+/// let host = SharedWorkerHost::serve(IndexDbEventRepository::new(None, None)).unwrap();
+/// std::mem::forget(host);
+/// ```
+pub struct SharedWorkerHost {
+    shared: Rc<Shared>,
+    on_connect: Option<(SharedWorkerGlobalScope, OnMessage)>,
+    sync_loops: Vec<AbortHandle>,
+}
+
+impl SharedWorkerHost {
+    /// Serves `repository` to the tabs connecting to the current shared worker.
+    pub fn serve(repository: IndexDbEventRepository) -> Result<Self, IndexDbAggregateError> {
+        let scope: SharedWorkerGlobalScope = js_sys::global().dyn_into().map_err(|_| {
+            IndexDbAggregateError::ConnectionError("not in a shared worker".to_string())
+        })?;
+        let mut host = Self::new(repository);
+        let shared = Rc::downgrade(&host.shared);
+        let on_connect = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let (Some(shared), Ok(port)) = (shared.upgrade(), event.ports().get(0).dyn_into())
+            else {
+                return;
+            };
+            accept(&shared, port);
+        });
+        let _ =
+            scope.add_event_listener_with_callback("connect", on_connect.as_ref().unchecked_ref());
+        host.on_connect = Some((scope, on_connect));
+        Ok(host)
+    }
+
+    /// Creates a host serving `repository` on the ports passed to
+    /// [`SharedWorkerHost::connect`] only.
+    pub fn new(repository: IndexDbEventRepository) -> Self {
+        Self {
+            shared: Rc::new(Shared {
+                repository: Rc::new(repository),
+                projections: RefCell::new(Vec::new()),
+                catching_up: Cell::new(false),
+                behind: Cell::new(false),
+                ports: RefCell::new(Vec::new()),
+            }),
+            on_connect: None,
+            sync_loops: Vec::new(),
+        }
+    }
+
+    /// Catches `runner` up now and after every write. Failures are logged to the console and
+    /// retried after the next write, the checkpoint of the runner staying where it was.
+    pub fn with_projection_runner<V, A>(self, runner: ProjectionRunner<V, A>) -> Self
+    where
+        V: View<A> + 'static,
+        A: Aggregate + 'static,
+    {
+        let runner = Rc::new(runner);
+        self.shared.projections.borrow_mut().push(Rc::new(move || {
+            let runner = runner.clone();
+            async move { runner.catch_up().await }.boxed_local()
+        }));
+        catch_up(&self.shared);
+        self
+    }
+
+    /// Runs the loop returned by `sync_loop`, given the repository, until the host is dropped.
+    pub fn with_sync_loop<F, Fut>(mut self, sync_loop: F) -> Self
+    where
+        F: FnOnce(Rc<IndexDbEventRepository>) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let (handle, registration) = AbortHandle::new_pair();
+        let sync_loop = Abortable::new(sync_loop(self.shared.repository.clone()), registration);
+        spawn_local(sync_loop.map(|_| ()));
+        self.sync_loops.push(handle);
+        self
+    }
+
+    /// Serves the repository on `port`, one end of a `MessageChannel` whose other end is given
+    /// to a [`WorkerEventRepository`].
+    pub fn connect(&self, port: MessagePort) {
+        accept(&self.shared, port);
+    }
+}
+
+impl Drop for SharedWorkerHost {
+    fn drop(&mut self) {
+        if let Some((scope, on_connect)) = &self.on_connect {
+            let _ = scope.remove_event_listener_with_callback(
+                "connect",
+                on_connect.as_ref().unchecked_ref(),
+            );
+        }
+        for (endpoint, on_message) in self.shared.ports.borrow().iter() {
+            endpoint.remove_listener(on_message);
+        }
+        for sync_loop in &self.sync_loops {
+            sync_loop.abort();
+        }
+    }
+}
+
+/// Serves the repository on the port of a new tab. Ports are kept until the host is dropped,
+/// as nothing tells when a tab closes.
+fn accept(shared: &Rc<Shared>, port: MessagePort) {
+    let endpoint = MessageEndpoint::from(port);
+    let weak: Weak<Shared> = Rc::downgrade(shared);
+    let on_write = Rc::new(move || {
+        if let Some(shared) = weak.upgrade() {
+            catch_up(&shared);
+        }
+    });
+    let on_message = listen(shared.repository.clone(), &endpoint, on_write);
+    endpoint.add_listener(&on_message);
+    shared.ports.borrow_mut().push((endpoint, on_message));
+}
+
+/// Catches every projection up, unless a catch-up is already running, in which case it runs
+/// again once done.
+fn catch_up(shared: &Rc<Shared>) {
+    if shared.catching_up.replace(true) {
+        shared.behind.set(true);
+        return;
+    }
+    let shared = shared.clone();
+    spawn_local(async move {
+        loop {
+            shared.behind.set(false);
+            let projections = shared.projections.borrow().clone();
+            for projection in projections {
+                if let Err(err) = projection().await {
+                    web_sys::console::error_1(&JsValue::from_str(&format!(
+                        "projection catch-up failed: {err}"
+                    )));
+                }
+            }
+            if !shared.behind.get() {
+                break;
+            }
+        }
+        shared.catching_up.set(false);
+    });
+}
+
+/// The event repository of a tab: the repository of the [`SharedWorkerHost`] when the browser
+/// supports shared workers, or direct access to the database otherwise.
+pub enum SharedEventRepository {
+    Shared(WorkerEventRepository),
+    Direct(Box<IndexDbEventRepository>),
+}
+
+impl SharedEventRepository {
+    /// Connects to the shared worker running `script_url`, or falls back to the repository
+    /// created by `fallback` if shared workers are not supported.
+    ///
+    /// ```no_run
+    /// use indexdb_es::{IndexDbEventRepository, SharedEventRepository};
+    ///
+    /// let repository = SharedEventRepository::connect("./shared-worker.js", || {
+    ///     IndexDbEventRepository::new(None, None)
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn connect<F>(script_url: &str, fallback: F) -> Result<Self, IndexDbAggregateError>
+    where
+        F: FnOnce() -> IndexDbEventRepository,
+    {
+        let supported = js_sys::Reflect::has(&js_sys::global(), &JsValue::from_str("SharedWorker"))
+            .unwrap_or(false);
+        if !supported {
+            return Ok(SharedEventRepository::Direct(Box::new(fallback())));
+        }
+        let worker = SharedWorker::new(script_url)
+            .map_err(|err| IndexDbAggregateError::ConnectionError(format!("{err:?}")))?;
+        Ok(SharedEventRepository::Shared(WorkerEventRepository::new(
+            worker.port(),
+        )))
+    }
+
+    /// Whether the calls go through the shared worker.
+    pub fn is_shared(&self) -> bool {
+        matches!(self, SharedEventRepository::Shared(_))
+    }
+}
+
+#[async_trait]
+impl PersistedEventRepository for SharedEventRepository {
+    async fn get_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => {
+                repository.get_events::<A>(aggregate_id).await
+            }
+            SharedEventRepository::Direct(repository) => {
+                repository.get_events::<A>(aggregate_id).await
+            }
+        }
+    }
+
+    async fn get_last_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        last_sequence: usize,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => {
+                repository
+                    .get_last_events::<A>(aggregate_id, last_sequence)
+                    .await
+            }
+            SharedEventRepository::Direct(repository) => {
+                repository
+                    .get_last_events::<A>(aggregate_id, last_sequence)
+                    .await
+            }
+        }
+    }
+
+    async fn get_snapshot<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Option<SerializedSnapshot>, PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => {
+                repository.get_snapshot::<A>(aggregate_id).await
+            }
+            SharedEventRepository::Direct(repository) => {
+                repository.get_snapshot::<A>(aggregate_id).await
+            }
+        }
+    }
+
+    async fn persist<A: Aggregate>(
+        &self,
+        events: &[SerializedEvent],
+        snapshot_update: Option<(String, Value, usize)>,
+    ) -> Result<(), PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => {
+                repository.persist::<A>(events, snapshot_update).await
+            }
+            SharedEventRepository::Direct(repository) => {
+                repository.persist::<A>(events, snapshot_update).await
+            }
+        }
+    }
+
+    async fn stream_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<ReplayStream, PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => {
+                repository.stream_events::<A>(aggregate_id).await
+            }
+            SharedEventRepository::Direct(repository) => {
+                repository.stream_events::<A>(aggregate_id).await
+            }
+        }
+    }
+
+    async fn stream_all_events<A: Aggregate>(&self) -> Result<ReplayStream, PersistenceError> {
+        match self {
+            SharedEventRepository::Shared(repository) => repository.stream_all_events::<A>().await,
+            SharedEventRepository::Direct(repository) => repository.stream_all_events::<A>().await,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::catalog::update_catalog;
+use crate::event_repository::{
+    add_events, check_tombstones, CATALOG_STORE, POSITION_STORE, SNAPSHOT_STORE, TOMBSTONE_STORE,
+};
+use crate::js_event::JsSnapshot;
+use crate::projection::project;
+use crate::storage::{Database, Key, ObjectStore, Transaction, TransactionMode};
+use crate::truncate::truncate_events;
+use crate::{IndexDbAggregateError, IndexDbEventRepository};
+use cqrs_es::persist::{SerializedEvent, SerializedSnapshot};
+use serde_json::Value;
+
+impl IndexDbEventRepository {
+    pub(crate) async fn select_snapshot(
+        &self,
+        aggregate_type: &str,
+        aggregate_id: &str,
+    ) -> Result<Option<SerializedSnapshot>, IndexDbAggregateError> {
+        let key = (aggregate_type.to_string(), aggregate_id.to_string());
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[SNAPSHOT_STORE], TransactionMode::ReadOnly)?;
+            let store = transaction.object_store(SNAPSHOT_STORE)?;
+            match store.get(&Key::aggregate(&key.0, &key.1)).await? {
+                Some(value) => {
+                    let snapshot = serde_json::from_value::<JsSnapshot>(value)?;
+                    Ok(Some(snapshot.into()))
+                }
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    /// Commits the events along with the updated snapshot of the aggregate.
+    ///
+    /// The snapshot covers the events up to the sequence given by [`snapshot_sequence`], the
+    /// others being replayed over it on load.
+    ///
+    /// A snapshot is only replaced by its direct successor, any other `current_snapshot` means
+    /// another writer got there first and results in an `OptimisticLock` error.
+    pub(crate) async fn insert_with_snapshot(
+        &self,
+        aggregate_type: &str,
+        events: &[SerializedEvent],
+        aggregate_id: String,
+        aggregate: Value,
+        current_snapshot: usize,
+    ) -> Result<(), IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+        let truncation = self.snapshot_truncation;
+        let aggregate_type = aggregate_type.to_string();
+        let events = events.to_vec();
+        let last_sequence = snapshot_sequence(&events, self.snapshot_size);
+        let now = self.clock.now();
+        let projections = self.projections.clone();
+        let mut store_names = vec![
+            store_name.clone(),
+            POSITION_STORE.to_string(),
+            CATALOG_STORE.to_string(),
+            SNAPSHOT_STORE.to_string(),
+            TOMBSTONE_STORE.to_string(),
+        ];
+        store_names.extend(self.projection_stores());
+        self.ensure_projections_caught_up().await?;
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&store_names, TransactionMode::ReadWrite)?;
+            check_tombstones(
+                &transaction,
+                &[(aggregate_type.clone(), aggregate_id.clone())],
+            )
+            .await?;
+
+            let first = add_events(&transaction, &store_name, &events, now).await?;
+            let catalog = transaction.object_store(CATALOG_STORE)?;
+            update_catalog(&catalog, &events, 0, now).await?;
+            project(&transaction, &projections, &events, first).await?;
+
+            let snapshots = transaction.object_store(SNAPSHOT_STORE)?;
+            let previous = match snapshots
+                .get(&Key::aggregate(&aggregate_type, &aggregate_id))
+                .await?
+            {
+                Some(value) => Some(serde_json::from_value::<JsSnapshot>(value)?),
+                None => None,
+            };
+            let previous_snapshot = previous.map_or(0, |s| s.current_snapshot);
+            if previous_snapshot + 1 != current_snapshot {
+                transaction.abort().await?;
+                return Err(IndexDbAggregateError::OptimisticLock);
+            }
+
+            let snapshot = JsSnapshot {
+                aggregate_type,
+                aggregate_id,
+                last_sequence,
+                current_snapshot,
+                payload: aggregate,
+            };
+            snapshots.put(&serde_json::to_value(&snapshot)?).await?;
+
+            if let Some(keep_last) = truncation {
+                let before = (last_sequence + 1).saturating_sub(keep_last);
+                truncate_events(
+                    &transaction.object_store(&store_name)?,
+                    &snapshot.aggregate_type,
+                    &snapshot.aggregate_id,
+                    before,
+                )
+                .await?;
+            }
+
+            transaction.commit().await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// The sequence of the last of `events` covered by the snapshot persisted along with them.
+///
+/// With a snapshot store of size `snapshot_size`, cqrs-es applies the events up to the last
+/// multiple of the size to the snapshot, and leaves those after it to be replayed. Otherwise the
+/// snapshot covers every event.
+fn snapshot_sequence(events: &[SerializedEvent], snapshot_size: Option<usize>) -> usize {
+    let last_sequence = events.last().map_or(0, |e| e.sequence);
+    match snapshot_size {
+        Some(size) => {
+            let first_sequence = events.first().map_or(0, |e| e.sequence);
+            (last_sequence - last_sequence % size).max(first_sequence)
+        }
+        None => last_sequence,
+    }
+}
@@ -0,0 +1,167 @@
+//! A database of one backend or another, picked when it is opened.
+
+use super::{Database, Index, Key, KeyPath, KeyRange, ObjectStore, Transaction, TransactionMode};
+use crate::IndexDbAggregateError;
+use async_trait::async_trait;
+use serde_json::Value;
+
+/// Either of two backends, or one of their transactions, stores or indexes.
+pub(crate) enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+impl<L: Database, R: Database> Database for Either<L, R> {
+    type Transaction = Either<L::Transaction, R::Transaction>;
+
+    fn transaction<S: AsRef<str>>(
+        &self,
+        store_names: &[S],
+        mode: TransactionMode,
+    ) -> Result<Self::Transaction, IndexDbAggregateError> {
+        match self {
+            Either::Left(db) => Ok(Either::Left(db.transaction(store_names, mode)?)),
+            Either::Right(db) => Ok(Either::Right(db.transaction(store_names, mode)?)),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<L: Transaction, R: Transaction> Transaction for Either<L, R> {
+    type ObjectStore = Either<L::ObjectStore, R::ObjectStore>;
+
+    fn object_store(&self, name: &str) -> Result<Self::ObjectStore, IndexDbAggregateError> {
+        match self {
+            Either::Left(transaction) => Ok(Either::Left(transaction.object_store(name)?)),
+            Either::Right(transaction) => Ok(Either::Right(transaction.object_store(name)?)),
+        }
+    }
+
+    async fn commit(self) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(transaction) => transaction.commit().await,
+            Either::Right(transaction) => transaction.commit().await,
+        }
+    }
+
+    async fn abort(self) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(transaction) => transaction.abort().await,
+            Either::Right(transaction) => transaction.abort().await,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<L: ObjectStore, R: ObjectStore> ObjectStore for Either<L, R> {
+    type Index = Either<L::Index, R::Index>;
+
+    fn key_path(&self) -> Result<Option<KeyPath>, IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.key_path(),
+            Either::Right(store) => store.key_path(),
+        }
+    }
+
+    fn index(&self, name: &str) -> Result<Self::Index, IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => Ok(Either::Left(store.index(name)?)),
+            Either::Right(store) => Ok(Either::Right(store.index(name)?)),
+        }
+    }
+
+    async fn get(&self, key: &Key) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.get(key).await,
+            Either::Right(store) => store.get(key).await,
+        }
+    }
+
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.get_all(range, limit).await,
+            Either::Right(store) => store.get_all(range, limit).await,
+        }
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.count(range).await,
+            Either::Right(store) => store.count(range).await,
+        }
+    }
+
+    async fn add(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.add(record).await,
+            Either::Right(store) => store.add(record).await,
+        }
+    }
+
+    async fn add_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.add_all(records).await,
+            Either::Right(store) => store.add_all(records).await,
+        }
+    }
+
+    async fn put(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.put(record).await,
+            Either::Right(store) => store.put(record).await,
+        }
+    }
+
+    async fn put_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.put_all(records).await,
+            Either::Right(store) => store.put_all(records).await,
+        }
+    }
+
+    async fn delete(&self, range: &KeyRange) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.delete(range).await,
+            Either::Right(store) => store.delete(range).await,
+        }
+    }
+
+    async fn clear(&self) -> Result<(), IndexDbAggregateError> {
+        match self {
+            Either::Left(store) => store.clear().await,
+            Either::Right(store) => store.clear().await,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<L: Index, R: Index> Index for Either<L, R> {
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        match self {
+            Either::Left(index) => index.get_all(range, limit).await,
+            Either::Right(index) => index.get_all(range, limit).await,
+        }
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        match self {
+            Either::Left(index) => index.count(range).await,
+            Either::Right(index) => index.count(range).await,
+        }
+    }
+
+    async fn last(&self) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self {
+            Either::Left(index) => index.last().await,
+            Either::Right(index) => index.last().await,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The byte encoding of keys shared by the backends that keep records in sorted maps of their
+//! own, ordered like IndexedDB orders keys.
+//!
+//! An index entry is keyed by the encoded `[value, key]` of the value it indexes and the key
+//! of its record, so that entries sort in index order then key order.
+
+use super::{Key, KeyRange};
+use std::ops::Bound;
+
+/// The bounds of a range of encoded keys.
+pub(crate) type Bounds = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+const END: u8 = 0x00;
+const NUMBER: u8 = 0x10;
+const STRING: u8 = 0x30;
+const ARRAY: u8 = 0x50;
+/// Sorts after the encoding of any key that shares the same prefix.
+const AFTER: u8 = 0xFF;
+
+/// Encodes a key into bytes that sort in the IndexedDB key order.
+pub(crate) fn encode(key: &Key) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_into(key, &mut bytes);
+    bytes
+}
+
+fn encode_into(key: &Key, bytes: &mut Vec<u8>) {
+    match key {
+        Key::Number(number) => {
+            bytes.push(NUMBER);
+            // -0 and 0 are the same key
+            let bits = if *number == 0.0 { 0 } else { number.to_bits() };
+            // Flip the sign bit of positive numbers and every bit of negative ones so that the
+            // bytes sort like the numbers
+            let bits = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits | 1 << 63
+            };
+            bytes.extend_from_slice(&bits.to_be_bytes());
+        }
+        Key::String(string) => {
+            bytes.push(STRING);
+            // Zero bytes are escaped so that the terminator sorts before any content
+            for unit in string.encode_utf16() {
+                for byte in unit.to_be_bytes() {
+                    bytes.push(byte);
+                    if byte == END {
+                        bytes.push(AFTER);
+                    }
+                }
+            }
+            bytes.push(END);
+        }
+        Key::Array(keys) => {
+            bytes.push(ARRAY);
+            for key in keys {
+                encode_into(key, bytes);
+            }
+            bytes.push(END);
+        }
+    }
+}
+
+/// The encoded `[value, key]` of an index entry, given the encoded key of its record.
+pub(crate) fn index_entry(value: &Key, key: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![ARRAY];
+    encode_into(value, &mut bytes);
+    bytes.extend_from_slice(key);
+    bytes.push(END);
+    bytes
+}
+
+/// The bounds of the encoded keys of a store within `range`.
+pub(crate) fn store_bounds(range: &KeyRange) -> Bounds {
+    (
+        range.lower.as_ref().map(encode),
+        range.upper.as_ref().map(encode),
+    )
+}
+
+/// The bounds of the encoded entries of an index whose value is within `range`.
+///
+/// Entries are keyed by `[value, key]`: the encoded prefix `[value` sorts before every entry
+/// of the value, and that prefix followed by [`AFTER`] after all of them.
+pub(crate) fn index_bounds(range: &KeyRange) -> Bounds {
+    let prefix = |key: &Key| {
+        let mut bytes = vec![ARRAY];
+        encode_into(key, &mut bytes);
+        bytes
+    };
+    let after = |key: &Key| {
+        let mut bytes = prefix(key);
+        bytes.push(AFTER);
+        bytes
+    };
+    let lower = match &range.lower {
+        Bound::Included(key) => Bound::Included(prefix(key)),
+        Bound::Excluded(key) => Bound::Excluded(after(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let upper = match &range.upper {
+        Bound::Included(key) => Bound::Excluded(after(key)),
+        Bound::Excluded(key) => Bound::Excluded(prefix(key)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (lower, upper)
+}
+
+/// Whether `bounds` select no key at all, which sorted maps refuse to range over.
+pub(crate) fn is_empty(bounds: &Bounds) -> bool {
+    match bounds {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (
+            Bound::Included(lower) | Bound::Excluded(lower),
+            Bound::Included(upper) | Bound::Excluded(upper),
+        ) => lower >= upper,
+        _ => false,
+    }
+}
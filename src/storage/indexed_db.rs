@@ -0,0 +1,444 @@
+//! The backend used in the browser, a thin layer over the IndexedDB bindings of `idb`.
+
+use super::{
+    Database, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema, Transaction, TransactionMode,
+};
+use crate::event_repository::{backfill_positions, base_schema, is_event_store, DB_VERSION};
+use crate::IndexDbAggregateError;
+use async_trait::async_trait;
+use futures::channel::oneshot::channel;
+use futures::future::{join_all, select, Either};
+use gloo_utils::format::JsValueSerdeExt;
+use idb::{CursorDirection, Factory, ObjectStoreParams, Query};
+use js_sys::Array;
+use serde_json::Value;
+use std::future::{Future, IntoFuture};
+use std::ops::Bound;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+
+/// Opens the database `db_name` with the stores of `schema`, then runs `f` against it on the
+/// local executor and hands its result back.
+///
+/// `idb` futures are not `Send`, so every database access is spawned this way to keep the
+/// futures returned by the repositories usable from `PersistedEventRepository`.
+pub(crate) fn run_in<T, F, Fut>(
+    db_name: String,
+    schema: Vec<StoreSchema>,
+    f: F,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+where
+    T: 'static,
+    F: FnOnce(IdbDatabase) -> Fut + 'static,
+    Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+{
+    let (sender, receiver) = channel::<Result<T, IndexDbAggregateError>>();
+
+    spawn_local(async move {
+        let result = match connect(&db_name, &schema).await {
+            Ok(db) => f(db).await,
+            Err(err) => Err(err),
+        };
+        let _ = sender.send(result);
+    });
+
+    async move {
+        match receiver.await {
+            Ok(result) => result,
+            Err(_) => Err(IndexDbAggregateError::UnknownError(
+                "database task dropped before completing".to_string(),
+            )),
+        }
+    }
+}
+
+/// An open IndexedDB database, closed on drop once its transactions complete.
+pub(crate) struct IdbDatabase(idb::Database);
+
+impl Drop for IdbDatabase {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl Database for IdbDatabase {
+    type Transaction = IdbTransaction;
+
+    fn transaction<S: AsRef<str>>(
+        &self,
+        store_names: &[S],
+        mode: TransactionMode,
+    ) -> Result<IdbTransaction, IndexDbAggregateError> {
+        let mode = match mode {
+            TransactionMode::ReadOnly => idb::TransactionMode::ReadOnly,
+            TransactionMode::ReadWrite => idb::TransactionMode::ReadWrite,
+        };
+        Ok(IdbTransaction(Some(self.0.transaction(store_names, mode)?)))
+    }
+}
+
+/// An IndexedDB transaction, taken out once committed or aborted.
+pub(crate) struct IdbTransaction(Option<idb::Transaction>);
+
+impl IdbTransaction {
+    fn take(&mut self) -> idb::Transaction {
+        self.0.take().expect("transaction already finished")
+    }
+}
+
+impl Drop for IdbTransaction {
+    fn drop(&mut self) {
+        // IndexedDB commits a transaction once it has no request left, so one dropped on an
+        // error must be aborted to be rolled back. It may have been aborted already by a failed
+        // request, in which case aborting it again throws.
+        if let Some(transaction) = self.0.take() {
+            let _ = JsValue::from(transaction)
+                .unchecked_into::<web_sys::IdbTransaction>()
+                .abort();
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Transaction for IdbTransaction {
+    type ObjectStore = IdbObjectStore;
+
+    fn object_store(&self, name: &str) -> Result<IdbObjectStore, IndexDbAggregateError> {
+        let transaction = self.0.as_ref().expect("transaction already finished");
+        Ok(IdbObjectStore(transaction.object_store(name)?))
+    }
+
+    async fn commit(mut self) -> Result<(), IndexDbAggregateError> {
+        Ok(self.take().commit().await?)
+    }
+
+    async fn abort(mut self) -> Result<(), IndexDbAggregateError> {
+        Ok(self.take().abort().await?)
+    }
+}
+
+pub(crate) struct IdbObjectStore(idb::ObjectStore);
+
+#[async_trait(?Send)]
+impl ObjectStore for IdbObjectStore {
+    type Index = IdbIndex;
+
+    fn key_path(&self) -> Result<Option<KeyPath>, IndexDbAggregateError> {
+        Ok(self.0.key_path()?.map(|key_path| match key_path {
+            idb::KeyPath::Single(path) => KeyPath::Single(path),
+            idb::KeyPath::Array(paths) => KeyPath::Array(paths),
+        }))
+    }
+
+    fn index(&self, name: &str) -> Result<IdbIndex, IndexDbAggregateError> {
+        Ok(IdbIndex(self.0.index(name)?))
+    }
+
+    async fn get(&self, key: &Key) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self.0.get(Query::Key(to_js(key))).await? {
+            Some(value) => Ok(Some(value.into_serde()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let values = self.0.get_all(query(range)?, limit).await?;
+        from_js(values)
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        Ok(self.0.count(query(range)?).await?)
+    }
+
+    async fn add(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        // IndexedDB aborts the transaction when the key is already taken
+        match self.0.add(&JsValue::from_serde(record)?, None).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(add_error(err)),
+        }
+    }
+
+    async fn add_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        let values = records
+            .iter()
+            .map(JsValue::from_serde)
+            .collect::<Result<Vec<_>, _>>()?;
+        // Every request is issued before any is waited for. IndexedDB runs them in order and
+        // aborts the transaction at the first key already taken, failing the others with it
+        let results = join_all(values.iter().map(|value| self.0.add(value, None))).await;
+        match results.into_iter().find_map(Result::err) {
+            None => Ok(()),
+            Some(err) => Err(add_error(err)),
+        }
+    }
+
+    async fn put(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        self.0.put(&JsValue::from_serde(record)?, None).await?;
+        Ok(())
+    }
+
+    async fn put_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        let values = records
+            .iter()
+            .map(JsValue::from_serde)
+            .collect::<Result<Vec<_>, _>>()?;
+        for result in join_all(values.iter().map(|value| self.0.put(value, None))).await {
+            result?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, range: &KeyRange) -> Result<(), IndexDbAggregateError> {
+        match query(range)? {
+            Some(query) => Ok(self.0.delete(query).await?),
+            None => Ok(self.0.clear().await?),
+        }
+    }
+
+    async fn clear(&self) -> Result<(), IndexDbAggregateError> {
+        Ok(self.0.clear().await?)
+    }
+}
+
+pub(crate) struct IdbIndex(idb::Index);
+
+#[async_trait(?Send)]
+impl Index for IdbIndex {
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let values = self.0.get_all(query(range)?, limit).await?;
+        from_js(values)
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        Ok(self.0.count(query(range)?).await?)
+    }
+
+    async fn last(&self) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self
+            .0
+            .open_cursor(None, Some(CursorDirection::Prev))
+            .await?
+        {
+            Some(cursor) => Ok(Some(cursor.value()?.into_serde()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn to_js(key: &Key) -> JsValue {
+    match key {
+        Key::Number(number) => JsValue::from_f64(*number),
+        Key::String(string) => JsValue::from_str(string),
+        Key::Array(keys) => keys.iter().map(to_js).collect::<Array>().into(),
+    }
+}
+
+fn from_js(values: Vec<JsValue>) -> Result<Vec<Value>, IndexDbAggregateError> {
+    values
+        .into_iter()
+        .map(|value| Ok(value.into_serde()?))
+        .collect()
+}
+
+/// The IndexedDB query matching `range`, `None` for every key.
+fn query(range: &KeyRange) -> Result<Option<Query>, IndexDbAggregateError> {
+    let bound = |bound: &Bound<Key>| match bound {
+        Bound::Included(key) => Some((to_js(key), false)),
+        Bound::Excluded(key) => Some((to_js(key), true)),
+        Bound::Unbounded => None,
+    };
+    let range = match (bound(&range.lower), bound(&range.upper)) {
+        (Some((lower, lower_open)), Some((upper, upper_open))) => {
+            idb::KeyRange::bound(&lower, &upper, Some(lower_open), Some(upper_open))?
+        }
+        (Some((lower, open)), None) => idb::KeyRange::lower_bound(&lower, Some(open))?,
+        (None, Some((upper, open))) => idb::KeyRange::upper_bound(&upper, Some(open))?,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Query::KeyRange(range)))
+}
+
+fn to_idb(key_path: &KeyPath) -> idb::KeyPath {
+    match key_path {
+        KeyPath::Single(path) => idb::KeyPath::new_single(path),
+        KeyPath::Array(paths) => idb::KeyPath::new_array(paths.iter().map(String::as_str)),
+    }
+}
+
+/// Opens the database, upgrading it to [`DB_VERSION`] and creating the stores and indexes of
+/// `schema` if needed.
+///
+/// Adding a store or an index bumps the database version past [`DB_VERSION`], so the database
+/// is first opened at its current version to find out whether an upgrade is needed. Every
+/// connection closes as soon as another tab asks for an upgrade, which then only waits for the
+/// running transactions; a connection kept open by other code fails the upgrade with a
+/// `ConnectionError` instead of leaving it pending.
+async fn connect(name: &str, schema: &[StoreSchema]) -> Result<IdbDatabase, IndexDbAggregateError> {
+    // Better error messages in debug mode
+    console_error_panic_hook::set_once();
+
+    // Get a factory instance from the global scope, be it a window or a worker of any kind
+    let factory = Factory::new().map_err(|err| {
+        IndexDbAggregateError::ConnectionError(format!("IndexedDB is not available: {err}"))
+    })?;
+    let schema: Vec<StoreSchema> = base_schema().into_iter().chain(schema.to_vec()).collect();
+
+    let database = open(&factory, name, None, &schema).await?;
+    let version = database.version()?;
+    let upgrade = if version < DB_VERSION {
+        DB_VERSION
+    } else if has_schema(&database, &schema)? {
+        return Ok(IdbDatabase(database));
+    } else {
+        version + 1
+    };
+    database.close();
+
+    Ok(IdbDatabase(
+        open(&factory, name, Some(upgrade), &schema).await?,
+    ))
+}
+
+/// Maps the failure of an add request: a key already taken fails with `OptimisticLock`, any
+/// other failure, such as a full quota, is reported as it is.
+fn add_error(err: idb::Error) -> IndexDbAggregateError {
+    match &err {
+        idb::Error::DomException(exception) if exception.name() == "ConstraintError" => {
+            IndexDbAggregateError::OptimisticLock
+        }
+        _ => err.into(),
+    }
+}
+
+/// Whether the database already has every store and index of `schema`.
+fn has_schema(
+    database: &idb::Database,
+    schema: &[StoreSchema],
+) -> Result<bool, IndexDbAggregateError> {
+    let store_names = database.store_names();
+    if !schema.iter().all(|store| store_names.contains(&store.name)) {
+        return Ok(false);
+    }
+    if schema.is_empty() {
+        return Ok(true);
+    }
+
+    let names: Vec<&str> = schema.iter().map(|store| store.name.as_str()).collect();
+    let transaction = database.transaction(&names, idb::TransactionMode::ReadOnly)?;
+    for store in schema {
+        let index_names = transaction.object_store(&store.name)?.index_names();
+        if !store
+            .indexes
+            .iter()
+            .all(|(index_name, _)| index_names.contains(index_name))
+        {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+async fn open(
+    factory: &Factory,
+    name: &str,
+    version: Option<u32>,
+    schema: &[StoreSchema],
+) -> Result<idb::Database, IndexDbAggregateError> {
+    // Create an open request for the database
+    let mut open_request = factory.open(name, version)?;
+    let schema = schema.to_vec();
+
+    // Hear about the connections left open that block an upgrade
+    let (blocked, on_blocked) = channel::<()>();
+    open_request.on_blocked(move |_| {
+        let _ = blocked.send(());
+    });
+
+    // Add an upgrade handler for database
+    open_request.on_upgrade_needed(move |event| {
+        // Get database instance from event
+        let database = event.database().unwrap();
+        let transaction = event.transaction().unwrap().unwrap();
+
+        for store in &schema {
+            let object_store = ensure_store(&database, &transaction, &store.name, &store.key_path);
+            for (index_name, key_path) in &store.indexes {
+                ensure_index(&object_store, index_name, key_path);
+            }
+        }
+
+        // Number the events written before the stores tracked global positions, within the
+        // upgrade transaction, which fails the upgrade by aborting if anything goes wrong
+        let event_stores: Vec<String> = schema
+            .iter()
+            .filter(|store| is_event_store(store))
+            .map(|store| store.name.clone())
+            .collect();
+        let mut transaction = IdbTransaction(Some(transaction));
+        spawn_local(async move {
+            for store_name in &event_stores {
+                if backfill_positions(&transaction, store_name).await.is_err() {
+                    return;
+                }
+            }
+            // Left to complete along with the upgrade
+            transaction.0.take();
+        });
+    });
+
+    let mut database = match select(open_request.into_future(), on_blocked).await {
+        Either::Left((database, _)) => database?,
+        Either::Right((_, request)) => {
+            // The upgrade goes on once the other connections close, only to be closed in turn
+            spawn_local(async move {
+                if let Ok(database) = request.await {
+                    database.close();
+                }
+            });
+            return Err(IndexDbAggregateError::ConnectionError(format!(
+                "the upgrade of `{name}` is blocked by another connection left open"
+            )));
+        }
+    };
+    // Let other tabs upgrade the database
+    database.on_version_change(|database| database.close());
+    Ok(database)
+}
+
+/// Returns the named object store, creating it first if the database does not have it yet.
+fn ensure_store(
+    database: &idb::Database,
+    transaction: &idb::Transaction,
+    store_name: &str,
+    key_path: &KeyPath,
+) -> idb::ObjectStore {
+    if database.store_names().iter().any(|name| name == store_name) {
+        transaction.object_store(store_name).unwrap()
+    } else {
+        // Prepare object store params
+        let mut store_params = ObjectStoreParams::new();
+        store_params.key_path(Some(to_idb(key_path)));
+
+        // Create object store
+        database
+            .create_object_store(store_name, store_params)
+            .unwrap()
+    }
+}
+
+/// Creates an index on the object store unless it already exists.
+fn ensure_index(store: &idb::ObjectStore, index_name: &str, key_path: &KeyPath) {
+    if !store.index_names().iter().any(|name| name == index_name) {
+        store
+            .create_index(index_name, to_idb(key_path), None)
+            .unwrap();
+    }
+}
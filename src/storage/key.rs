@@ -0,0 +1,181 @@
+use crate::index_range::lookup;
+use crate::{IndexDbAggregateError, IndexRange};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ops::Bound;
+
+/// An IndexedDB key. Keys sort numbers before strings before arrays, strings by UTF-16 code
+/// unit and arrays element by element.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Key {
+    Number(f64),
+    String(String),
+    Array(Vec<Key>),
+}
+
+impl Key {
+    /// Converts a JSON value into a key, `None` if it is not a valid key such as a boolean or
+    /// an object.
+    pub(crate) fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Number(number) => number.as_f64().map(Key::Number),
+            Value::String(string) => Some(Key::String(string.clone())),
+            Value::Array(values) => values
+                .iter()
+                .map(Key::from_value)
+                .collect::<Option<Vec<_>>>()
+                .map(Key::Array),
+            _ => None,
+        }
+    }
+
+    /// The `[aggregate_type, aggregate_id]` key identifying an aggregate instance.
+    pub(crate) fn aggregate(aggregate_type: &str, aggregate_id: &str) -> Key {
+        Key::Array(vec![aggregate_type.into(), aggregate_id.into()])
+    }
+
+    /// The `[aggregate_type, aggregate_id, sequence]` primary key of an event.
+    pub(crate) fn event(aggregate_type: &str, aggregate_id: &str, sequence: f64) -> Key {
+        Key::Array(vec![
+            aggregate_type.into(),
+            aggregate_id.into(),
+            sequence.into(),
+        ])
+    }
+}
+
+impl From<&str> for Key {
+    fn from(value: &str) -> Self {
+        Key::String(value.to_string())
+    }
+}
+
+impl From<f64> for Key {
+    fn from(value: f64) -> Self {
+        Key::Number(value)
+    }
+}
+
+/// Where the key of a record, or the value it is indexed by, is found in the record: a single
+/// dot separated path, or several of them making up an array key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum KeyPath {
+    Single(String),
+    Array(Vec<String>),
+}
+
+impl KeyPath {
+    pub(crate) fn single(path: &str) -> Self {
+        KeyPath::Single(path.to_string())
+    }
+
+    pub(crate) fn array(paths: &[&str]) -> Self {
+        KeyPath::Array(paths.iter().map(|path| path.to_string()).collect())
+    }
+
+    /// Extracts the key of `record`, `None` if part of it is missing or is not a valid key.
+    pub(crate) fn extract(&self, record: &Value) -> Option<Key> {
+        match self {
+            KeyPath::Single(path) => Key::from_value(&lookup(record, path)?),
+            KeyPath::Array(paths) => paths
+                .iter()
+                .map(|path| Key::from_value(&lookup(record, path)?))
+                .collect::<Option<Vec<_>>>()
+                .map(Key::Array),
+        }
+    }
+}
+
+/// A range of keys, like an IndexedDB `KeyRange`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct KeyRange {
+    pub(crate) lower: Bound<Key>,
+    pub(crate) upper: Bound<Key>,
+}
+
+impl KeyRange {
+    /// Every key.
+    pub(crate) fn all() -> Self {
+        Self {
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        }
+    }
+
+    pub(crate) fn only(key: Key) -> Self {
+        Self {
+            lower: Bound::Included(key.clone()),
+            upper: Bound::Included(key),
+        }
+    }
+
+    pub(crate) fn bound(lower: Key, upper: Key, lower_open: bool, upper_open: bool) -> Self {
+        Self {
+            lower: bound(lower, lower_open),
+            upper: bound(upper, upper_open),
+        }
+    }
+
+    pub(crate) fn lower_bound(lower: Key, open: bool) -> Self {
+        Self {
+            lower: bound(lower, open),
+            upper: Bound::Unbounded,
+        }
+    }
+
+    /// Selects the entries of an index within `range` and after the entry `after`, for an
+    /// index keyed by the `arity` indexed values followed by a tiebreaker, such as the global
+    /// position of an event or the id of a view, that orders the entries sharing the same
+    /// values.
+    pub(crate) fn index(
+        range: &IndexRange,
+        arity: usize,
+        after: Option<(&Value, Key)>,
+    ) -> Result<Self, IndexDbAggregateError> {
+        // Numbers sort before any other key and arrays after, so `-Infinity` and `[]` are below
+        // and above every tiebreaker
+        let lower = match (after, &range.lower) {
+            (Some((value, tiebreaker)), _) => Bound::Excluded(index_key(value, arity, tiebreaker)?),
+            (None, Some(lower)) => {
+                Bound::Included(index_key(lower, arity, f64::NEG_INFINITY.into())?)
+            }
+            (None, None) => Bound::Unbounded,
+        };
+        let upper = match &range.upper {
+            Some(upper) => Bound::Included(index_key(upper, arity, Key::Array(Vec::new()))?),
+            None => Bound::Unbounded,
+        };
+        Ok(Self { lower, upper })
+    }
+}
+
+fn bound(key: Key, open: bool) -> Bound<Key> {
+    if open {
+        Bound::Excluded(key)
+    } else {
+        Bound::Included(key)
+    }
+}
+
+/// Builds the key of an index entry from its indexed values and its tiebreaker.
+fn index_key(value: &Value, arity: usize, tiebreaker: Key) -> Result<Key, IndexDbAggregateError> {
+    let values = match value {
+        Value::Array(values) if arity > 1 && values.len() == arity => values.clone(),
+        _ if arity > 1 => {
+            return Err(IndexDbAggregateError::DeserializationError(format!(
+                "expected an array of {arity} values, got {value}"
+            )))
+        }
+        _ => vec![value.clone()],
+    };
+    let mut keys = values
+        .iter()
+        .map(|value| {
+            Key::from_value(value).ok_or_else(|| {
+                IndexDbAggregateError::DeserializationError(format!("{value} is not a valid key"))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    keys.push(tiebreaker);
+    Ok(Key::Array(keys))
+}
@@ -0,0 +1,1461 @@
+//! The append-only log backend, keeping every store of a database in a single segment file.
+//!
+//! Each transaction appends the records it puts and the keys it deletes to the log, followed by
+//! a commit entry holding their checksum, and nothing is ever written over. The stores live in
+//! memory as a compact offset index, mapping the encoded key of each record to where the record
+//! sits in the log, and records are read back from the log on demand. The offset index is
+//! checkpointed to a second file, so that opening the log only replays what was committed
+//! since. A transaction torn by a crash fails its checksum and is dropped on the next open.
+//!
+//! Records overwritten or deleted stay in the log until it is compacted, see [`Log::compact`].
+//! The compacted log is first written to a third file, then copied over the log, so that a
+//! compaction cut short by a crash is completed on the next open.
+//!
+//! In the browser the files live in the origin private file system, see [`super::opfs`].
+//! Elsewhere they are the plain files `{db_name}.log`, `{db_name}.idx` and `{db_name}.cmp`.
+
+use super::encoding::{encode, index_bounds, index_entry, is_empty, store_bounds, Bounds};
+use super::{
+    backfill, Database, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema, Transaction,
+    TransactionMode,
+};
+use crate::event_repository::base_schema;
+use crate::IndexDbAggregateError;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::ops::DerefMut;
+use std::rc::Rc;
+
+#[cfg(target_arch = "wasm32")]
+use super::opfs::{open_files, OpfsFile as SegmentFile};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File as SegmentFile;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// Prefix marking the names of the databases kept in an append-only log, see [`log_db_name`].
+const LOG_PREFIX: &str = ":log:";
+
+/// How many bytes may be committed to the log before the offset index is checkpointed again, at
+/// the least.
+const CHECKPOINT_INTERVAL: u64 = 1 << 20;
+
+/// The size of the chunks the compacted log is written and copied in.
+const COMPACTION_CHUNK: usize = 1 << 20;
+
+/// A record put in a store: the store name, the encoded key then the JSON record.
+const PUT: u8 = 1;
+/// A record deleted from a store: the store name then the encoded key.
+const DELETE: u8 = 2;
+/// Every record of a store deleted: the store name.
+const CLEAR: u8 = 3;
+/// A store created or given new indexes: its JSON [`StoreSchema`].
+const SCHEMA: u8 = 4;
+/// The end of a transaction: the checksum of its entries.
+const COMMIT: u8 = 5;
+
+/// The size of the header of an entry, its kind then the length of its payload.
+const HEADER: usize = 5;
+
+/// The initial state of the FNV-1a checksum of the entries of a transaction.
+const CHECKSUM: u32 = 0x811c_9dc5;
+
+/// The name under which the log `db_name` is shared by the repositories created with `opfs`.
+pub(crate) fn log_db_name(db_name: &str) -> String {
+    format!("{LOG_PREFIX}{db_name}")
+}
+
+/// The name of the log `db_name` refers to, `None` if it is not the name of a log.
+pub(crate) fn log_name(db_name: &str) -> Option<&str> {
+    db_name.strip_prefix(LOG_PREFIX)
+}
+
+/// The segment, the offset index and the compaction file of a log.
+pub(crate) type LogFiles = (SegmentFile, SegmentFile, SegmentFile);
+
+/// A file the log is read from and written to at given offsets.
+pub(crate) trait LogFile {
+    fn size(&mut self) -> Result<u64, IndexDbAggregateError>;
+
+    /// Fills `buffer` with the bytes at `offset`, failing if the file ends before.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), IndexDbAggregateError>;
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> Result<(), IndexDbAggregateError>;
+
+    fn truncate(&mut self, size: u64) -> Result<(), IndexDbAggregateError>;
+
+    /// Persists what was written so far.
+    fn flush(&mut self) -> Result<(), IndexDbAggregateError>;
+}
+
+/// An open log, shared by the transactions run against it. JS handles are not `Send`, so the
+/// browser keeps it to its thread.
+#[cfg(target_arch = "wasm32")]
+type Shared = Rc<RefCell<Log>>;
+#[cfg(not(target_arch = "wasm32"))]
+type Shared = Arc<Mutex<Log>>;
+
+#[cfg(target_arch = "wasm32")]
+fn share(log: Log) -> Shared {
+    Rc::new(RefCell::new(log))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn share(log: Log) -> Shared {
+    Arc::new(Mutex::new(log))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn lock(log: &Shared) -> impl DerefMut<Target = Log> + '_ {
+    log.borrow_mut()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn lock(log: &Shared) -> impl DerefMut<Target = Log> + '_ {
+    log.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The log of a database once opened, behind a lock held for the whole of every [`run_in`]
+/// against it so that the transactions of different runs never interleave.
+type Slot = futures::lock::Mutex<Option<Shared>>;
+
+/// Returns the slot of the log `name`.
+///
+/// A file can only be opened once at a time, so each log is opened on first use and its handle
+/// shared by every repository for the rest of the process.
+#[cfg(not(target_arch = "wasm32"))]
+fn slot(name: &str) -> Result<Arc<Slot>, IndexDbAggregateError> {
+    static LOGS: OnceLock<Mutex<HashMap<String, Arc<Slot>>>> = OnceLock::new();
+
+    let mut logs = LOGS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|err| IndexDbAggregateError::ConnectionError(err.to_string()))?;
+    Ok(logs.entry(name.to_string()).or_default().clone())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn slot(name: &str) -> Result<Rc<Slot>, IndexDbAggregateError> {
+    thread_local! {
+        static LOGS: RefCell<HashMap<String, Rc<Slot>>> = Default::default();
+    }
+
+    Ok(LOGS.with(|logs| {
+        logs.borrow_mut()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }))
+}
+
+/// Opens the log `name` with the stores of `schema`, then runs `f` against it and hands its
+/// result back.
+pub(crate) fn run_in<T, F, Fut>(
+    name: String,
+    schema: Vec<StoreSchema>,
+    f: F,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+where
+    T: 'static,
+    F: FnOnce(LogDatabase) -> Fut + 'static,
+    Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+{
+    spawn(name.clone(), async move {
+        let slot = slot(&name)?;
+        let mut opened = slot.lock().await;
+        let log = match &mut *opened {
+            Some(log) => log.clone(),
+            None => opened
+                .insert(share(Log::open(open_files(&name).await?)?))
+                .clone(),
+        };
+        f(connect(log, &schema).await?).await
+    })
+}
+
+/// Runs `task` on the local executor, like the IndexedDB backend does.
+#[cfg(target_arch = "wasm32")]
+fn spawn<T: 'static>(
+    _name: String,
+    task: impl Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+        let _ = sender.send(task.await);
+    });
+
+    async move {
+        match receiver.await {
+            Ok(result) => result,
+            Err(_) => Err(IndexDbAggregateError::UnknownError(
+                "database task dropped before completing".to_string(),
+            )),
+        }
+    }
+}
+
+/// Runs `task` to completion on the calling thread, the files being synchronous, like the redb
+/// backend does.
+///
+/// A run nested within another against the same log would wait forever for the slot held by
+/// the outer run on this very thread, so it fails right away instead. Runs from other threads
+/// wait for the slot as usual.
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn<T>(
+    name: String,
+    task: impl Future<Output = Result<T, IndexDbAggregateError>>,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>> {
+    thread_local! {
+        /// The logs a run is in progress against on this thread.
+        static RUNNING: RefCell<std::collections::HashSet<String>> = Default::default();
+    }
+
+    /// Marks the log as no longer running once the run completes or unwinds.
+    struct Running(String);
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            RUNNING.with(|running| running.borrow_mut().remove(&self.0));
+        }
+    }
+
+    let result = match RUNNING.with(|running| running.borrow_mut().insert(name.clone())) {
+        true => {
+            let _running = Running(name);
+            futures::executor::block_on(task)
+        }
+        false => Err(IndexDbAggregateError::UnknownError(format!(
+            "the log {name} is already in use by an outer run on this thread"
+        ))),
+    };
+    std::future::ready(result)
+}
+
+/// Opens the segment, the offset index and the compaction file of the log `name`, creating
+/// them if needed.
+#[cfg(not(target_arch = "wasm32"))]
+async fn open_files(name: &str) -> Result<LogFiles, IndexDbAggregateError> {
+    let open = |path: String| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    };
+    Ok((
+        open(format!("{name}.log"))?,
+        open(format!("{name}.idx"))?,
+        open(format!("{name}.cmp"))?,
+    ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LogFile for SegmentFile {
+    fn size(&mut self) -> Result<u64, IndexDbAggregateError> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), IndexDbAggregateError> {
+        use std::io::{Read, Seek, SeekFrom};
+        self.seek(SeekFrom::Start(offset))?;
+        Ok(self.read_exact(buffer)?)
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> Result<(), IndexDbAggregateError> {
+        use std::io::{Seek, SeekFrom, Write};
+        self.seek(SeekFrom::Start(offset))?;
+        Ok(self.write_all(bytes)?)
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), IndexDbAggregateError> {
+        Ok(self.set_len(size)?)
+    }
+
+    fn flush(&mut self) -> Result<(), IndexDbAggregateError> {
+        Ok(self.sync_data()?)
+    }
+}
+
+/// Creates the stores and indexes of `schema` that the log does not have yet, in a transaction
+/// of their own. Indexes are filled with the records already in their store, and the events of
+/// an upgraded log are given the global positions they miss.
+async fn connect(
+    log: Shared,
+    schema: &[StoreSchema],
+) -> Result<LogDatabase, IndexDbAggregateError> {
+    let upgraded = {
+        let mut log = lock(&log);
+        let result =
+            base_schema()
+                .iter()
+                .chain(schema)
+                .try_for_each(|store| match log.missing(store) {
+                    Some(store) => {
+                        if log.pending.is_none() {
+                            log.begin()?;
+                        }
+                        log.define(&store)
+                    }
+                    None => Ok(()),
+                });
+        match result {
+            Ok(()) if log.pending.is_some() => {
+                log.commit()?;
+                true
+            }
+            Ok(()) => false,
+            Err(err) => {
+                log.rollback()?;
+                return Err(err);
+            }
+        }
+    };
+
+    let database = LogDatabase { log };
+    if upgraded {
+        let declared: Vec<StoreSchema> = base_schema().into_iter().chain(schema.to_vec()).collect();
+        backfill(&database, &declared).await?;
+    }
+    Ok(database)
+}
+
+/// Where a record sits in the log.
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    offset: u64,
+    len: u32,
+}
+
+/// A store of the log: where each of its records is, by encoded key, and the entries of each of
+/// its indexes, pointing to the encoded key of their record.
+#[derive(Clone)]
+struct Store {
+    schema: StoreSchema,
+    records: BTreeMap<Vec<u8>, Location>,
+    indexes: HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl Store {
+    fn new(schema: StoreSchema) -> Self {
+        let indexes = schema
+            .indexes
+            .iter()
+            .map(|(name, _)| (name.clone(), BTreeMap::new()))
+            .collect();
+        Self {
+            schema,
+            records: BTreeMap::new(),
+            indexes,
+        }
+    }
+
+    /// Replaces the index entries of `previous` with those of `record`, both under `key`.
+    fn index(&mut self, key: &[u8], previous: Option<&Value>, record: Option<&Value>) {
+        for (name, key_path) in &self.schema.indexes {
+            let entries = self.indexes.entry(name.clone()).or_default();
+            if let Some(value) = previous.and_then(|previous| key_path.extract(previous)) {
+                entries.remove(&index_entry(&value, key));
+            }
+            if let Some(value) = record.and_then(|record| key_path.extract(record)) {
+                entries.insert(index_entry(&value, key), key.to_vec());
+            }
+        }
+    }
+
+    fn entries(&self, name: &str) -> Result<&BTreeMap<Vec<u8>, Vec<u8>>, IndexDbAggregateError> {
+        self.indexes.get(name).ok_or_else(|| {
+            IndexDbAggregateError::UnknownError(format!(
+                "no index {name} in store {}",
+                self.schema.name
+            ))
+        })
+    }
+
+    /// The location of the record an index entry points to.
+    fn location(&self, key: &[u8]) -> Result<Location, IndexDbAggregateError> {
+        self.records.get(key).copied().ok_or_else(|| {
+            IndexDbAggregateError::UnknownError("index entry without a record".to_string())
+        })
+    }
+}
+
+/// The write transaction in progress: where its entries start and end, their checksum so far,
+/// and how to undo what it changed in memory.
+struct Pending {
+    start: u64,
+    end: u64,
+    checksum: u32,
+    undo: Vec<Undo>,
+}
+
+enum Undo {
+    /// The record under `key` was put or deleted, and was at `previous` before.
+    Record {
+        store: String,
+        key: Vec<u8>,
+        previous: Option<Location>,
+    },
+    /// The store was cleared or given new indexes, and was `previous` before.
+    Store {
+        name: String,
+        previous: Option<Store>,
+    },
+}
+
+/// An open log along with the offset index of its stores.
+pub(crate) struct Log {
+    segment: SegmentFile,
+    checkpoint: SegmentFile,
+    compaction: SegmentFile,
+    /// The end of the last committed transaction.
+    end: u64,
+    /// The end of the log when the offset index was last checkpointed.
+    checkpointed: u64,
+    /// The size of the last checkpoint.
+    checkpoint_size: u64,
+    stores: HashMap<String, Store>,
+    pending: Option<Pending>,
+}
+
+impl Log {
+    /// Completes a compaction cut short, loads the last checkpoint of the offset index, then
+    /// replays the transactions committed after it and drops any incomplete one at the end of
+    /// the log.
+    fn open((segment, checkpoint, compaction): LogFiles) -> Result<Self, IndexDbAggregateError> {
+        let mut log = Self {
+            segment,
+            checkpoint,
+            compaction,
+            end: 0,
+            checkpointed: 0,
+            checkpoint_size: 0,
+            stores: HashMap::new(),
+            pending: None,
+        };
+        if let Some(len) = log.compacted()? {
+            log.copy_compacted(len)?;
+        }
+
+        let size = log.segment.size()?;
+        log.checkpoint_size = log.checkpoint.size()?;
+        if let Some((end, stores)) =
+            read_checkpoint(&mut log.checkpoint)?.filter(|(end, _)| *end <= size)
+        {
+            log.end = end;
+            log.checkpointed = end;
+            log.stores = stores;
+        }
+        log.replay(size)?;
+        log.maybe_checkpoint()?;
+        Ok(log)
+    }
+
+    fn replay(&mut self, size: u64) -> Result<(), IndexDbAggregateError> {
+        let start = self.end;
+        let mut bytes = vec![0; (size - start) as usize];
+        self.segment.read_at(start, &mut bytes)?;
+
+        let mut position = 0;
+        let mut entries = Vec::new();
+        let mut checksum = CHECKSUM;
+        while let Some((kind, payload)) = entry(&bytes[position..]) {
+            let offset = start + (position + HEADER) as u64;
+            let len = HEADER + payload.len();
+            if kind == COMMIT {
+                if payload != checksum.to_le_bytes() {
+                    break;
+                }
+                for (kind, offset, payload) in entries.drain(..) {
+                    self.apply(kind, offset, payload)?;
+                }
+                checksum = CHECKSUM;
+                self.end = start + (position + len) as u64;
+            } else {
+                checksum = update(checksum, &bytes[position..position + len]);
+                entries.push((kind, offset, payload));
+            }
+            position += len;
+        }
+
+        // Whatever follows the last commit was torn by a crash
+        if self.end < size {
+            self.segment.truncate(self.end)?;
+        }
+        Ok(())
+    }
+
+    /// Applies a committed entry whose payload is at `offset`.
+    fn apply(
+        &mut self,
+        kind: u8,
+        offset: u64,
+        payload: &[u8],
+    ) -> Result<(), IndexDbAggregateError> {
+        let mut reader = Reader(payload);
+        let store = reader.string().ok_or_else(corrupted)?;
+        match kind {
+            PUT => {
+                let key = reader.field().ok_or_else(corrupted)?;
+                let location = Location {
+                    offset: offset + (payload.len() - reader.0.len()) as u64,
+                    len: reader.0.len() as u32,
+                };
+                let record: Value = serde_json::from_slice(reader.0)?;
+                self.put_record(&store, key, location, &record)?;
+            }
+            DELETE => {
+                self.delete_record(&store, reader.0)?;
+            }
+            CLEAR => {
+                self.clear_store(&store)?;
+            }
+            SCHEMA => {
+                self.define_store(serde_json::from_slice(reader.0)?)?;
+            }
+            _ => return Err(corrupted()),
+        }
+        Ok(())
+    }
+
+    fn store(&self, name: &str) -> Result<&Store, IndexDbAggregateError> {
+        self.stores.get(name).ok_or_else(|| no_store(name))
+    }
+
+    /// Puts the record at `location` under `key`, returning where the one it replaces was.
+    fn put_record(
+        &mut self,
+        name: &str,
+        key: &[u8],
+        location: Location,
+        record: &Value,
+    ) -> Result<Option<Location>, IndexDbAggregateError> {
+        let store = self.stores.get_mut(name).ok_or_else(|| no_store(name))?;
+        let previous = store.records.insert(key.to_vec(), location);
+        let previous_record = previous
+            .map(|previous| read_record(&mut self.segment, previous))
+            .transpose()?;
+        store.index(key, previous_record.as_ref(), Some(record));
+        Ok(previous)
+    }
+
+    /// Deletes the record under `key`, returning where it was.
+    fn delete_record(
+        &mut self,
+        name: &str,
+        key: &[u8],
+    ) -> Result<Option<Location>, IndexDbAggregateError> {
+        let store = self.stores.get_mut(name).ok_or_else(|| no_store(name))?;
+        let previous = store.records.remove(key);
+        if let Some(previous) = previous {
+            let previous_record = read_record(&mut self.segment, previous)?;
+            store.index(key, Some(&previous_record), None);
+        }
+        Ok(previous)
+    }
+
+    /// Empties the store, returning what it held.
+    fn clear_store(&mut self, name: &str) -> Result<Store, IndexDbAggregateError> {
+        let store = self.stores.get_mut(name).ok_or_else(|| no_store(name))?;
+        let empty = Store::new(store.schema.clone());
+        Ok(std::mem::replace(store, empty))
+    }
+
+    /// Creates the store, or fills the indexes it does not have yet.
+    fn define_store(&mut self, schema: StoreSchema) -> Result<(), IndexDbAggregateError> {
+        let store = self
+            .stores
+            .entry(schema.name.clone())
+            .or_insert_with(|| Store::new(schema.clone()));
+        for (name, key_path) in &schema.indexes {
+            if store.indexes.contains_key(name) {
+                continue;
+            }
+            let mut entries = BTreeMap::new();
+            for (key, location) in &store.records {
+                let record = read_record(&mut self.segment, *location)?;
+                if let Some(value) = key_path.extract(&record) {
+                    entries.insert(index_entry(&value, key), key.clone());
+                }
+            }
+            store.indexes.insert(name.clone(), entries);
+        }
+        store.schema = schema;
+        Ok(())
+    }
+
+    /// The schema the store needs to have every index of `store`, `None` if it already has.
+    fn missing(&self, store: &StoreSchema) -> Option<StoreSchema> {
+        let Some(existing) = self.stores.get(&store.name) else {
+            return Some(store.clone());
+        };
+        let mut schema = existing.schema.clone();
+        for (name, key_path) in &store.indexes {
+            if !existing.indexes.contains_key(name) {
+                schema.indexes.push((name.clone(), key_path.clone()));
+            }
+        }
+        (schema.indexes.len() > existing.schema.indexes.len()).then_some(schema)
+    }
+
+    fn begin(&mut self) -> Result<(), IndexDbAggregateError> {
+        if self.pending.is_some() {
+            return Err(IndexDbAggregateError::UnknownError(
+                "another write transaction is in progress".to_string(),
+            ));
+        }
+        self.pending = Some(Pending {
+            start: self.end,
+            end: self.end,
+            checksum: CHECKSUM,
+            undo: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Appends an entry of the pending transaction, returning the offset of its payload.
+    fn append(&mut self, kind: u8, payload: &[u8]) -> Result<u64, IndexDbAggregateError> {
+        let pending = self.pending.as_mut().ok_or_else(finished)?;
+        let bytes = encode_entry(kind, payload);
+        self.segment.write_at(pending.end, &bytes)?;
+
+        pending.checksum = update(pending.checksum, &bytes);
+        let offset = pending.end + HEADER as u64;
+        pending.end += bytes.len() as u64;
+        Ok(offset)
+    }
+
+    fn record_undo(&mut self, undo: Undo) {
+        if let Some(pending) = &mut self.pending {
+            pending.undo.push(undo);
+        }
+    }
+
+    fn put(
+        &mut self,
+        name: &str,
+        key: Vec<u8>,
+        record: &Value,
+    ) -> Result<(), IndexDbAggregateError> {
+        let json = serde_json::to_vec(record)?;
+        let mut payload = Vec::new();
+        write_field(&mut payload, name.as_bytes());
+        write_field(&mut payload, &key);
+        payload.extend_from_slice(&json);
+
+        let offset = self.append(PUT, &payload)?;
+        let location = Location {
+            offset: offset + (payload.len() - json.len()) as u64,
+            len: json.len() as u32,
+        };
+        let previous = self.put_record(name, &key, location, record)?;
+        self.record_undo(Undo::Record {
+            store: name.to_string(),
+            key,
+            previous,
+        });
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str, bounds: &Bounds) -> Result<(), IndexDbAggregateError> {
+        let keys: Vec<Vec<u8>> = range(&self.store(name)?.records, bounds)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            let mut payload = Vec::new();
+            write_field(&mut payload, name.as_bytes());
+            payload.extend_from_slice(&key);
+            self.append(DELETE, &payload)?;
+
+            let previous = self.delete_record(name, &key)?;
+            self.record_undo(Undo::Record {
+                store: name.to_string(),
+                key,
+                previous,
+            });
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, name: &str) -> Result<(), IndexDbAggregateError> {
+        let mut payload = Vec::new();
+        write_field(&mut payload, name.as_bytes());
+        self.append(CLEAR, &payload)?;
+
+        let previous = self.clear_store(name)?;
+        self.record_undo(Undo::Store {
+            name: name.to_string(),
+            previous: Some(previous),
+        });
+        Ok(())
+    }
+
+    fn define(&mut self, schema: &StoreSchema) -> Result<(), IndexDbAggregateError> {
+        let mut payload = Vec::new();
+        write_field(&mut payload, schema.name.as_bytes());
+        payload.extend_from_slice(&serde_json::to_vec(schema)?);
+        self.append(SCHEMA, &payload)?;
+
+        let previous = self.stores.get(&schema.name).cloned();
+        self.define_store(schema.clone())?;
+        self.record_undo(Undo::Store {
+            name: schema.name.clone(),
+            previous,
+        });
+        Ok(())
+    }
+
+    /// Seals the pending transaction with the checksum of its entries and flushes the log.
+    fn commit(&mut self) -> Result<(), IndexDbAggregateError> {
+        let pending = self.pending.as_ref().ok_or_else(finished)?;
+        if pending.end > pending.start {
+            let checksum = pending.checksum;
+            self.append(COMMIT, &checksum.to_le_bytes())?;
+            self.segment.flush()?;
+        }
+        if let Some(pending) = self.pending.take() {
+            self.end = pending.end;
+        }
+        self.maybe_checkpoint()
+    }
+
+    /// Undoes the changes of the pending transaction and cuts its entries off the log.
+    fn rollback(&mut self) -> Result<(), IndexDbAggregateError> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        for undo in pending.undo.into_iter().rev() {
+            match undo {
+                Undo::Record {
+                    store,
+                    key,
+                    previous,
+                } => {
+                    let store = self
+                        .stores
+                        .get_mut(&store)
+                        .ok_or_else(|| no_store(&store))?;
+                    let current = match previous {
+                        Some(previous) => store.records.insert(key.clone(), previous),
+                        None => store.records.remove(&key),
+                    };
+                    let current = current
+                        .map(|current| read_record(&mut self.segment, current))
+                        .transpose()?;
+                    let previous = previous
+                        .map(|previous| read_record(&mut self.segment, previous))
+                        .transpose()?;
+                    store.index(&key, current.as_ref(), previous.as_ref());
+                }
+                Undo::Store {
+                    name,
+                    previous: Some(previous),
+                } => {
+                    self.stores.insert(name, previous);
+                }
+                Undo::Store {
+                    name,
+                    previous: None,
+                } => {
+                    self.stores.remove(&name);
+                }
+            }
+        }
+        if pending.end > pending.start {
+            self.segment.truncate(pending.start)?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoints the offset index once enough was committed since the last checkpoint.
+    ///
+    /// Each checkpoint rewrites the whole offset index, so it waits for at least as many bytes
+    /// as the last one took: the cost of checkpointing stays proportional to what is written.
+    fn maybe_checkpoint(&mut self) -> Result<(), IndexDbAggregateError> {
+        if self.end - self.checkpointed < CHECKPOINT_INTERVAL.max(self.checkpoint_size) {
+            return Ok(());
+        }
+        self.write_checkpoint()
+    }
+
+    fn write_checkpoint(&mut self) -> Result<(), IndexDbAggregateError> {
+        // The end of the log it covers, then every store with its records and index entries
+        let mut bytes = self.end.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(self.stores.len() as u32).to_le_bytes());
+        for store in self.stores.values() {
+            write_field(&mut bytes, &serde_json::to_vec(&store.schema)?);
+            bytes.extend_from_slice(&(store.records.len() as u32).to_le_bytes());
+            for (key, location) in &store.records {
+                write_field(&mut bytes, key);
+                bytes.extend_from_slice(&location.offset.to_le_bytes());
+                bytes.extend_from_slice(&location.len.to_le_bytes());
+            }
+            bytes.extend_from_slice(&(store.indexes.len() as u32).to_le_bytes());
+            for (name, entries) in &store.indexes {
+                write_field(&mut bytes, name.as_bytes());
+                bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+                for (entry, key) in entries {
+                    write_field(&mut bytes, entry);
+                    write_field(&mut bytes, key);
+                }
+            }
+        }
+
+        // A checkpoint torn by a crash fails its checksum, and the log is replayed in full
+        let checksum = update(CHECKSUM, &bytes);
+        self.checkpoint.write_at(0, &checksum.to_le_bytes())?;
+        self.checkpoint.write_at(4, &bytes)?;
+        self.checkpoint.truncate(4 + bytes.len() as u64)?;
+        self.checkpoint.flush()?;
+        self.checkpointed = self.end;
+        self.checkpoint_size = 4 + bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Rewrites the log with only the records its stores hold, if they take less than half of
+    /// it, dropping the records overwritten or deleted since they were written. Returns whether
+    /// the log was compacted.
+    ///
+    /// The compacted log is a single transaction, defining every store then putting every
+    /// record. It is written to the compaction file along with its checksum, then copied over
+    /// the log.
+    pub(crate) fn compact(&mut self) -> Result<bool, IndexDbAggregateError> {
+        if self.pending.is_some() {
+            return Err(IndexDbAggregateError::UnknownError(
+                "cannot compact the log during a write transaction".to_string(),
+            ));
+        }
+        if self.live_size() * 2 > self.end {
+            return Ok(false);
+        }
+
+        let mut stores: Vec<&Store> = self.stores.values().collect();
+        stores.sort_by(|a, b| a.schema.name.cmp(&b.schema.name));
+        let mut writer = CompactionWriter::new(&mut self.compaction);
+        for store in &stores {
+            let mut payload = Vec::new();
+            write_field(&mut payload, store.schema.name.as_bytes());
+            payload.extend_from_slice(&serde_json::to_vec(&store.schema)?);
+            writer.entry(SCHEMA, &payload)?;
+        }
+        for store in &stores {
+            for (key, location) in &store.records {
+                let mut payload = Vec::new();
+                write_field(&mut payload, store.schema.name.as_bytes());
+                write_field(&mut payload, key);
+                let start = payload.len();
+                payload.resize(start + location.len as usize, 0);
+                self.segment
+                    .read_at(location.offset, &mut payload[start..])?;
+                writer.entry(PUT, &payload)?;
+            }
+        }
+        let len = writer.finish()?;
+
+        self.copy_compacted(len)?;
+        self.stores.clear();
+        self.end = 0;
+        self.replay(len)?;
+        self.write_checkpoint()?;
+        Ok(true)
+    }
+
+    /// How many bytes the records of the stores would take once compacted.
+    fn live_size(&self) -> u64 {
+        self.stores
+            .values()
+            .map(|store| {
+                let name = store.schema.name.len();
+                store
+                    .records
+                    .iter()
+                    .map(|(key, location)| {
+                        (HEADER + 8 + name + key.len()) as u64 + location.len as u64
+                    })
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+
+    /// The length of the compacted log held by the compaction file, `None` if it holds none or
+    /// it is torn.
+    fn compacted(&mut self) -> Result<Option<u64>, IndexDbAggregateError> {
+        let size = self.compaction.size()?;
+        if size < COMPACTION_HEADER {
+            return Ok(None);
+        }
+        let mut header = [0; COMPACTION_HEADER as usize];
+        self.compaction.read_at(0, &mut header)?;
+        let mut reader = Reader(&header);
+        let (Some(checksum), Some(len)) = (reader.u32(), reader.u64()) else {
+            return Ok(None);
+        };
+        if COMPACTION_HEADER + len != size {
+            return Ok(None);
+        }
+        let mut computed = CHECKSUM;
+        let mut offset = 0;
+        while offset < len {
+            let mut chunk = vec![0; (len - offset).min(COMPACTION_CHUNK as u64) as usize];
+            self.compaction
+                .read_at(COMPACTION_HEADER + offset, &mut chunk)?;
+            computed = update(computed, &chunk);
+            offset += chunk.len() as u64;
+        }
+        Ok((computed == checksum).then_some(len))
+    }
+
+    /// Copies the compacted log of `len` bytes over the log, then empties the compaction file.
+    /// The checkpoint points into the log being replaced, so it is dropped first.
+    fn copy_compacted(&mut self, len: u64) -> Result<(), IndexDbAggregateError> {
+        self.checkpoint.truncate(0)?;
+        self.checkpoint.flush()?;
+        self.checkpoint_size = 0;
+        self.checkpointed = 0;
+
+        let mut offset = 0;
+        while offset < len {
+            let mut chunk = vec![0; (len - offset).min(COMPACTION_CHUNK as u64) as usize];
+            self.compaction
+                .read_at(COMPACTION_HEADER + offset, &mut chunk)?;
+            self.segment.write_at(offset, &chunk)?;
+            offset += chunk.len() as u64;
+        }
+        self.segment.truncate(len)?;
+        self.segment.flush()?;
+
+        self.compaction.truncate(0)?;
+        self.compaction.flush()
+    }
+
+    fn get(&mut self, name: &str, key: &[u8]) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self.store(name)?.records.get(key).copied() {
+            Some(location) => Ok(Some(read_record(&mut self.segment, location)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_all(
+        &mut self,
+        name: &str,
+        bounds: &Bounds,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let locations: Vec<Location> = range(&self.store(name)?.records, bounds)
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(|(_, location)| *location)
+            .collect();
+        self.read_records(locations)
+    }
+
+    fn count(&self, name: &str, bounds: &Bounds) -> Result<u32, IndexDbAggregateError> {
+        Ok(range(&self.store(name)?.records, bounds).count() as u32)
+    }
+
+    fn index_get_all(
+        &mut self,
+        name: &str,
+        index: &str,
+        bounds: &Bounds,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let store = self.store(name)?;
+        let locations = range(store.entries(index)?, bounds)
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(|(_, key)| store.location(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.read_records(locations)
+    }
+
+    fn index_count(
+        &self,
+        name: &str,
+        index: &str,
+        bounds: &Bounds,
+    ) -> Result<u32, IndexDbAggregateError> {
+        Ok(range(self.store(name)?.entries(index)?, bounds).count() as u32)
+    }
+
+    fn index_last(
+        &mut self,
+        name: &str,
+        index: &str,
+    ) -> Result<Option<Value>, IndexDbAggregateError> {
+        let store = self.store(name)?;
+        let location = match store.entries(index)?.values().next_back() {
+            Some(key) => store.location(key)?,
+            None => return Ok(None),
+        };
+        read_record(&mut self.segment, location).map(Some)
+    }
+
+    fn read_records(
+        &mut self,
+        locations: Vec<Location>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        locations
+            .into_iter()
+            .map(|location| read_record(&mut self.segment, location))
+            .collect()
+    }
+}
+
+/// The size of the header of the compaction file: the checksum of the compacted log, then its
+/// length.
+const COMPACTION_HEADER: u64 = 12;
+
+/// Writes a compacted log to the compaction file a chunk at a time.
+struct CompactionWriter<'a> {
+    file: &'a mut SegmentFile,
+    chunk: Vec<u8>,
+    /// How many bytes of the compacted log were written to the file so far.
+    written: u64,
+    /// The checksum of the entries of the transaction, then of the whole compacted log.
+    entries: u32,
+    all: u32,
+}
+
+impl<'a> CompactionWriter<'a> {
+    fn new(file: &'a mut SegmentFile) -> Self {
+        Self {
+            file,
+            chunk: Vec::new(),
+            written: 0,
+            entries: CHECKSUM,
+            all: CHECKSUM,
+        }
+    }
+
+    fn entry(&mut self, kind: u8, payload: &[u8]) -> Result<(), IndexDbAggregateError> {
+        let bytes = encode_entry(kind, payload);
+        self.entries = update(self.entries, &bytes);
+        self.write(&bytes)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), IndexDbAggregateError> {
+        self.all = update(self.all, bytes);
+        self.chunk.extend_from_slice(bytes);
+        if self.chunk.len() >= COMPACTION_CHUNK {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush_chunk(&mut self) -> Result<(), IndexDbAggregateError> {
+        self.file
+            .write_at(COMPACTION_HEADER + self.written, &self.chunk)?;
+        self.written += self.chunk.len() as u64;
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Seals the transaction and writes the header once the compacted log is flushed, returning
+    /// its length.
+    fn finish(mut self) -> Result<u64, IndexDbAggregateError> {
+        let commit = encode_entry(COMMIT, &self.entries.to_le_bytes());
+        self.write(&commit)?;
+        self.flush_chunk()?;
+        self.file.truncate(COMPACTION_HEADER + self.written)?;
+        self.file.flush()?;
+
+        let mut header = self.all.to_le_bytes().to_vec();
+        header.extend_from_slice(&self.written.to_le_bytes());
+        self.file.write_at(0, &header)?;
+        self.file.flush()?;
+        Ok(self.written)
+    }
+}
+
+/// The stores of a log, as of the end of the log they cover.
+type Checkpoint = (u64, HashMap<String, Store>);
+
+/// Reads the last checkpoint of the offset index, `None` if there is none or it is torn.
+fn read_checkpoint(
+    checkpoint: &mut SegmentFile,
+) -> Result<Option<Checkpoint>, IndexDbAggregateError> {
+    let size = checkpoint.size()? as usize;
+    if size < 4 {
+        return Ok(None);
+    }
+    let mut bytes = vec![0; size];
+    checkpoint.read_at(0, &mut bytes)?;
+    let (checksum, body) = bytes.split_at(4);
+    if checksum != update(CHECKSUM, body).to_le_bytes() {
+        return Ok(None);
+    }
+
+    let mut reader = Reader(body);
+    let parse = |reader: &mut Reader| {
+        let end = reader.u64()?;
+        let mut stores = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let schema: StoreSchema = serde_json::from_slice(reader.field()?).ok()?;
+            let mut store = Store::new(schema);
+            for _ in 0..reader.u32()? {
+                let key = reader.field()?.to_vec();
+                let location = Location {
+                    offset: reader.u64()?,
+                    len: reader.u32()?,
+                };
+                store.records.insert(key, location);
+            }
+            for _ in 0..reader.u32()? {
+                let name = reader.string()?;
+                let mut entries = BTreeMap::new();
+                for _ in 0..reader.u32()? {
+                    entries.insert(reader.field()?.to_vec(), reader.field()?.to_vec());
+                }
+                store.indexes.insert(name, entries);
+            }
+            stores.insert(store.schema.name.clone(), store);
+        }
+        Some((end, stores))
+    };
+    Ok(parse(&mut reader))
+}
+
+/// Reads the kind and the payload of the entry at the start of `bytes`, `None` if it is cut
+/// short.
+fn entry(bytes: &[u8]) -> Option<(u8, &[u8])> {
+    let mut reader = Reader(bytes);
+    let kind = reader.take(1)?[0];
+    let len = reader.u32()? as usize;
+    Some((kind, reader.take(len)?))
+}
+
+fn read_record(
+    segment: &mut SegmentFile,
+    location: Location,
+) -> Result<Value, IndexDbAggregateError> {
+    let mut bytes = vec![0; location.len as usize];
+    segment.read_at(location.offset, &mut bytes)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// The entries of `map` within `bounds`, in key order.
+fn range<'a, V>(
+    map: &'a BTreeMap<Vec<u8>, V>,
+    bounds: &Bounds,
+) -> impl Iterator<Item = (&'a Vec<u8>, &'a V)> {
+    // An empty range selects nothing, where the map would panic
+    let entries = (!is_empty(bounds)).then(|| {
+        map.range::<[u8], _>((
+            bounds.0.as_ref().map(Vec::as_slice),
+            bounds.1.as_ref().map(Vec::as_slice),
+        ))
+    });
+    entries.into_iter().flatten()
+}
+
+/// An entry of the log: its kind, the length of its payload then the payload.
+fn encode_entry(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER + payload.len());
+    bytes.push(kind);
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Writes `field` prefixed by its length.
+fn write_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(field);
+}
+
+/// Reads the fields of an entry or a checkpoint, in the order they were written.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    /// Reads a field written by [`write_field`].
+    fn field(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.field()?.to_vec()).ok()
+    }
+}
+
+/// Feeds `bytes` to an FNV-1a checksum.
+fn update(checksum: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(checksum, |checksum, byte| {
+        (checksum ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// An open log.
+pub(crate) struct LogDatabase {
+    log: Shared,
+}
+
+impl LogDatabase {
+    /// Compacts the log, see [`Log::compact`].
+    pub(crate) fn compact(&self) -> Result<bool, IndexDbAggregateError> {
+        lock(&self.log).compact()
+    }
+}
+
+impl Database for LogDatabase {
+    type Transaction = LogTransaction;
+
+    fn transaction<S: AsRef<str>>(
+        &self,
+        store_names: &[S],
+        mode: TransactionMode,
+    ) -> Result<LogTransaction, IndexDbAggregateError> {
+        let mut log = lock(&self.log);
+        for name in store_names {
+            log.store(name.as_ref())?;
+        }
+        if mode == TransactionMode::ReadWrite {
+            log.begin()?;
+        }
+        Ok(LogTransaction {
+            scope: Rc::new(Scope {
+                log: self.log.clone(),
+                mode,
+                active: Cell::new(true),
+            }),
+        })
+    }
+}
+
+/// What the stores of a transaction share with it.
+struct Scope {
+    log: Shared,
+    mode: TransactionMode,
+    active: Cell<bool>,
+}
+
+impl Scope {
+    /// Runs `f` against the log while the transaction is active.
+    fn read<T>(
+        &self,
+        f: impl FnOnce(&mut Log) -> Result<T, IndexDbAggregateError>,
+    ) -> Result<T, IndexDbAggregateError> {
+        if !self.active.get() {
+            return Err(finished());
+        }
+        f(&mut lock(&self.log))
+    }
+
+    /// Runs `f` against the log within the write transaction.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut Log) -> Result<T, IndexDbAggregateError>,
+    ) -> Result<T, IndexDbAggregateError> {
+        if self.mode == TransactionMode::ReadOnly {
+            return Err(IndexDbAggregateError::UnknownError(
+                "the transaction is read-only".to_string(),
+            ));
+        }
+        self.read(f)
+    }
+}
+
+/// A transaction over the log. Write transactions are applied as they go and undone unless
+/// committed.
+pub(crate) struct LogTransaction {
+    scope: Rc<Scope>,
+}
+
+impl LogTransaction {
+    /// Ends the transaction, running `f` to seal or undo it if it writes.
+    fn finish(
+        &self,
+        f: impl FnOnce(&mut Log) -> Result<(), IndexDbAggregateError>,
+    ) -> Result<(), IndexDbAggregateError> {
+        if !self.scope.active.replace(false) {
+            return Err(finished());
+        }
+        match self.scope.mode {
+            TransactionMode::ReadWrite => f(&mut lock(&self.scope.log)),
+            TransactionMode::ReadOnly => Ok(()),
+        }
+    }
+}
+
+impl Drop for LogTransaction {
+    fn drop(&mut self) {
+        let _ = self.finish(Log::rollback);
+    }
+}
+
+#[async_trait(?Send)]
+impl Transaction for LogTransaction {
+    type ObjectStore = LogObjectStore;
+
+    fn object_store(&self, name: &str) -> Result<LogObjectStore, IndexDbAggregateError> {
+        let schema = self.scope.read(|log| Ok(log.store(name)?.schema.clone()))?;
+        Ok(LogObjectStore {
+            scope: self.scope.clone(),
+            schema,
+        })
+    }
+
+    async fn commit(self) -> Result<(), IndexDbAggregateError> {
+        self.finish(|log| {
+            let result = log.commit();
+            if result.is_err() {
+                log.rollback()?;
+            }
+            result
+        })
+    }
+
+    async fn abort(self) -> Result<(), IndexDbAggregateError> {
+        self.finish(Log::rollback)
+    }
+}
+
+/// A store within a transaction over the log.
+pub(crate) struct LogObjectStore {
+    scope: Rc<Scope>,
+    schema: StoreSchema,
+}
+
+impl LogObjectStore {
+    /// Encodes the key of `record`, failing like IndexedDB if it has none.
+    fn record_key(&self, record: &Value) -> Result<Vec<u8>, IndexDbAggregateError> {
+        match self.schema.key_path.extract(record) {
+            Some(key) => Ok(encode(&key)),
+            None => Err(IndexDbAggregateError::DeserializationError(format!(
+                "record without a valid key in store {}",
+                self.schema.name
+            ))),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ObjectStore for LogObjectStore {
+    type Index = LogIndex;
+
+    fn key_path(&self) -> Result<Option<KeyPath>, IndexDbAggregateError> {
+        Ok(Some(self.schema.key_path.clone()))
+    }
+
+    fn index(&self, name: &str) -> Result<LogIndex, IndexDbAggregateError> {
+        if !self.schema.indexes.iter().any(|(index, _)| index == name) {
+            return Err(IndexDbAggregateError::UnknownError(format!(
+                "no index {name} in store {}",
+                self.schema.name
+            )));
+        }
+        Ok(LogIndex {
+            scope: self.scope.clone(),
+            store_name: self.schema.name.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    async fn get(&self, key: &Key) -> Result<Option<Value>, IndexDbAggregateError> {
+        let key = encode(key);
+        self.scope.read(|log| log.get(&self.schema.name, &key))
+    }
+
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.scope
+            .read(|log| log.get_all(&self.schema.name, &bounds, limit))
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.scope.read(|log| log.count(&self.schema.name, &bounds))
+    }
+
+    async fn add(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        let key = self.record_key(record)?;
+        self.scope.write(|log| {
+            if log.store(&self.schema.name)?.records.contains_key(&key) {
+                return Err(IndexDbAggregateError::OptimisticLock);
+            }
+            log.put(&self.schema.name, key, record)
+        })
+    }
+
+    async fn put(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        let key = self.record_key(record)?;
+        self.scope
+            .write(|log| log.put(&self.schema.name, key, record))
+    }
+
+    async fn delete(&self, range: &KeyRange) -> Result<(), IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.scope
+            .write(|log| log.delete(&self.schema.name, &bounds))
+    }
+
+    async fn clear(&self) -> Result<(), IndexDbAggregateError> {
+        self.scope.write(|log| log.clear(&self.schema.name))
+    }
+}
+
+/// An index of a store within a transaction over the log.
+pub(crate) struct LogIndex {
+    scope: Rc<Scope>,
+    store_name: String,
+    name: String,
+}
+
+#[async_trait(?Send)]
+impl Index for LogIndex {
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let bounds = index_bounds(range);
+        self.scope
+            .read(|log| log.index_get_all(&self.store_name, &self.name, &bounds, limit))
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        let bounds = index_bounds(range);
+        self.scope
+            .read(|log| log.index_count(&self.store_name, &self.name, &bounds))
+    }
+
+    async fn last(&self) -> Result<Option<Value>, IndexDbAggregateError> {
+        self.scope
+            .read(|log| log.index_last(&self.store_name, &self.name))
+    }
+}
+
+fn no_store(name: &str) -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError(format!("no object store {name}"))
+}
+
+fn finished() -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError("the transaction has finished".to_string())
+}
+
+fn corrupted() -> IndexDbAggregateError {
+    IndexDbAggregateError::DeserializationError("corrupted log entry".to_string())
+}
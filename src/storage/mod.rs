@@ -0,0 +1,203 @@
+//! The storage engine behind the repositories, modelled on IndexedDB.
+//!
+//! A database holds object stores of JSON records, each keyed by the value at the key path of
+//! its store and optionally indexed by the values at other key paths. Records are read and
+//! written within transactions spanning a set of stores, which are all-or-nothing.
+//!
+//! The repositories are written against the traits of this module, so that every backend runs
+//! the same logic: IndexedDB in the browser and an embedded redb database, on file or in
+//! memory, everywhere else. Databases named with [`log_db_name`] are kept in an append-only log
+//! instead, in the origin private file system when in the browser.
+
+pub(crate) use self::either::Either;
+pub(crate) use self::encoding::encode;
+pub(crate) use self::key::*;
+pub(crate) use self::log::log_db_name;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use self::redb_backend::memory_db_name;
+
+#[cfg(target_arch = "wasm32")]
+use self::indexed_db::{run_in as run_in_default, IdbDatabase as DefaultDatabase};
+use self::log::{log_name, LogDatabase};
+#[cfg(not(target_arch = "wasm32"))]
+use self::redb_backend::{run_in as run_in_default, RedbDatabase as DefaultDatabase};
+
+mod either;
+mod encoding;
+#[cfg(target_arch = "wasm32")]
+mod indexed_db;
+mod key;
+mod log;
+#[cfg(target_arch = "wasm32")]
+mod opfs;
+#[cfg(not(target_arch = "wasm32"))]
+mod redb_backend;
+
+use crate::event_repository::{backfill_positions, is_event_store, POSITION_STORE};
+use crate::IndexDbAggregateError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+
+/// A database of any backend.
+pub(crate) type Db = Either<DefaultDatabase, LogDatabase>;
+
+/// Opens the database `db_name` with the stores of `schema` on its backend, then runs `f`
+/// against it and hands its result back.
+pub(crate) fn run_in<T, F, Fut>(
+    db_name: String,
+    schema: Vec<StoreSchema>,
+    f: F,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+where
+    T: 'static,
+    F: FnOnce(Db) -> Fut + 'static,
+    Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+{
+    match log_name(&db_name) {
+        Some(name) => {
+            futures::future::Either::Right(self::log::run_in(name.to_string(), schema, |db| {
+                f(Either::Right(db))
+            }))
+        }
+        None => {
+            futures::future::Either::Left(run_in_default(db_name, schema, |db| f(Either::Left(db))))
+        }
+    }
+}
+
+/// Compacts `db` if it is an append-only log, returning whether it was. The other backends
+/// reclaim the space of deleted records on their own.
+pub(crate) fn compact(db: &Db) -> Result<bool, IndexDbAggregateError> {
+    match db {
+        Either::Left(_) => Ok(false),
+        Either::Right(log) => log.compact(),
+    }
+}
+
+/// Numbers the events written before the events stores of `schema` tracked global positions,
+/// see [`backfill_positions`]. The backends keeping their own schema run it when they upgrade
+/// a database, as IndexedDB does within its upgrade transaction.
+async fn backfill(db: &impl Database, schema: &[StoreSchema]) -> Result<(), IndexDbAggregateError> {
+    for store in schema.iter().filter(|store| is_event_store(store)) {
+        let transaction = db.transaction(
+            &[store.name.as_str(), POSITION_STORE],
+            TransactionMode::ReadWrite,
+        )?;
+        backfill_positions(&transaction, &store.name).await?;
+        transaction.commit().await?;
+    }
+    Ok(())
+}
+
+/// Whether a transaction may write to its stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TransactionMode {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// An object store that must exist in the database, along with its indexes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct StoreSchema {
+    pub(crate) name: String,
+    pub(crate) key_path: KeyPath,
+    /// The indexes of the store, as `(name, key path)`.
+    pub(crate) indexes: Vec<(String, KeyPath)>,
+}
+
+/// An open database.
+pub(crate) trait Database {
+    type Transaction: Transaction;
+
+    /// Starts a transaction over the named stores, which must all exist.
+    fn transaction<S: AsRef<str>>(
+        &self,
+        store_names: &[S],
+        mode: TransactionMode,
+    ) -> Result<Self::Transaction, IndexDbAggregateError>;
+}
+
+/// A transaction, rolled back unless committed.
+#[async_trait(?Send)]
+pub(crate) trait Transaction: Sized {
+    type ObjectStore: ObjectStore;
+
+    /// Returns a store within the scope of the transaction.
+    fn object_store(&self, name: &str) -> Result<Self::ObjectStore, IndexDbAggregateError>;
+
+    async fn commit(self) -> Result<(), IndexDbAggregateError>;
+
+    async fn abort(self) -> Result<(), IndexDbAggregateError>;
+}
+
+/// A store of records within a transaction.
+#[async_trait(?Send)]
+pub(crate) trait ObjectStore {
+    type Index: Index;
+
+    /// The key path of the records, `None` for a store with out-of-line keys.
+    fn key_path(&self) -> Result<Option<KeyPath>, IndexDbAggregateError>;
+
+    /// Returns a named index of the store.
+    fn index(&self, name: &str) -> Result<Self::Index, IndexDbAggregateError>;
+
+    async fn get(&self, key: &Key) -> Result<Option<Value>, IndexDbAggregateError>;
+
+    /// Returns the records within `range` in key order, at most `limit` of them.
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError>;
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError>;
+
+    /// Adds a record, failing with `OptimisticLock` if its key is already taken.
+    async fn add(&self, record: &Value) -> Result<(), IndexDbAggregateError>;
+
+    /// Adds records in order, failing with `OptimisticLock` if the key of any of them is
+    /// already taken, in which case none is added once the transaction is rolled back.
+    /// Backends that queue their requests issue them all before waiting for any.
+    async fn add_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        for record in records {
+            self.add(record).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds a record or replaces the one with the same key.
+    async fn put(&self, record: &Value) -> Result<(), IndexDbAggregateError>;
+
+    /// Puts records in order, issuing every request before waiting for any like
+    /// [`ObjectStore::add_all`].
+    async fn put_all(&self, records: &[Value]) -> Result<(), IndexDbAggregateError> {
+        for record in records {
+            self.put(record).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, range: &KeyRange) -> Result<(), IndexDbAggregateError>;
+
+    async fn clear(&self) -> Result<(), IndexDbAggregateError>;
+}
+
+/// An index of a store, listing its records by the value at the key path of the index then by
+/// their key. Records missing that value, or whose value is not a valid key, are not indexed.
+#[async_trait(?Send)]
+pub(crate) trait Index {
+    /// Returns the records whose indexed value is within `range` in index order, at most
+    /// `limit` of them.
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError>;
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError>;
+
+    /// Returns the last record in index order.
+    async fn last(&self) -> Result<Option<Value>, IndexDbAggregateError>;
+}
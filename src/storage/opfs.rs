@@ -0,0 +1,114 @@
+//! The files of the log backend in the browser, kept in the origin private file system and
+//! written through synchronous access handles.
+
+use super::log::{LogFile, LogFiles};
+use crate::IndexDbAggregateError;
+use js_sys::Promise;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetFileOptions,
+    FileSystemReadWriteOptions, FileSystemSyncAccessHandle, WorkerGlobalScope,
+};
+
+/// A file of the origin private file system.
+pub(crate) struct OpfsFile(FileSystemSyncAccessHandle);
+
+/// Opens the segment, the offset index and the compaction file of the log `name`, creating
+/// them if needed.
+///
+/// Synchronous access handles can only be created in a dedicated worker, and a file only has
+/// one at a time, which the handle of the log keeps for the rest of the worker's life.
+pub(crate) async fn open_files(name: &str) -> Result<LogFiles, IndexDbAggregateError> {
+    let scope: WorkerGlobalScope = js_sys::global().dyn_into().map_err(|_| {
+        IndexDbAggregateError::ConnectionError(
+            "the OPFS backend is only available in a dedicated worker".to_string(),
+        )
+    })?;
+    let root: FileSystemDirectoryHandle =
+        resolve(scope.navigator().storage().get_directory()).await?;
+    Ok((
+        open(&root, &format!("{name}.log")).await?,
+        open(&root, &format!("{name}.idx")).await?,
+        open(&root, &format!("{name}.cmp")).await?,
+    ))
+}
+
+async fn open(
+    root: &FileSystemDirectoryHandle,
+    name: &str,
+) -> Result<OpfsFile, IndexDbAggregateError> {
+    let options = FileSystemGetFileOptions::new();
+    options.set_create(true);
+    let file: FileSystemFileHandle =
+        resolve(root.get_file_handle_with_options(name, &options)).await?;
+    Ok(OpfsFile(resolve(file.create_sync_access_handle()).await?))
+}
+
+async fn resolve<T: JsCast>(promise: Promise) -> Result<T, IndexDbAggregateError> {
+    let value = JsFuture::from(promise).await.map_err(connection_error)?;
+    value.dyn_into().map_err(connection_error)
+}
+
+/// Reads or writes at `offset`.
+fn at(offset: u64) -> FileSystemReadWriteOptions {
+    let options = FileSystemReadWriteOptions::new();
+    options.set_at(offset as f64);
+    options
+}
+
+impl LogFile for OpfsFile {
+    fn size(&mut self) -> Result<u64, IndexDbAggregateError> {
+        Ok(self.0.get_size().map_err(error)? as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), IndexDbAggregateError> {
+        let read = self
+            .0
+            .read_with_u8_array_and_options(buffer, &at(offset))
+            .map_err(error)?;
+        if read as usize != buffer.len() {
+            return Err(IndexDbAggregateError::UnknownError(
+                "unexpected end of file".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8]) -> Result<(), IndexDbAggregateError> {
+        let written = self
+            .0
+            .write_with_u8_array_and_options(bytes, &at(offset))
+            .map_err(error)?;
+        if written as usize != bytes.len() {
+            return Err(IndexDbAggregateError::UnknownError(
+                "the file is full".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> Result<(), IndexDbAggregateError> {
+        self.0.truncate_with_f64(size as f64).map_err(error)
+    }
+
+    fn flush(&mut self) -> Result<(), IndexDbAggregateError> {
+        self.0.flush().map_err(error)
+    }
+}
+
+/// The message of a JS error.
+fn message(err: JsValue) -> String {
+    match err.dyn_ref::<js_sys::Error>() {
+        Some(err) => err.message().into(),
+        None => format!("{err:?}"),
+    }
+}
+
+fn error(err: JsValue) -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError(message(err))
+}
+
+fn connection_error(err: JsValue) -> IndexDbAggregateError {
+    IndexDbAggregateError::ConnectionError(message(err))
+}
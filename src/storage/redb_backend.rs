@@ -0,0 +1,651 @@
+//! The backend used outside the browser, storing the object stores in an embedded
+//! [redb](https://docs.rs/redb) database, on file or in memory.
+//!
+//! Every object store is a table of JSON records keyed by their encoded key, see
+//! [`encode`](super::encoding::encode). Each index is a table of its own, keyed by the encoded
+//! `[value, key]` of its entries, and is maintained along with its store. The schema of the
+//! stores is kept in the database.
+
+use super::encoding::{encode, index_bounds, index_entry, is_empty, store_bounds, Bounds};
+use super::{
+    backfill, Database, Index, Key, KeyRange, ObjectStore, StoreSchema, Transaction,
+    TransactionMode,
+};
+use crate::event_repository::base_schema;
+use crate::IndexDbAggregateError;
+use async_trait::async_trait;
+use redb::backends::InMemoryBackend;
+use redb::{ReadTransaction, ReadableTable, TableDefinition, TableError, WriteTransaction};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The type of the keys and values of every table, encoded keys and JSON records.
+type Bytes = &'static [u8];
+
+/// An encoded key and its value, as read from a table.
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// Table holding the [`StoreSchema`] of every store, keyed by store name.
+const SCHEMA_TABLE: &str = "\0schema";
+
+/// Prefix marking the names of in-memory databases, see [`memory_db_name`].
+const MEMORY_PREFIX: &str = ":memory:";
+
+/// The name under which the in-memory database `db_name` is shared by the repositories
+/// created with `in_memory`.
+pub(crate) fn memory_db_name(db_name: &str) -> String {
+    format!("{MEMORY_PREFIX}{db_name}")
+}
+
+/// Opens the database `db_name` with the stores of `schema`, then runs `f` against it and
+/// hands its result back.
+///
+/// redb is synchronous, so `f` runs to completion before this returns, much like the
+/// IndexedDB backend spawns it right away.
+pub(crate) fn run_in<T, F, Fut>(
+    db_name: String,
+    schema: Vec<StoreSchema>,
+    f: F,
+) -> impl Future<Output = Result<T, IndexDbAggregateError>>
+where
+    T: 'static,
+    F: FnOnce(RedbDatabase) -> Fut + 'static,
+    Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+{
+    let result = futures::executor::block_on(async move {
+        let db = connect(&db_name, &schema).await?;
+        f(db).await
+    });
+    std::future::ready(result)
+}
+
+/// An open redb database.
+pub(crate) struct RedbDatabase {
+    database: Arc<redb::Database>,
+    schema: Rc<HashMap<String, StoreSchema>>,
+}
+
+/// Opens the database, creating the stores and indexes of `schema` that it does not have yet.
+/// Indexes are filled with the records already in their store, and the events of an upgraded
+/// database are given the global positions they miss.
+async fn connect(
+    db_name: &str,
+    schema: &[StoreSchema],
+) -> Result<RedbDatabase, IndexDbAggregateError> {
+    let database = open(db_name)?;
+    let declared: Vec<StoreSchema> = base_schema().into_iter().chain(schema.to_vec()).collect();
+
+    let mut stored = read_schema(&database.begin_read()?)?;
+    let upgraded = !declared.iter().all(|store| has_store(&stored, store));
+    if upgraded {
+        let transaction = database.begin_write()?;
+        stored = read_schema(&transaction)?;
+        for store in &declared {
+            create_store(&transaction, &mut stored, store)?;
+        }
+        transaction.commit()?;
+    }
+
+    let database = RedbDatabase {
+        database,
+        schema: Rc::new(stored),
+    };
+    if upgraded {
+        backfill(&database, &declared).await?;
+    }
+    Ok(database)
+}
+
+/// Returns the database `db_name`, stored in the file `{db_name}.redb` unless it is an
+/// in-memory database.
+///
+/// redb refuses to open a file twice, so the database is opened on first use and its handle is
+/// shared by every repository for the rest of the process. In-memory databases are likewise
+/// kept, and their content with them, until the process exits.
+fn open(db_name: &str) -> Result<Arc<redb::Database>, IndexDbAggregateError> {
+    static DATABASES: OnceLock<Mutex<HashMap<String, Arc<redb::Database>>>> = OnceLock::new();
+
+    let mut databases = DATABASES
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|err| IndexDbAggregateError::ConnectionError(err.to_string()))?;
+    if let Some(database) = databases.get(db_name) {
+        return Ok(database.clone());
+    }
+    let database = Arc::new(if db_name.starts_with(MEMORY_PREFIX) {
+        redb::Database::builder().create_with_backend(InMemoryBackend::new())?
+    } else {
+        redb::Database::create(format!("{db_name}.redb"))?
+    });
+    databases.insert(db_name.to_string(), database.clone());
+    Ok(database)
+}
+
+fn table(name: &str) -> TableDefinition<'_, Bytes, Bytes> {
+    TableDefinition::new(name)
+}
+
+/// The table holding the entries of an index.
+fn index_table(store_name: &str, index_name: &str) -> String {
+    format!("{store_name}\0{index_name}")
+}
+
+fn read_schema(
+    transaction: &impl SchemaSource,
+) -> Result<HashMap<String, StoreSchema>, IndexDbAggregateError> {
+    transaction.stores()
+}
+
+/// A transaction the schema can be read from.
+trait SchemaSource {
+    fn stores(&self) -> Result<HashMap<String, StoreSchema>, IndexDbAggregateError>;
+}
+
+impl SchemaSource for ReadTransaction {
+    fn stores(&self) -> Result<HashMap<String, StoreSchema>, IndexDbAggregateError> {
+        match self.open_table(table(SCHEMA_TABLE)) {
+            Ok(schema) => parse_schema(&schema),
+            Err(TableError::TableDoesNotExist(_)) => Ok(HashMap::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl SchemaSource for WriteTransaction {
+    fn stores(&self) -> Result<HashMap<String, StoreSchema>, IndexDbAggregateError> {
+        parse_schema(&self.open_table(table(SCHEMA_TABLE))?)
+    }
+}
+
+fn parse_schema(
+    schema: &impl ReadableTable<Bytes, Bytes>,
+) -> Result<HashMap<String, StoreSchema>, IndexDbAggregateError> {
+    schema
+        .iter()?
+        .map(|entry| {
+            let (_, value) = entry?;
+            let store: StoreSchema = serde_json::from_slice(value.value())?;
+            Ok((store.name.clone(), store))
+        })
+        .collect()
+}
+
+/// Whether the store and all of its indexes exist.
+fn has_store(stored: &HashMap<String, StoreSchema>, store: &StoreSchema) -> bool {
+    stored.get(&store.name).is_some_and(|existing| {
+        store.indexes.iter().all(|(name, _)| {
+            existing
+                .indexes
+                .iter()
+                .any(|(existing, _)| existing == name)
+        })
+    })
+}
+
+/// Creates the store unless it exists, along with its missing indexes.
+fn create_store(
+    transaction: &WriteTransaction,
+    stored: &mut HashMap<String, StoreSchema>,
+    store: &StoreSchema,
+) -> Result<(), IndexDbAggregateError> {
+    if has_store(stored, store) {
+        return Ok(());
+    }
+    let existing = stored
+        .entry(store.name.clone())
+        .or_insert_with(|| StoreSchema {
+            indexes: Vec::new(),
+            ..store.clone()
+        });
+    let records = transaction.open_table(table(&existing.name))?;
+    for (name, key_path) in &store.indexes {
+        if existing
+            .indexes
+            .iter()
+            .any(|(existing, _)| existing == name)
+        {
+            continue;
+        }
+        let mut entries = transaction.open_table(table(&index_table(&existing.name, name)))?;
+        for entry in records.iter()? {
+            let (key, value) = entry?;
+            let record: Value = serde_json::from_slice(value.value())?;
+            if let Some(index_key) = key_path.extract(&record) {
+                entries.insert(index_entry(&index_key, key.value()).as_slice(), key.value())?;
+            }
+        }
+        existing.indexes.push((name.clone(), key_path.clone()));
+    }
+    transaction.open_table(table(SCHEMA_TABLE))?.insert(
+        existing.name.as_bytes(),
+        serde_json::to_vec(existing)?.as_slice(),
+    )?;
+    Ok(())
+}
+
+impl Database for RedbDatabase {
+    type Transaction = RedbTransaction;
+
+    fn transaction<S: AsRef<str>>(
+        &self,
+        store_names: &[S],
+        mode: TransactionMode,
+    ) -> Result<RedbTransaction, IndexDbAggregateError> {
+        for name in store_names {
+            if !self.schema.contains_key(name.as_ref()) {
+                return Err(no_store(name.as_ref()));
+            }
+        }
+        let inner = match mode {
+            TransactionMode::ReadOnly => Inner::Read(self.database.begin_read()?),
+            TransactionMode::ReadWrite => Inner::Write(Box::new(self.database.begin_write()?)),
+        };
+        Ok(RedbTransaction {
+            inner: Rc::new(RefCell::new(Some(inner))),
+            schema: self.schema.clone(),
+        })
+    }
+}
+
+enum Inner {
+    Read(ReadTransaction),
+    Write(Box<WriteTransaction>),
+}
+
+/// A redb transaction, shared with the stores opened within it until it is committed or
+/// aborted.
+pub(crate) struct RedbTransaction {
+    inner: Rc<RefCell<Option<Inner>>>,
+    schema: Rc<HashMap<String, StoreSchema>>,
+}
+
+impl RedbTransaction {
+    fn finish(self) -> Result<Option<WriteTransaction>, IndexDbAggregateError> {
+        match self.inner.borrow_mut().take() {
+            Some(Inner::Write(transaction)) => Ok(Some(*transaction)),
+            Some(Inner::Read(_)) => Ok(None),
+            None => Err(finished()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Transaction for RedbTransaction {
+    type ObjectStore = RedbObjectStore;
+
+    fn object_store(&self, name: &str) -> Result<RedbObjectStore, IndexDbAggregateError> {
+        match self.schema.get(name) {
+            Some(schema) => Ok(RedbObjectStore {
+                inner: self.inner.clone(),
+                schema: schema.clone(),
+            }),
+            None => Err(no_store(name)),
+        }
+    }
+
+    async fn commit(self) -> Result<(), IndexDbAggregateError> {
+        if let Some(transaction) = self.finish()? {
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+
+    async fn abort(self) -> Result<(), IndexDbAggregateError> {
+        if let Some(transaction) = self.finish()? {
+            transaction.abort()?;
+        }
+        Ok(())
+    }
+}
+
+/// A store within a redb transaction.
+pub(crate) struct RedbObjectStore {
+    inner: Rc<RefCell<Option<Inner>>>,
+    schema: StoreSchema,
+}
+
+impl RedbObjectStore {
+    /// Runs `f` against the table `name`, along with the table of the `records` it indexes if
+    /// any, within a transaction of either mode.
+    fn read<T>(
+        &self,
+        name: &str,
+        records: Option<&str>,
+        f: impl FnOnce(&Tables) -> Result<T, IndexDbAggregateError>,
+    ) -> Result<T, IndexDbAggregateError> {
+        let inner = self.inner.borrow();
+        let tables = match inner.as_ref().ok_or_else(finished)? {
+            Inner::Read(transaction) => Tables::Read(
+                transaction.open_table(table(name))?,
+                records
+                    .map(|records| transaction.open_table(table(records)))
+                    .transpose()?,
+            ),
+            Inner::Write(transaction) => Tables::Write(
+                transaction.open_table(table(name))?,
+                records
+                    .map(|records| transaction.open_table(table(records)))
+                    .transpose()?,
+            ),
+        };
+        f(&tables)
+    }
+
+    /// Runs `f` within the write transaction.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&WriteTransaction) -> Result<T, IndexDbAggregateError>,
+    ) -> Result<T, IndexDbAggregateError> {
+        match self.inner.borrow().as_ref().ok_or_else(finished)? {
+            Inner::Write(transaction) => f(transaction),
+            Inner::Read(_) => Err(IndexDbAggregateError::UnknownError(
+                "the transaction is read-only".to_string(),
+            )),
+        }
+    }
+
+    /// Stores `record` under `key`, replacing `previous`, and updates the indexes accordingly.
+    fn store(
+        &self,
+        transaction: &WriteTransaction,
+        key: &[u8],
+        previous: Option<&Value>,
+        record: Option<&Value>,
+    ) -> Result<(), IndexDbAggregateError> {
+        for (name, key_path) in &self.schema.indexes {
+            let mut entries =
+                transaction.open_table(table(&index_table(&self.schema.name, name)))?;
+            if let Some(index_key) = previous.and_then(|previous| key_path.extract(previous)) {
+                entries.remove(index_entry(&index_key, key).as_slice())?;
+            }
+            if let Some(index_key) = record.and_then(|record| key_path.extract(record)) {
+                entries.insert(index_entry(&index_key, key).as_slice(), key)?;
+            }
+        }
+        let mut records = transaction.open_table(table(&self.schema.name))?;
+        match record {
+            Some(record) => records.insert(key, serde_json::to_vec(record)?.as_slice())?,
+            None => records.remove(key)?,
+        };
+        Ok(())
+    }
+
+    /// Encodes the key of `record`, failing like IndexedDB if it has none.
+    fn record_key(&self, record: &Value) -> Result<Vec<u8>, IndexDbAggregateError> {
+        match self.schema.key_path.extract(record) {
+            Some(key) => Ok(encode(&key)),
+            None => Err(IndexDbAggregateError::DeserializationError(format!(
+                "record without a valid key in store {}",
+                self.schema.name
+            ))),
+        }
+    }
+
+    fn get_record(
+        transaction: &WriteTransaction,
+        store_name: &str,
+        key: &[u8],
+    ) -> Result<Option<Value>, IndexDbAggregateError> {
+        match transaction.open_table(table(store_name))?.get(key)? {
+            Some(value) => Ok(Some(serde_json::from_slice(value.value())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ObjectStore for RedbObjectStore {
+    type Index = RedbIndex;
+
+    fn key_path(&self) -> Result<Option<super::KeyPath>, IndexDbAggregateError> {
+        Ok(Some(self.schema.key_path.clone()))
+    }
+
+    fn index(&self, name: &str) -> Result<RedbIndex, IndexDbAggregateError> {
+        if !self.schema.indexes.iter().any(|(index, _)| index == name) {
+            return Err(IndexDbAggregateError::UnknownError(format!(
+                "no index {name} in store {}",
+                self.schema.name
+            )));
+        }
+        Ok(RedbIndex {
+            inner: self.inner.clone(),
+            store_name: self.schema.name.clone(),
+            table_name: index_table(&self.schema.name, name),
+        })
+    }
+
+    async fn get(&self, key: &Key) -> Result<Option<Value>, IndexDbAggregateError> {
+        let key = encode(key);
+        self.read(&self.schema.name, None, |tables| tables.get(key.as_slice()))
+    }
+
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.read(&self.schema.name, None, |tables| {
+            tables
+                .entries(&bounds, limit)?
+                .into_iter()
+                .map(|(_, value)| Ok(serde_json::from_slice(&value)?))
+                .collect()
+        })
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.read(&self.schema.name, None, |tables| tables.count(&bounds))
+    }
+
+    async fn add(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        let key = self.record_key(record)?;
+        self.write(|transaction| {
+            if Self::get_record(transaction, &self.schema.name, &key)?.is_some() {
+                return Err(IndexDbAggregateError::OptimisticLock);
+            }
+            self.store(transaction, &key, None, Some(record))
+        })
+    }
+
+    async fn put(&self, record: &Value) -> Result<(), IndexDbAggregateError> {
+        let key = self.record_key(record)?;
+        self.write(|transaction| {
+            let previous = Self::get_record(transaction, &self.schema.name, &key)?;
+            self.store(transaction, &key, previous.as_ref(), Some(record))
+        })
+    }
+
+    async fn delete(&self, range: &KeyRange) -> Result<(), IndexDbAggregateError> {
+        let bounds = store_bounds(range);
+        self.write(|transaction| {
+            let removed = {
+                let records = transaction.open_table(table(&self.schema.name))?;
+                entries(&records, &bounds, None)?
+            };
+            for (key, value) in removed {
+                let previous: Value = serde_json::from_slice(&value)?;
+                self.store(transaction, &key, Some(&previous), None)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn clear(&self) -> Result<(), IndexDbAggregateError> {
+        self.write(|transaction| {
+            transaction
+                .open_table(table(&self.schema.name))?
+                .retain(|_, _| false)?;
+            for (name, _) in &self.schema.indexes {
+                transaction
+                    .open_table(table(&index_table(&self.schema.name, name)))?
+                    .retain(|_, _| false)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An index of a store within a redb transaction.
+pub(crate) struct RedbIndex {
+    inner: Rc<RefCell<Option<Inner>>>,
+    store_name: String,
+    table_name: String,
+}
+
+impl RedbIndex {
+    fn store(&self) -> RedbObjectStore {
+        RedbObjectStore {
+            inner: self.inner.clone(),
+            schema: StoreSchema {
+                name: self.store_name.clone(),
+                key_path: super::KeyPath::Array(Vec::new()),
+                indexes: Vec::new(),
+            },
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Index for RedbIndex {
+    async fn get_all(
+        &self,
+        range: &KeyRange,
+        limit: Option<u32>,
+    ) -> Result<Vec<Value>, IndexDbAggregateError> {
+        let bounds = index_bounds(range);
+        self.store()
+            .read(&self.table_name, Some(&self.store_name), |tables| {
+                tables
+                    .entries(&bounds, limit)?
+                    .into_iter()
+                    .map(|(_, key)| tables.get_record(&key))
+                    .collect()
+            })
+    }
+
+    async fn count(&self, range: &KeyRange) -> Result<u32, IndexDbAggregateError> {
+        let bounds = index_bounds(range);
+        self.store()
+            .read(&self.table_name, Some(&self.store_name), |tables| {
+                tables.count(&bounds)
+            })
+    }
+
+    async fn last(&self) -> Result<Option<Value>, IndexDbAggregateError> {
+        self.store().read(
+            &self.table_name,
+            Some(&self.store_name),
+            |tables| match tables.last_value()? {
+                Some(key) => tables.get_record(&key).map(Some),
+                None => Ok(None),
+            },
+        )
+    }
+}
+
+/// The tables opened for reading: a store or an index, then the store an index points to.
+enum Tables<'a> {
+    Read(
+        redb::ReadOnlyTable<Bytes, Bytes>,
+        Option<redb::ReadOnlyTable<Bytes, Bytes>>,
+    ),
+    Write(
+        redb::Table<'a, Bytes, Bytes>,
+        Option<redb::Table<'a, Bytes, Bytes>>,
+    ),
+}
+
+impl Tables<'_> {
+    /// Reads a record of the first table.
+    fn get(&self, key: &[u8]) -> Result<Option<Value>, IndexDbAggregateError> {
+        match self {
+            Tables::Read(table, _) => get(table, key),
+            Tables::Write(table, _) => get(table, key),
+        }
+    }
+
+    /// Reads the record of the store an index entry points to.
+    fn get_record(&self, key: &[u8]) -> Result<Value, IndexDbAggregateError> {
+        let record = match self {
+            Tables::Read(_, Some(table)) => get(table, key),
+            Tables::Write(_, Some(table)) => get(table, key),
+            _ => Ok(None),
+        }?;
+        record.ok_or_else(|| {
+            IndexDbAggregateError::UnknownError("index entry without a record".to_string())
+        })
+    }
+
+    fn entries(
+        &self,
+        bounds: &Bounds,
+        limit: Option<u32>,
+    ) -> Result<Vec<Entry>, IndexDbAggregateError> {
+        match self {
+            Tables::Read(table, _) => entries(table, bounds, limit),
+            Tables::Write(table, _) => entries(table, bounds, limit),
+        }
+    }
+
+    fn count(&self, bounds: &Bounds) -> Result<u32, IndexDbAggregateError> {
+        Ok(self.entries(bounds, None)?.len() as u32)
+    }
+
+    fn last_value(&self) -> Result<Option<Vec<u8>>, IndexDbAggregateError> {
+        let last = match self {
+            Tables::Read(table, _) => table.last()?,
+            Tables::Write(table, _) => table.last()?,
+        };
+        Ok(last.map(|(_, value)| value.value().to_vec()))
+    }
+}
+
+fn get(
+    table: &impl ReadableTable<Bytes, Bytes>,
+    key: &[u8],
+) -> Result<Option<Value>, IndexDbAggregateError> {
+    match table.get(key)? {
+        Some(value) => Ok(Some(serde_json::from_slice(value.value())?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the entries within `bounds` in key order, at most `limit` of them.
+fn entries(
+    table: &impl ReadableTable<Bytes, Bytes>,
+    bounds: &Bounds,
+    limit: Option<u32>,
+) -> Result<Vec<Entry>, IndexDbAggregateError> {
+    // An empty range selects nothing, where redb would panic
+    if is_empty(bounds) {
+        return Ok(Vec::new());
+    }
+
+    let bounds = (
+        bounds.0.as_ref().map(Vec::as_slice),
+        bounds.1.as_ref().map(Vec::as_slice),
+    );
+    table
+        .range::<&[u8]>(bounds)?
+        .take(limit.map_or(usize::MAX, |limit| limit as usize))
+        .map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.value().to_vec(), value.value().to_vec()))
+        })
+        .collect()
+}
+
+fn no_store(name: &str) -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError(format!("no object store {name}"))
+}
+
+fn finished() -> IndexDbAggregateError {
+    IndexDbAggregateError::UnknownError("the transaction has finished".to_string())
+}
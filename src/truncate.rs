@@ -0,0 +1,59 @@
+use crate::event_repository::stream_range_before;
+use crate::storage::{compact, Database, ObjectStore, Transaction, TransactionMode};
+use crate::{IndexDbAggregateError, IndexDbEventRepository};
+use cqrs_es::Aggregate;
+
+impl IndexDbEventRepository {
+    /// Deletes the events of an aggregate instance that precede `sequence`.
+    ///
+    /// Truncated streams can only be loaded from a snapshot: the truncated events are no
+    /// longer returned by `get_events`, while `get_snapshot` followed by `get_last_events`
+    /// keep working as long as the snapshot covers them. To truncate automatically as
+    /// snapshots are persisted, see [`IndexDbEventRepository::with_snapshot_truncation`].
+    pub async fn truncate_before<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        sequence: usize,
+    ) -> Result<(), IndexDbAggregateError> {
+        let store_name = self.store_name.clone();
+        let aggregate_type = A::aggregate_type();
+        let aggregate_id = aggregate_id.to_string();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&store_name], TransactionMode::ReadWrite)?;
+            let store = transaction.object_store(&store_name)?;
+            truncate_events(&store, &aggregate_type, &aggregate_id, sequence).await?;
+            transaction.commit().await
+        })
+        .await
+    }
+
+    /// Reclaims the space taken by the records overwritten or deleted, e.g. by
+    /// [`truncate_before`](IndexDbEventRepository::truncate_before), when the repository keeps
+    /// its events in an [append-only log](IndexDbEventRepository::opfs). Returns whether the
+    /// log was compacted, which only happens once the records left take less than half of it.
+    ///
+    /// The other backends reclaim the space on their own, and are left as they are. To compact
+    /// in the background, see `LeaderElection::with_compaction` in the browser.
+    pub async fn compact(&self) -> Result<bool, IndexDbAggregateError> {
+        self.run(|db| async move { compact(&db) }).await
+    }
+}
+
+pub(crate) async fn truncate_events(
+    store: &impl ObjectStore,
+    aggregate_type: &str,
+    aggregate_id: &str,
+    before_sequence: usize,
+) -> Result<(), IndexDbAggregateError> {
+    if before_sequence > 1 {
+        store
+            .delete(&stream_range_before(
+                aggregate_type,
+                aggregate_id,
+                before_sequence,
+            ))
+            .await?;
+    }
+    Ok(())
+}
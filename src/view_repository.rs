@@ -1,62 +1,401 @@
+use crate::index_range::lookup;
+use crate::projection_runner::Replayer;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::memory_db_name;
+use crate::storage::{
+    log_db_name, run_in, Database, Db, Index, Key, KeyPath, KeyRange, ObjectStore, StoreSchema,
+    Transaction, TransactionMode,
+};
+use crate::{IndexDbAggregateError, IndexDbEventRepository, IndexRange};
+use async_trait::async_trait;
+use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
+use cqrs_es::{Aggregate, View};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
-// use async_trait::async_trait;
-// use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
-// use cqrs_es::{Aggregate, View};
+/// A view as stored in its object store, keyed by `view_id`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ViewRecord {
+    pub(crate) view_id: String,
+    pub(crate) version: i64,
+    pub(crate) payload: Value,
+}
 
-// use crate::error::IndexDbAggregateError;
+/// The last view of a page read from an index, to resume reading after it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewCursor {
+    pub value: Value,
+    pub view_id: String,
+}
 
-// /// A postgres backed query repository for use in backing a `GenericQuery`.
+/// Where a listing of the views resumes, after the last view of the previous page.
+///
+/// The cursor is opaque, but serializable so that it can be kept between sessions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ViewListCursor {
+    view_id: String,
+}
+
+/// A page of views read from an index, ordered by indexed value then view id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewPage<V> {
+    pub views: Vec<V>,
+    /// The cursor to pass as `after` to read the next page, `None` once the last page is read.
+    pub next: Option<ViewCursor>,
+}
+
+/// A page of a view store, listed by view id.
+pub struct ViewList<V> {
+    pub views: Vec<(String, V, ViewContext)>,
+    /// The cursor to pass as `after` to read the next page, `None` once the last page is read.
+    pub next: Option<ViewListCursor>,
+}
+
+/// An IndexedDB backed query repository for use in backing a `GenericQuery`, stored in redb
+/// outside the browser.
 pub struct IndexDbViewRepository<V, A> {
-    insert_sql: String,
-    update_sql: String,
-    select_sql: String,
-    // pool: Pool<IndexDb>,
+    pub(crate) db_name: String,
+    pub(crate) view_name: String,
+    /// The declared indexes, as `(name, fields)`.
+    pub(crate) indexes: Vec<(String, Vec<String>)>,
+    pub(crate) schema_version: u32,
+    /// The store of the events the views are rebuilt from.
+    pub(crate) event_store: String,
+    /// Whether the schema version of the views was checked since the repository was created.
+    schema_checked: Mutex<bool>,
     _phantom: PhantomData<(V, A)>,
 }
 
-// impl<V, A> IndexDbViewRepository<V, A>
-// where
-//     V: View<A>,
-//     A: Aggregate,
-// {
-//     /// Creates a new `IndexDbViewRepository` that will store serialized views in a IndexDb table named
-//     /// identically to the `view_name` value provided. This table should be created by the user
-//     /// before using this query repository (see `/db/init.sql` sql initialization file).
-//     ///
-//     /// ```
-//     /// # use cqrs_es::doc::MyAggregate;
-//     /// # use cqrs_es::persist::doc::MyView;
-//     /// use sqlx::{Pool, IndexDb};
-//     /// use postgres_es::IndexDbViewRepository;
-//     ///
-//     /// fn configure_view_repo(pool: Pool<IndexDb>) -> IndexDbViewRepository<MyView,MyAggregate> {
-//     ///     IndexDbViewRepository::new("my_view_table", pool)
-//     /// }
-//     /// ```
-//     pub fn new(view_name: &str) -> Self {
-//         todo!()
-//     }
-// }
-
-// #[async_trait]
-// impl<V, A> ViewRepository<V, A> for IndexDbViewRepository<V, A>
-// where
-//     V: View<A>,
-//     A: Aggregate,
-// {
-//     async fn load(&self, view_id: &str) -> Result<Option<V>, PersistenceError> {
-//         todo!()
-//     }
-
-//     async fn load_with_context(
-//         &self,
-//         view_id: &str,
-//     ) -> Result<Option<(V, ViewContext)>, PersistenceError> {
-//         todo!()
-//     }
-
-//     async fn update_view(&self, view: V, context: ViewContext) -> Result<(), PersistenceError> {
-//         todo!()
-//     }
-// }
+impl<V, A> IndexDbViewRepository<V, A>
+where
+    V: View<A> + 'static,
+    A: Aggregate + 'static,
+{
+    /// Creates a new `IndexDbViewRepository` that will store serialized views in an object
+    /// store named identically to the `view_name` value provided. The store is created the
+    /// first time the repository is used.
+    pub fn new(db_name: Option<String>, view_name: &str) -> Self {
+        Self {
+            db_name: db_name.unwrap_or("cqrs".to_string()),
+            view_name: view_name.to_string(),
+            indexes: Vec::new(),
+            schema_version: 0,
+            event_store: "events".to_string(),
+            schema_checked: Mutex::new(false),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates a new `IndexDbViewRepository` storing its views in the in-memory database
+    /// `db_name`, shared with the repositories created in memory under the same name. See
+    /// [`IndexDbEventRepository::in_memory`](crate::IndexDbEventRepository::in_memory).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn in_memory(db_name: &str, view_name: &str) -> Self {
+        Self::new(Some(memory_db_name(db_name)), view_name)
+    }
+
+    /// Creates a new `IndexDbViewRepository` storing its views in the append-only log
+    /// `db_name`. See [`IndexDbEventRepository::opfs`](crate::IndexDbEventRepository::opfs).
+    pub fn opfs(db_name: &str, view_name: &str) -> Self {
+        Self::new(Some(log_db_name(db_name)), view_name)
+    }
+
+    /// Declares the version of the serialized form of `V`, 0 by default. Bump it whenever a
+    /// change to `V` makes the stored views unreadable.
+    ///
+    /// The version is recorded along with the checkpoint of the views. When the repository is
+    /// first used, views recorded under another version are rebuilt from the events of the
+    /// [event store](IndexDbViewRepository::with_event_store) before anything is read, as a
+    /// `ProjectionRunner` would on its next run.
+    pub fn with_schema_version(mut self, schema_version: u32) -> Self {
+        self.schema_version = schema_version;
+        self
+    }
+
+    /// Sets the store of the events the views are rebuilt from when their
+    /// [schema version](IndexDbViewRepository::with_schema_version) changes, `"events"` by
+    /// default.
+    pub fn with_event_store(mut self, store_name: &str) -> Self {
+        self.event_store = store_name.to_string();
+        self
+    }
+
+    /// Declares an index named `name` on the given fields of the serialized view, each a dot
+    /// separated path such as `"assignee.id"`, which can then be searched with
+    /// [`IndexDbViewRepository::find_views`].
+    ///
+    /// Missing indexes are created, over the existing views too, the next time the database is
+    /// opened.
+    pub fn with_index(mut self, name: &str, fields: &[&str]) -> Self {
+        self.indexes.push((
+            name.to_string(),
+            fields.iter().map(|field| field.to_string()).collect(),
+        ));
+        self
+    }
+
+    /// Returns the views whose fields indexed by `index` fall within `range`, starting after
+    /// the view `after`.
+    ///
+    /// For an index over several fields, the bounds of `range` are arrays holding one value per
+    /// field. Views missing one of the fields, or whose value is not a valid IndexedDB key such
+    /// as a boolean or an object, are not indexed.
+    pub async fn find_views(
+        &self,
+        index: &str,
+        range: IndexRange,
+        after: Option<ViewCursor>,
+        limit: u32,
+    ) -> Result<ViewPage<V>, IndexDbAggregateError> {
+        let fields = match self.indexes.iter().find(|(name, _)| name == index) {
+            Some((_, fields)) => fields.clone(),
+            None => {
+                return Err(IndexDbAggregateError::UnknownError(format!(
+                    "no index {index} declared on view {}",
+                    self.view_name
+                )))
+            }
+        };
+        let view_name = self.view_name.clone();
+        let index = index.to_string();
+        let range = KeyRange::index(
+            &range,
+            fields.len(),
+            after
+                .as_ref()
+                .map(|after| (&after.value, after.view_id.as_str().into())),
+        )?;
+
+        let records = self
+            .run(move |db| async move {
+                let transaction = db.transaction(&[&view_name], TransactionMode::ReadOnly)?;
+                transaction
+                    .object_store(&view_name)?
+                    .index(&index)?
+                    .get_all(&range, Some(limit))
+                    .await?
+                    .into_iter()
+                    .map(|value| Ok(serde_json::from_value::<ViewRecord>(value)?))
+                    .collect::<Result<Vec<_>, IndexDbAggregateError>>()
+            })
+            .await?;
+
+        let mut views = Vec::with_capacity(records.len());
+        let mut last = None;
+        for record in records {
+            let indexed: Option<Vec<Value>> = fields
+                .iter()
+                .map(|field| lookup(&record.payload, field))
+                .collect();
+            last = indexed.map(|mut indexed| ViewCursor {
+                value: if indexed.len() == 1 {
+                    indexed.remove(0)
+                } else {
+                    Value::Array(indexed)
+                },
+                view_id: record.view_id,
+            });
+            views.push(serde_json::from_value(record.payload)?);
+        }
+
+        let next = last.filter(|_| views.len() == limit as usize);
+        Ok(ViewPage { views, next })
+    }
+
+    /// Lists the views of the store ordered by view id, starting after the cursor `after`
+    /// returned with the previous page.
+    pub async fn list(
+        &self,
+        after: Option<ViewListCursor>,
+        limit: u32,
+    ) -> Result<ViewList<V>, IndexDbAggregateError> {
+        let view_name = self.view_name.clone();
+
+        let (records, more) = self
+            .run(move |db| async move {
+                let transaction = db.transaction(&[&view_name], TransactionMode::ReadOnly)?;
+                let store = transaction.object_store(&view_name)?;
+                let range = match after {
+                    Some(after) => KeyRange::lower_bound(after.view_id.as_str().into(), true),
+                    None => KeyRange::all(),
+                };
+
+                // One record past the page tells whether another page follows
+                let mut records = store
+                    .get_all(&range, Some(limit.saturating_add(1)))
+                    .await?
+                    .into_iter()
+                    .map(|value| Ok(serde_json::from_value::<ViewRecord>(value)?))
+                    .collect::<Result<Vec<_>, IndexDbAggregateError>>()?;
+                let more = records.len() > limit as usize;
+                records.truncate(limit as usize);
+                Ok((records, more))
+            })
+            .await?;
+
+        let next = records
+            .last()
+            .filter(|_| more)
+            .map(|record| ViewListCursor {
+                view_id: record.view_id.clone(),
+            });
+        let views = records
+            .into_iter()
+            .map(|record| {
+                let view = serde_json::from_value(record.payload)?;
+                let context = ViewContext::new(record.view_id.clone(), record.version);
+                Ok((record.view_id, view, context))
+            })
+            .collect::<Result<_, IndexDbAggregateError>>()?;
+        Ok(ViewList { views, next })
+    }
+
+    /// Counts the views of the store.
+    pub async fn count(&self) -> Result<u32, IndexDbAggregateError> {
+        let view_name = self.view_name.clone();
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&view_name], TransactionMode::ReadOnly)?;
+            transaction
+                .object_store(&view_name)?
+                .count(&KeyRange::all())
+                .await
+        })
+        .await
+    }
+
+    /// The view store and its indexes.
+    pub(crate) fn schema(&self) -> Vec<StoreSchema> {
+        vec![StoreSchema {
+            name: self.view_name.clone(),
+            key_path: KeyPath::single("view_id"),
+            indexes: self
+                .indexes
+                .iter()
+                .map(|(name, fields)| {
+                    let mut key_path: Vec<String> = fields
+                        .iter()
+                        .map(|field| format!("payload.{field}"))
+                        .collect();
+                    key_path.push("view_id".to_string());
+                    (name.clone(), KeyPath::Array(key_path))
+                })
+                .collect(),
+        }]
+    }
+
+    /// Rebuilds the views if their schema version changed, unless it was already checked since
+    /// the repository was created.
+    async fn ensure_schema(&self) -> Result<(), IndexDbAggregateError> {
+        let mut checked = self.schema_checked.lock().await;
+        if *checked {
+            return Ok(());
+        }
+        let event_repository =
+            IndexDbEventRepository::new(Some(self.db_name.clone()), Some(self.event_store.clone()));
+        let projection = Arc::new(Self {
+            db_name: self.db_name.clone(),
+            view_name: self.view_name.clone(),
+            indexes: self.indexes.clone(),
+            schema_version: self.schema_version,
+            event_store: self.event_store.clone(),
+            schema_checked: Mutex::new(true),
+            _phantom: PhantomData,
+        });
+        Replayer::new(&event_repository, projection)
+            .upgrade()
+            .await?;
+        *checked = true;
+        Ok(())
+    }
+
+    /// Runs `f` against an open connection to the database and hands its result back, once the
+    /// views are [upgraded](IndexDbViewRepository::with_schema_version).
+    pub(crate) async fn run<T, F, Fut>(&self, f: F) -> Result<T, IndexDbAggregateError>
+    where
+        T: 'static,
+        F: FnOnce(Db) -> Fut + 'static,
+        Fut: Future<Output = Result<T, IndexDbAggregateError>> + 'static,
+    {
+        self.ensure_schema().await?;
+        run_in(self.db_name.clone(), self.schema(), f).await
+    }
+}
+
+#[async_trait]
+impl<V, A> ViewRepository<V, A> for IndexDbViewRepository<V, A>
+where
+    V: View<A> + 'static,
+    A: Aggregate + 'static,
+{
+    async fn load(&self, view_id: &str) -> Result<Option<V>, PersistenceError> {
+        Ok(self.load_with_context(view_id).await?.map(|(view, _)| view))
+    }
+
+    async fn load_with_context(
+        &self,
+        view_id: &str,
+    ) -> Result<Option<(V, ViewContext)>, PersistenceError> {
+        let view_name = self.view_name.clone();
+        let view_id = view_id.to_string();
+
+        let record = self
+            .run(move |db| async move {
+                let transaction = db.transaction(&[&view_name], TransactionMode::ReadOnly)?;
+                let value = transaction
+                    .object_store(&view_name)?
+                    .get(&Key::from(view_id.as_str()))
+                    .await?;
+                match value {
+                    Some(value) => Ok(Some(serde_json::from_value::<ViewRecord>(value)?)),
+                    None => Ok(None),
+                }
+            })
+            .await?;
+
+        match record {
+            Some(record) => {
+                let view =
+                    serde_json::from_value(record.payload).map_err(IndexDbAggregateError::from)?;
+                Ok(Some((
+                    view,
+                    ViewContext::new(record.view_id, record.version),
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn update_view(&self, view: V, context: ViewContext) -> Result<(), PersistenceError> {
+        let view_name = self.view_name.clone();
+        let record = ViewRecord {
+            view_id: context.view_instance_id,
+            version: context.version + 1,
+            payload: serde_json::to_value(&view).map_err(IndexDbAggregateError::from)?,
+        };
+
+        self.run(move |db| async move {
+            let transaction = db.transaction(&[&view_name], TransactionMode::ReadWrite)?;
+            let store = transaction.object_store(&view_name)?;
+
+            // The stored view must still be the one the update was computed from
+            let stored_version = match store.get(&Key::from(record.view_id.as_str())).await? {
+                Some(value) => serde_json::from_value::<ViewRecord>(value)?.version,
+                None => 0,
+            };
+            if stored_version != context.version {
+                transaction.abort().await?;
+                return Err(IndexDbAggregateError::OptimisticLock);
+            }
+
+            store.put(&serde_json::to_value(&record)?).await?;
+            transaction.commit().await
+        })
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,502 @@
+//! Running the repository in a dedicated worker, so that replays and serialization stay off the
+//! UI thread.
+//!
+//! The worker serves an [`IndexDbEventRepository`] with a [`WorkerHost`], and the page talks to
+//! it through a [`WorkerEventRepository`], which implements `PersistedEventRepository` by
+//! forwarding every call as a request envelope and waiting for the matching response envelope.
+
+use crate::event_repository::replay_stream;
+use crate::js_event::{JsEvent, JsSnapshot};
+use crate::{IndexDbAggregateError, IndexDbEventRepository};
+use async_trait::async_trait;
+use cqrs_es::persist::{
+    PersistedEventRepository, PersistenceError, ReplayStream, SerializedEvent, SerializedSnapshot,
+};
+use cqrs_es::Aggregate;
+use futures::channel::oneshot::{channel, Sender};
+use gloo_utils::format::JsValueSerdeExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{DedicatedWorkerGlobalScope, EventTarget, MessageEvent, MessagePort, Worker};
+
+/// A handler of the messages received on an endpoint.
+pub(crate) type OnMessage = Closure<dyn FnMut(MessageEvent)>;
+
+/// One end of a message channel: the worker as seen from the page, the global scope of the
+/// worker as seen from inside it, or a port of a `MessageChannel`.
+#[derive(Clone, Debug)]
+pub enum MessageEndpoint {
+    Worker(Worker),
+    Scope(DedicatedWorkerGlobalScope),
+    Port(MessagePort),
+}
+
+impl MessageEndpoint {
+    /// The global scope of the current dedicated worker.
+    pub fn worker_scope() -> Result<Self, IndexDbAggregateError> {
+        let scope = js_sys::global().dyn_into().map_err(|_| {
+            IndexDbAggregateError::ConnectionError("not in a dedicated worker".to_string())
+        })?;
+        Ok(MessageEndpoint::Scope(scope))
+    }
+
+    fn post(&self, message: &impl Serialize) -> Result<(), IndexDbAggregateError> {
+        let message = JsValue::from_serde(message)?;
+        let result = match self {
+            MessageEndpoint::Worker(worker) => worker.post_message(&message),
+            MessageEndpoint::Scope(scope) => scope.post_message(&message),
+            MessageEndpoint::Port(port) => port.post_message(&message),
+        };
+        result.map_err(|err| IndexDbAggregateError::ConnectionError(format!("{err:?}")))
+    }
+
+    fn target(&self) -> &EventTarget {
+        match self {
+            MessageEndpoint::Worker(worker) => worker.as_ref(),
+            MessageEndpoint::Scope(scope) => scope.as_ref(),
+            MessageEndpoint::Port(port) => port.as_ref(),
+        }
+    }
+
+    /// Adds `handler` to the listeners of the messages received on the endpoint, leaving the
+    /// others in place.
+    pub(crate) fn add_listener(&self, handler: &OnMessage) {
+        let _ = self
+            .target()
+            .add_event_listener_with_callback("message", handler.as_ref().unchecked_ref());
+        // Unlike setting `onmessage`, adding a listener does not start the port
+        if let MessageEndpoint::Port(port) = self {
+            port.start();
+        }
+    }
+
+    /// Removes `handler` from the listeners of the messages received on the endpoint.
+    pub(crate) fn remove_listener(&self, handler: &OnMessage) {
+        let _ = self
+            .target()
+            .remove_event_listener_with_callback("message", handler.as_ref().unchecked_ref());
+    }
+}
+
+impl From<Worker> for MessageEndpoint {
+    fn from(worker: Worker) -> Self {
+        MessageEndpoint::Worker(worker)
+    }
+}
+
+impl From<DedicatedWorkerGlobalScope> for MessageEndpoint {
+    fn from(scope: DedicatedWorkerGlobalScope) -> Self {
+        MessageEndpoint::Scope(scope)
+    }
+}
+
+impl From<MessagePort> for MessageEndpoint {
+    fn from(port: MessagePort) -> Self {
+        MessageEndpoint::Port(port)
+    }
+}
+
+/// Marks the envelopes of this protocol, telling them apart from the other messages the
+/// endpoint may carry.
+const PROTOCOL: &str = "indexdb-es/1";
+
+/// A request or a response, tagged with the id pairing them.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    protocol: String,
+    id: u32,
+    body: T,
+}
+
+impl<T> Envelope<T> {
+    fn new(id: u32, body: T) -> Self {
+        Self {
+            protocol: PROTOCOL.to_string(),
+            id,
+            body,
+        }
+    }
+}
+
+/// The envelope carried by `event`, `None` if the message is not one of this protocol.
+fn envelope(event: &MessageEvent) -> Option<Envelope<Value>> {
+    let envelope = event.data().into_serde::<Envelope<Value>>().ok()?;
+    (envelope.protocol == PROTOCOL).then_some(envelope)
+}
+
+/// A call of the page to the repository in the worker.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    GetEvents {
+        aggregate_type: String,
+        aggregate_id: String,
+        from_sequence: usize,
+    },
+    GetAllEvents {
+        aggregate_type: String,
+    },
+    GetSnapshot {
+        aggregate_type: String,
+        aggregate_id: String,
+    },
+    Persist {
+        aggregate_type: String,
+        events: Vec<JsEvent>,
+        snapshot_update: Option<(String, Value, usize)>,
+    },
+}
+
+/// What the repository in the worker returned, the error of a failed call included.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+enum Response {
+    Events { events: Vec<JsEvent> },
+    Snapshot { snapshot: Option<JsSnapshot> },
+    Persisted,
+    Failed { error: IndexDbAggregateError },
+}
+
+/// Serves an [`IndexDbEventRepository`] to the [`WorkerEventRepository`] at the other end of a
+/// message channel, for as long as it is kept alive.
+///
+/// ```no_run
+/// use indexdb_es::{IndexDbEventRepository, WorkerHost};
+///
+/// // In the entry point of the worker
+/// let host = WorkerHost::serve(IndexDbEventRepository::new(None, None)).unwrap();
+/// std::mem::forget(host);
+/// ```
+pub struct WorkerHost {
+    endpoint: MessageEndpoint,
+    on_message: OnMessage,
+}
+
+impl WorkerHost {
+    /// Serves `repository` to the page that started the current dedicated worker.
+    pub fn serve(repository: IndexDbEventRepository) -> Result<Self, IndexDbAggregateError> {
+        Ok(Self::serve_on(repository, MessageEndpoint::worker_scope()?))
+    }
+
+    /// Serves `repository` on `endpoint`. Requests are handled concurrently, each in a task of
+    /// its own.
+    pub fn serve_on(
+        repository: IndexDbEventRepository,
+        endpoint: impl Into<MessageEndpoint>,
+    ) -> Self {
+        let endpoint = endpoint.into();
+        let on_message = listen(Rc::new(repository), &endpoint, Rc::new(|| {}));
+        endpoint.add_listener(&on_message);
+
+        Self {
+            endpoint,
+            on_message,
+        }
+    }
+}
+
+impl Drop for WorkerHost {
+    fn drop(&mut self) {
+        self.endpoint.remove_listener(&self.on_message);
+    }
+}
+
+/// Handles the requests received on `endpoint`, each in a task of its own, and calls
+/// `on_write` after every successful write.
+pub(crate) fn listen(
+    repository: Rc<IndexDbEventRepository>,
+    endpoint: &MessageEndpoint,
+    on_write: Rc<dyn Fn()>,
+) -> OnMessage {
+    let endpoint = endpoint.clone();
+    Closure::new(move |event: MessageEvent| {
+        // Messages of other protocols are left to other listeners
+        let Some(envelope) = envelope(&event) else {
+            return;
+        };
+        let repository = repository.clone();
+        let endpoint = endpoint.clone();
+        let on_write = on_write.clone();
+        spawn_local(async move {
+            let body = match serde_json::from_value(envelope.body) {
+                Ok(request) => handle(&repository, request).await,
+                Err(err) => Err(err.into()),
+            };
+            if let Ok(Response::Persisted) = body {
+                on_write();
+            }
+            let body = body.unwrap_or_else(|error| Response::Failed { error });
+            let _ = endpoint.post(&Envelope::new(envelope.id, body));
+        });
+    })
+}
+
+async fn handle(
+    repository: &IndexDbEventRepository,
+    request: Request,
+) -> Result<Response, IndexDbAggregateError> {
+    match request {
+        Request::GetEvents {
+            aggregate_type,
+            aggregate_id,
+            from_sequence,
+        } => {
+            let events = repository
+                .select_events(&aggregate_type, &aggregate_id, from_sequence)
+                .await?;
+            Ok(Response::Events {
+                events: events.into_iter().map(JsEvent::from).collect(),
+            })
+        }
+        Request::GetAllEvents { aggregate_type } => {
+            let events = repository.select_all_events(&aggregate_type).await?;
+            Ok(Response::Events {
+                events: events.into_iter().map(JsEvent::from).collect(),
+            })
+        }
+        Request::GetSnapshot {
+            aggregate_type,
+            aggregate_id,
+        } => {
+            let snapshot = repository
+                .select_snapshot(&aggregate_type, &aggregate_id)
+                .await?;
+            Ok(Response::Snapshot {
+                snapshot: snapshot.map(|snapshot| JsSnapshot {
+                    aggregate_type,
+                    aggregate_id: snapshot.aggregate_id,
+                    last_sequence: snapshot.current_sequence,
+                    current_snapshot: snapshot.current_snapshot,
+                    payload: snapshot.aggregate,
+                }),
+            })
+        }
+        Request::Persist {
+            aggregate_type,
+            events,
+            snapshot_update,
+        } => {
+            let events: Vec<SerializedEvent> = events.into_iter().map(Into::into).collect();
+            match snapshot_update {
+                None => repository.write_events(&events).await?,
+                Some((aggregate_id, aggregate, current_snapshot)) => {
+                    repository
+                        .insert_with_snapshot(
+                            &aggregate_type,
+                            &events,
+                            aggregate_id,
+                            aggregate,
+                            current_snapshot,
+                        )
+                        .await?
+                }
+            }
+            Ok(Response::Persisted)
+        }
+    }
+}
+
+/// The connection of a [`WorkerEventRepository`] to its endpoint. Endpoints are not `Send`,
+/// so connections stay on the thread that opened them and repositories refer to them by id.
+struct Connection {
+    endpoint: MessageEndpoint,
+    pending: Rc<RefCell<HashMap<u32, Sender<Response>>>>,
+    next_id: u32,
+    on_message: OnMessage,
+}
+
+thread_local! {
+    static CONNECTIONS: RefCell<HashMap<u32, Connection>> = RefCell::new(HashMap::new());
+    static NEXT_CONNECTION: Cell<u32> = const { Cell::new(0) };
+}
+
+/// An event repository forwarding every call to an [`IndexDbEventRepository`] served by a
+/// [`WorkerHost`], typically in a dedicated worker. Errors come back as they were raised, so
+/// that a conflicting write still fails with an optimistic lock error.
+///
+/// ```no_run
+/// use indexdb_es::WorkerEventRepository;
+/// use web_sys::Worker;
+///
+/// let worker = Worker::new("./worker.js").unwrap();
+/// let repository = WorkerEventRepository::new(worker);
+/// ```
+pub struct WorkerEventRepository {
+    connection: u32,
+}
+
+impl WorkerEventRepository {
+    /// Creates a repository talking to the host at the other end of `endpoint`, such as the
+    /// worker it runs in.
+    pub fn new(endpoint: impl Into<MessageEndpoint>) -> Self {
+        let endpoint = endpoint.into();
+        let pending: Rc<RefCell<HashMap<u32, Sender<Response>>>> = Default::default();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+            let pending = pending.clone();
+            move |event: MessageEvent| {
+                let Some(envelope) = envelope(&event) else {
+                    return;
+                };
+                if let Some(sender) = pending.borrow_mut().remove(&envelope.id) {
+                    let response = serde_json::from_value(envelope.body)
+                        .unwrap_or_else(|err| Response::Failed { error: err.into() });
+                    let _ = sender.send(response);
+                }
+            }
+        });
+        endpoint.add_listener(&on_message);
+
+        let connection = NEXT_CONNECTION.with(|next| next.replace(next.get().wrapping_add(1)));
+        CONNECTIONS.with(|connections| {
+            connections.borrow_mut().insert(
+                connection,
+                Connection {
+                    endpoint,
+                    pending,
+                    next_id: 0,
+                    on_message,
+                },
+            )
+        });
+        Self { connection }
+    }
+
+    /// Posts `request` and returns the future of its response, which unlike the endpoint can be
+    /// sent across threads.
+    fn call(&self, request: Request) -> impl Future<Output = Result<Response, PersistenceError>> {
+        let receiver = CONNECTIONS.with(|connections| {
+            let mut connections = connections.borrow_mut();
+            let connection = connections.get_mut(&self.connection).ok_or_else(|| {
+                IndexDbAggregateError::ConnectionError("the connection is closed".to_string())
+            })?;
+            let id = connection.next_id;
+            connection.next_id = id.wrapping_add(1);
+
+            let (sender, receiver) = channel();
+            connection.pending.borrow_mut().insert(id, sender);
+            if let Err(err) = connection.endpoint.post(&Envelope::new(id, request)) {
+                connection.pending.borrow_mut().remove(&id);
+                return Err(err);
+            }
+            Ok(receiver)
+        });
+
+        async move {
+            match receiver?.await {
+                Ok(Response::Failed { error }) => Err(error.into()),
+                Ok(response) => Ok(response),
+                Err(_) => Err(IndexDbAggregateError::ConnectionError(
+                    "the connection closed before responding".to_string(),
+                )
+                .into()),
+            }
+        }
+    }
+
+    async fn events(
+        &self,
+        aggregate_type: String,
+        aggregate_id: &str,
+        from_sequence: usize,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        let request = Request::GetEvents {
+            aggregate_type,
+            aggregate_id: aggregate_id.to_string(),
+            from_sequence,
+        };
+        match self.call(request).await? {
+            Response::Events { events } => Ok(events.into_iter().map(Into::into).collect()),
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+impl Drop for WorkerEventRepository {
+    fn drop(&mut self) {
+        let connection =
+            CONNECTIONS.with(|connections| connections.borrow_mut().remove(&self.connection));
+        if let Some(connection) = connection {
+            connection.endpoint.remove_listener(&connection.on_message);
+        }
+    }
+}
+
+#[async_trait]
+impl PersistedEventRepository for WorkerEventRepository {
+    async fn get_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        self.events(A::aggregate_type(), aggregate_id, 0).await
+    }
+
+    async fn get_last_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+        last_sequence: usize,
+    ) -> Result<Vec<SerializedEvent>, PersistenceError> {
+        self.events(A::aggregate_type(), aggregate_id, last_sequence + 1)
+            .await
+    }
+
+    async fn get_snapshot<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<Option<SerializedSnapshot>, PersistenceError> {
+        let request = Request::GetSnapshot {
+            aggregate_type: A::aggregate_type(),
+            aggregate_id: aggregate_id.to_string(),
+        };
+        match self.call(request).await? {
+            Response::Snapshot { snapshot } => Ok(snapshot.map(Into::into)),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn persist<A: Aggregate>(
+        &self,
+        events: &[SerializedEvent],
+        snapshot_update: Option<(String, Value, usize)>,
+    ) -> Result<(), PersistenceError> {
+        let request = Request::Persist {
+            aggregate_type: A::aggregate_type(),
+            events: events.iter().cloned().map(JsEvent::from).collect(),
+            snapshot_update,
+        };
+        match self.call(request).await? {
+            Response::Persisted => Ok(()),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn stream_events<A: Aggregate>(
+        &self,
+        aggregate_id: &str,
+    ) -> Result<ReplayStream, PersistenceError> {
+        let events = self.events(A::aggregate_type(), aggregate_id, 0).await?;
+        replay_stream(events).await
+    }
+
+    async fn stream_all_events<A: Aggregate>(&self) -> Result<ReplayStream, PersistenceError> {
+        let request = Request::GetAllEvents {
+            aggregate_type: A::aggregate_type(),
+        };
+        match self.call(request).await? {
+            Response::Events { events } => {
+                replay_stream(events.into_iter().map(Into::into).collect()).await
+            }
+            _ => Err(unexpected()),
+        }
+    }
+}
+
+fn unexpected() -> PersistenceError {
+    IndexDbAggregateError::UnknownError("unexpected response from the worker".to_string()).into()
+}
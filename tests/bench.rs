@@ -0,0 +1,134 @@
+//! The throughput of the event store in the browser, in events per second, for inserts, reads
+//! and replays. Ignored by default, run them with `just bench`.
+//!
+//! Each result is logged as a JSON line, such as
+//! `{"bench":"read, by aggregate","events":10000,"seconds":0.5,"events_per_second":20000.0}`,
+//! so that runs can be compared.
+
+#![cfg(target_arch = "wasm32")]
+
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView, Tested,
+};
+use cqrs_es::persist::{PersistedEventRepository, SerializedEvent};
+use indexdb_es::{IndexDbEventRepository, IndexDbViewRepository, ProjectionRunner};
+use serde_json::json;
+use std::future::Future;
+use wasm_bindgen_test::*;
+
+mod tests {
+    pub(crate) mod testing;
+}
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const AGGREGATES: usize = 100;
+const EVENTS_PER_AGGREGATE: usize = 100;
+
+/// The events of the aggregate instance `id`.
+fn events(id: &str) -> Vec<SerializedEvent> {
+    (1..=EVENTS_PER_AGGREGATE)
+        .map(|sequence| {
+            let event = match sequence {
+                1 => TestEvent::Created(Created { id: id.to_string() }),
+                _ => TestEvent::Tested(Tested {
+                    test_name: format!("test {sequence}"),
+                }),
+            };
+            test_event_envelope(id, sequence, event)
+        })
+        .collect()
+}
+
+fn ids() -> Vec<String> {
+    (0..AGGREGATES).map(|i| format!("aggregate-{i}")).collect()
+}
+
+/// A repository of the database `db_name` holding the events of every aggregate instance.
+async fn populated(db_name: Option<String>) -> IndexDbEventRepository {
+    let event_repo = IndexDbEventRepository::new(db_name, None);
+    let all: Vec<SerializedEvent> = ids().iter().flat_map(|id| events(id)).collect();
+    event_repo
+        .insert_events::<TestAggregate>(&all)
+        .await
+        .unwrap();
+    event_repo
+}
+
+/// Runs `f`, which returns the number of events it processed, and logs its throughput.
+async fn measure<F, Fut>(name: &str, f: F)
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = usize>,
+{
+    let start = js_sys::Date::now();
+    let count = f().await;
+    let seconds = ((js_sys::Date::now() - start) / 1000.0).max(0.001);
+    let result = json!({
+        "bench": name,
+        "events": count,
+        "seconds": seconds,
+        "events_per_second": count as f64 / seconds,
+    });
+    console_log!("{result}");
+}
+
+#[wasm_bindgen_test]
+#[ignore]
+async fn insert_throughput() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    measure("insert, one transaction", || async {
+        let all: Vec<SerializedEvent> = ids().iter().flat_map(|id| events(id)).collect();
+        event_repo
+            .insert_events::<TestAggregate>(&all)
+            .await
+            .unwrap();
+        all.len()
+    })
+    .await;
+
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    measure("insert, one transaction per aggregate", || async {
+        for id in ids() {
+            event_repo
+                .insert_events::<TestAggregate>(&events(&id))
+                .await
+                .unwrap();
+        }
+        AGGREGATES * EVENTS_PER_AGGREGATE
+    })
+    .await;
+}
+
+#[wasm_bindgen_test]
+#[ignore]
+async fn read_throughput() {
+    let event_repo = populated(test_db_name()).await;
+    measure("read, by aggregate", || async {
+        let mut read = 0;
+        for id in ids() {
+            read += event_repo
+                .get_events::<TestAggregate>(&id)
+                .await
+                .unwrap()
+                .len();
+        }
+        read
+    })
+    .await;
+}
+
+#[wasm_bindgen_test]
+#[ignore]
+async fn replay_throughput() {
+    let db_name = test_db_name();
+    let event_repo = populated(db_name.clone()).await;
+    let runner = ProjectionRunner::new(
+        &event_repo,
+        IndexDbViewRepository::<TestView, TestAggregate>::new(db_name, "test_view"),
+    );
+    measure("replay, into a view", || async {
+        runner.rebuild(|_| {}).await.unwrap()
+    })
+    .await;
+}
@@ -0,0 +1,14 @@
+//! The tests that need a service worker, such as those of Background Sync.
+
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen_test::wasm_bindgen_test_configure;
+
+mod tests {
+    mod background_sync;
+    mod conformance;
+    mod outbox;
+    mod testing;
+}
+
+wasm_bindgen_test_configure!(run_in_service_worker);
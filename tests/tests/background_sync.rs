@@ -0,0 +1,97 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use indexdb_es::{BackgroundSync, IndexDbAggregateError, IndexDbEventRepository, Outbox};
+use js_sys::Reflect;
+use std::cell::Cell;
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::*;
+use web_sys::{EventTarget, ExtendableEvent};
+
+/// Fires a `sync` event tagged `tag`, as the browser would once online. The event is not
+/// trusted, so `waitUntil` throws and the flush runs without extending it: the promise the
+/// browser would retry on is left to [`failed_flush_retried`].
+fn fire_sync(tag: &str) {
+    let event = ExtendableEvent::new("sync").unwrap();
+    Reflect::set(&event, &JsValue::from_str("tag"), &JsValue::from_str(tag)).unwrap();
+    let scope: EventTarget = js_sys::global().unchecked_into();
+    scope.dispatch_event(&event).unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn flush_on_sync() {
+    let db_name = test_db_name();
+    let event_repo = IndexDbEventRepository::new(db_name.clone(), None);
+    for id in ["a", "b"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(
+                id,
+                1,
+                TestEvent::Created(Created { id: id.to_string() }),
+            )])
+            .await
+            .unwrap();
+    }
+
+    let pushed = Rc::new(Cell::new(0));
+    let sync = BackgroundSync::listen("outbox", Outbox::new(&event_repo, "server"), {
+        let pushed = pushed.clone();
+        move |events| {
+            pushed.set(pushed.get() + events.len());
+            async { Ok(()) }
+        }
+    })
+    .unwrap();
+
+    // Only the events of the registered tag flush the outbox
+    fire_sync("other");
+    fire_sync("outbox");
+    let outbox = Outbox::new(&IndexDbEventRepository::new(db_name, None), "server");
+    for _ in 0..100 {
+        if outbox.pending().await.unwrap() == 0 {
+            break;
+        }
+    }
+    assert_eq!(0, outbox.pending().await.unwrap());
+    assert_eq!(2, pushed.get());
+    assert_eq!(0, sync.flush().await.unwrap());
+}
+
+#[wasm_bindgen_test]
+async fn failed_flush_retried() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            "a",
+            1,
+            TestEvent::Created(Created {
+                id: "a".to_string(),
+            }),
+        )])
+        .await
+        .unwrap();
+
+    let online = Rc::new(Cell::new(false));
+    let sync = BackgroundSync::listen("retried", Outbox::new(&event_repo, "server"), {
+        let online = online.clone();
+        move |_| {
+            let result = match online.get() {
+                true => Ok(()),
+                false => Err(IndexDbAggregateError::ConnectionError(
+                    "offline".to_string(),
+                )),
+            };
+            async move { result }
+        }
+    })
+    .unwrap();
+
+    // A failed push rejects the flush, which the browser retries later, and keeps the events
+    assert!(sync.flush().await.is_err());
+    let outbox = Outbox::new(&event_repo, "server");
+    assert_eq!(0, outbox.checkpoint().await.unwrap());
+    assert_eq!(1, outbox.pending().await.unwrap());
+
+    online.set(true);
+    assert_eq!(1, sync.flush().await.unwrap());
+    assert_eq!(1, outbox.checkpoint().await.unwrap());
+}
@@ -0,0 +1,56 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use indexdb_es::IndexDbEventRepository;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn aggregate_catalog() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    for id in ["order-1", "order-2"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(
+                id,
+                1,
+                TestEvent::Created(Created { id: id.to_string() }),
+            )])
+            .await
+            .unwrap();
+    }
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            "order-1",
+            2,
+            TestEvent::Tested(Tested {
+                test_name: "a test was run".to_string(),
+            }),
+        )])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        2,
+        event_repo
+            .count_aggregates::<TestAggregate>()
+            .await
+            .unwrap()
+    );
+
+    let page = event_repo
+        .list_aggregates::<TestAggregate>(None, 1)
+        .await
+        .unwrap();
+    assert_eq!(1, page.len());
+    assert_eq!("order-1", page[0].aggregate_id);
+    assert_eq!(2, page[0].last_sequence);
+    assert_eq!(2, page[0].event_count);
+    assert!(page[0].created_at <= page[0].updated_at);
+
+    let page = event_repo
+        .list_aggregates::<TestAggregate>(Some(page[0].aggregate_id.clone()), 10)
+        .await
+        .unwrap();
+    assert_eq!(1, page.len());
+    assert_eq!("order-2", page[0].aggregate_id);
+    assert_eq!(1, page[0].event_count);
+}
@@ -0,0 +1,66 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use indexdb_es::{Clock, IndexDbEventRepository};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wasm_bindgen_test::*;
+
+/// A clock that only moves when told to.
+#[derive(Clone, Default)]
+struct TestClock(Arc<AtomicU64>);
+
+impl TestClock {
+    fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> f64 {
+        self.0.load(Ordering::SeqCst) as f64
+    }
+}
+
+fn created(id: &str) -> TestEvent {
+    TestEvent::Created(Created { id: id.to_string() })
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn events_between() {
+    let clock = TestClock::default();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None).with_clock(clock.clone());
+    for (now, id) in [(1_000, "a"), (2_000, "b"), (3_000, "c")] {
+        clock.set(now);
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, 1, created(id))])
+            .await
+            .unwrap();
+    }
+
+    let events = event_repo
+        .events_between(1_000.0, 3_000.0, None)
+        .await
+        .unwrap();
+    let recorded: Vec<_> = events
+        .iter()
+        .map(|e| (e.recorded_at, e.event.aggregate_id.as_str()))
+        .collect();
+    assert_eq!(vec![(1_000.0, "a"), (2_000.0, "b")], recorded);
+
+    let events = event_repo
+        .events_between(0.0, 10_000.0, Some("TestAggregate"))
+        .await
+        .unwrap();
+    assert_eq!(3, events.len());
+    let events = event_repo
+        .events_between(0.0, 10_000.0, Some("OtherAggregate"))
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+
+    // The catalog uses the same clock
+    let summaries = event_repo
+        .list_aggregates::<TestAggregate>(None, 10)
+        .await
+        .unwrap();
+    assert_eq!(1_000.0, summaries[0].created_at);
+}
@@ -0,0 +1,388 @@
+//! The behaviour every storage backend must share, checked against each of them.
+
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView, Tested,
+};
+use cqrs_es::persist::{
+    PersistedEventRepository, PersistenceError, SerializedEvent, ViewContext, ViewRepository,
+};
+use indexdb_es::{
+    DeleteOptions, IndexDbAggregateError, IndexDbEventRepository, IndexDbViewRepository, IndexRange,
+};
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+/// Opens the repositories of a database on the backend under test.
+pub(crate) struct Backend {
+    event_repo: fn(&str) -> IndexDbEventRepository,
+    view_repo: fn(&str, &str) -> IndexDbViewRepository<TestView, TestAggregate>,
+}
+
+impl Backend {
+    /// IndexedDB in the browser, a redb file everywhere else.
+    fn default() -> Self {
+        Self {
+            event_repo: |db_name| IndexDbEventRepository::new(Some(db_name.to_string()), None),
+            view_repo: |db_name, view_name| {
+                IndexDbViewRepository::new(Some(db_name.to_string()), view_name)
+            },
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn memory() -> Self {
+        Self {
+            event_repo: IndexDbEventRepository::in_memory,
+            view_repo: IndexDbViewRepository::in_memory,
+        }
+    }
+
+    /// The append-only log, in OPFS when in a dedicated worker, in plain files elsewhere.
+    // Only the worker tests use it in the browser
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub(crate) fn opfs() -> Self {
+        Self {
+            event_repo: IndexDbEventRepository::opfs,
+            view_repo: IndexDbViewRepository::opfs,
+        }
+    }
+}
+
+fn created(id: &str) -> SerializedEvent {
+    test_event_envelope(id, 1, TestEvent::Created(Created { id: id.to_string() }))
+}
+
+fn tested(id: &str, sequence: usize) -> SerializedEvent {
+    test_event_envelope(
+        id,
+        sequence,
+        TestEvent::Tested(Tested {
+            test_name: format!("test {sequence}"),
+        }),
+    )
+}
+
+fn sequences(events: &[SerializedEvent]) -> Vec<usize> {
+    events.iter().map(|event| event.sequence).collect()
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn default_backend() {
+    conformance(Backend::default()).await;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::test]
+async fn memory_backend() {
+    conformance(Backend::memory()).await;
+}
+
+pub(crate) async fn conformance(backend: Backend) {
+    key_order(&backend).await;
+    atomic_batches(&backend).await;
+    rollbacks(&backend).await;
+    snapshots(&backend).await;
+    tombstones(&backend).await;
+    indexes(&backend).await;
+    views(&backend).await;
+}
+
+/// Keys sort like IndexedDB keys: sequences as numbers, and an aggregate id never matches
+/// another one it is a prefix of.
+async fn key_order(backend: &Backend) {
+    let event_repo = (backend.event_repo)(&test_db_name().unwrap());
+    event_repo
+        .insert_events::<TestAggregate>(&[created("a"), tested("a", 2), tested("a", 10)])
+        .await
+        .unwrap();
+    event_repo
+        .insert_events::<TestAggregate>(&[created("ab"), created("")])
+        .await
+        .unwrap();
+
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert_eq!(vec![1, 2, 10], sequences(&events));
+    let events = event_repo
+        .get_last_events::<TestAggregate>("a", 2)
+        .await
+        .unwrap();
+    assert_eq!(vec![10], sequences(&events));
+
+    let ids: Vec<String> = event_repo
+        .list_aggregates::<TestAggregate>(None, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|summary| summary.aggregate_id)
+        .collect();
+    assert_eq!(vec!["", "a", "ab"], ids);
+    let page = event_repo
+        .list_aggregates::<TestAggregate>(Some("a".to_string()), 10)
+        .await
+        .unwrap();
+    assert_eq!("ab", page[0].aggregate_id);
+    assert_eq!(
+        3,
+        event_repo
+            .count_aggregates::<TestAggregate>()
+            .await
+            .unwrap()
+    );
+}
+
+/// A batch holding an existing event is rejected as a whole.
+async fn atomic_batches(backend: &Backend) {
+    let event_repo = (backend.event_repo)(&test_db_name().unwrap());
+    event_repo
+        .insert_events::<TestAggregate>(&[created("a"), tested("a", 2)])
+        .await
+        .unwrap();
+
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[tested("a", 3), tested("a", 2)])
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::OptimisticLock));
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert_eq!(vec![1, 2], sequences(&events));
+    let summaries = event_repo
+        .list_aggregates::<TestAggregate>(None, 10)
+        .await
+        .unwrap();
+    assert_eq!(2, summaries[0].event_count);
+
+    // So is a batch holding the same event twice
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[tested("a", 3), tested("a", 3)])
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::OptimisticLock));
+
+    // Positions carry on after the rejected batches
+    event_repo
+        .insert_events::<TestAggregate>(&[tested("a", 3)])
+        .await
+        .unwrap();
+    let page = event_repo.events_by_type("Tested", None, 10).await.unwrap();
+    assert_eq!(vec![2, 3], sequences(&page.events));
+}
+
+/// A transaction failing after some of its writes leaves the events, the catalog and the views
+/// untouched.
+async fn rollbacks(backend: &Backend) {
+    let db_name = test_db_name().unwrap();
+    let event_repo =
+        (backend.event_repo)(&db_name).with_projection((backend.view_repo)(&db_name, "views"));
+    event_repo
+        .insert_events::<TestAggregate>(&[created("a")])
+        .await
+        .unwrap();
+
+    // The view cannot read the last event, once the others are written
+    let unreadable = SerializedEvent {
+        payload: json!({ "Unknown": {} }),
+        ..tested("a", 3)
+    };
+    event_repo
+        .insert_events::<TestAggregate>(&[tested("a", 2), unreadable])
+        .await
+        .unwrap_err();
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert_eq!(vec![1], sequences(&events));
+    let summaries = event_repo
+        .list_aggregates::<TestAggregate>(None, 10)
+        .await
+        .unwrap();
+    assert_eq!(1, summaries[0].event_count);
+    let (view, context) = (backend.view_repo)(&db_name, "views")
+        .load_with_context("a")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(1, view.events.len());
+    assert_eq!(1, context.version);
+}
+
+/// A snapshot is only replaced by its direct successor, and truncation keeps the events it
+/// does not cover.
+async fn snapshots(backend: &Backend) {
+    let event_repo = (backend.event_repo)(&test_db_name().unwrap()).with_snapshot_truncation(1);
+    let aggregate = json!({ "id": "a", "description": "", "tests": [] });
+    event_repo
+        .persist::<TestAggregate>(
+            &[created("a"), tested("a", 2)],
+            Some(("a".to_string(), aggregate.clone(), 1)),
+        )
+        .await
+        .unwrap();
+
+    let result = event_repo
+        .persist::<TestAggregate>(
+            &[tested("a", 3)],
+            Some(("a".to_string(), aggregate.clone(), 1)),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(result, PersistenceError::OptimisticLockError));
+
+    event_repo
+        .persist::<TestAggregate>(&[tested("a", 3)], Some(("a".to_string(), aggregate, 2)))
+        .await
+        .unwrap();
+    let snapshot = event_repo
+        .get_snapshot::<TestAggregate>("a")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (3, 2),
+        (snapshot.current_sequence, snapshot.current_snapshot)
+    );
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert_eq!(vec![3], sequences(&events));
+}
+
+/// Soft deleted aggregates can neither be read nor appended to, hard deleted ones are gone.
+async fn tombstones(backend: &Backend) {
+    let event_repo = (backend.event_repo)(&test_db_name().unwrap());
+    event_repo
+        .insert_events::<TestAggregate>(&[created("a"), created("b")])
+        .await
+        .unwrap();
+
+    event_repo
+        .delete_aggregate::<TestAggregate>("a", DeleteOptions::default().soft())
+        .await
+        .unwrap();
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[tested("a", 2)])
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::AggregateDeleted(_)));
+    assert!(event_repo.get_events::<TestAggregate>("a").await.is_err());
+
+    event_repo
+        .delete_aggregate::<TestAggregate>("a", DeleteOptions::default())
+        .await
+        .unwrap();
+    event_repo
+        .delete_aggregate::<TestAggregate>("b", DeleteOptions::default())
+        .await
+        .unwrap();
+    assert!(event_repo
+        .get_events::<TestAggregate>("a")
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        0,
+        event_repo
+            .count_aggregates::<TestAggregate>()
+            .await
+            .unwrap()
+    );
+    let page = event_repo
+        .events_by_type("Created", None, 10)
+        .await
+        .unwrap();
+    assert!(page.events.is_empty());
+}
+
+/// Indexes follow the records they index, in index order then key order.
+async fn indexes(backend: &Backend) {
+    let db_name = test_db_name().unwrap();
+    let event_repo = (backend.event_repo)(&db_name).with_metadata_index("user_id");
+    for (id, user_id) in [("a", "u2"), ("b", "u1"), ("c", "u2")] {
+        let event = SerializedEvent {
+            metadata: json!({ "user_id": user_id }),
+            ..created(id)
+        };
+        event_repo
+            .insert_events::<TestAggregate>(&[event])
+            .await
+            .unwrap();
+    }
+
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("u2"), None, 1)
+        .await
+        .unwrap();
+    assert_eq!("a", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("u2"), page.next, 1)
+        .await
+        .unwrap();
+    assert_eq!("c", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::at_most("u1"), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(1, page.events.len());
+
+    // Truncated events leave the indexes
+    event_repo
+        .truncate_before::<TestAggregate>("a", 2)
+        .await
+        .unwrap();
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("u2"), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(1, page.events.len());
+    let page = event_repo
+        .events_by_type("Created", None, 10)
+        .await
+        .unwrap();
+    assert_eq!(2, page.events.len());
+}
+
+/// Views are paged by id and versioned.
+async fn views(backend: &Backend) {
+    let view_repo = (backend.view_repo)(&test_db_name().unwrap(), "views");
+    for id in ["v2", "v1", "v3"] {
+        let context = ViewContext::new(id.to_string(), 0);
+        view_repo
+            .update_view(TestView::default(), context)
+            .await
+            .unwrap();
+    }
+
+    let page = view_repo.list(None, 2).await.unwrap();
+    let ids: Vec<&str> = page.views.iter().map(|(id, _, _)| id.as_str()).collect();
+    assert_eq!(vec!["v1", "v2"], ids);
+    let page = view_repo.list(page.next, 2).await.unwrap();
+    assert_eq!(1, page.views.len());
+    assert_eq!(None, page.next);
+    assert_eq!(3, view_repo.count().await.unwrap());
+
+    let result = view_repo
+        .update_view(TestView::default(), ViewContext::new("v1".to_string(), 0))
+        .await
+        .unwrap_err();
+    assert!(matches!(result, PersistenceError::OptimisticLockError));
+    let (_, context) = view_repo.load_with_context("v1").await.unwrap().unwrap();
+    assert_eq!(1, context.version);
+}
+
+/// In-memory repositories share the database of the same name only.
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::test]
+async fn memory_databases_shared_by_name() {
+    let db_name = uuid::Uuid::new_v4().to_string();
+    IndexDbEventRepository::in_memory(&db_name)
+        .insert_events::<TestAggregate>(&[created("a")])
+        .await
+        .unwrap();
+
+    let shared = IndexDbEventRepository::in_memory(&db_name);
+    assert_eq!(
+        1,
+        shared.get_events::<TestAggregate>("a").await.unwrap().len()
+    );
+    let other = IndexDbEventRepository::in_memory(&uuid::Uuid::new_v4().to_string());
+    assert!(other
+        .get_events::<TestAggregate>("a")
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,131 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView, Tested,
+};
+use cqrs_es::persist::{PersistedEventRepository, ViewRepository};
+use indexdb_es::{
+    renumber_events, ConflictMode, DeleteOptions, ExportOptions, ImportOptions,
+    IndexDbAggregateError, IndexDbEventRepository, IndexDbViewRepository,
+};
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn delete_aggregates() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+
+    // Hard delete removes the stream, which can then be recreated
+    let id = uuid::Uuid::new_v4().to_string();
+    let created = test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() }));
+    event_repo
+        .insert_events::<TestAggregate>(std::slice::from_ref(&created))
+        .await
+        .unwrap();
+    event_repo
+        .delete_aggregate::<TestAggregate>(&id, DeleteOptions::default())
+        .await
+        .unwrap();
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert!(events.is_empty());
+    event_repo
+        .insert_events::<TestAggregate>(&[created])
+        .await
+        .unwrap();
+
+    // Soft delete keeps the events behind a tombstone
+    event_repo
+        .delete_aggregate::<TestAggregate>(&id, DeleteOptions::default().soft())
+        .await
+        .unwrap();
+    assert!(event_repo.get_events::<TestAggregate>(&id).await.is_err());
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            &id,
+            2,
+            TestEvent::Tested(Tested {
+                test_name: "a test was run".to_string(),
+            }),
+        )])
+        .await
+        .unwrap_err();
+    match result {
+        IndexDbAggregateError::AggregateDeleted(aggregate_id) => assert_eq!(id, aggregate_id),
+        _ => panic!("invalid error result found during insert: {}", result),
+    };
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn deleted_aggregates_not_recreated() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    let id = uuid::Uuid::new_v4().to_string();
+    let created = test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() }));
+    let tested = test_event_envelope(
+        &id,
+        2,
+        TestEvent::Tested(Tested {
+            test_name: "a test was run".to_string(),
+        }),
+    );
+    event_repo
+        .insert_events::<TestAggregate>(&[created.clone(), tested.clone()])
+        .await
+        .unwrap();
+    let (dump, _) = event_repo
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+    event_repo
+        .delete_aggregate::<TestAggregate>(&id, DeleteOptions::default().soft())
+        .await
+        .unwrap();
+
+    // Neither an import nor a rebase writes new events behind the tombstone
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    target
+        .insert_events::<TestAggregate>(std::slice::from_ref(&created))
+        .await
+        .unwrap();
+    target
+        .delete_aggregate::<TestAggregate>(&id, DeleteOptions::default().soft())
+        .await
+        .unwrap();
+    let result = target
+        .import(
+            dump.as_slice(),
+            ImportOptions::default().with_conflict_mode(ConflictMode::Skip),
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::AggregateDeleted(_)));
+    let result = event_repo
+        .rebase::<TestAggregate, _, _>(&id, &[tested], renumber_events)
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::AggregateDeleted(_)));
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn hard_delete_removes_projected_views() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    for id in ["a", "b"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(
+                id,
+                1,
+                TestEvent::Created(Created { id: id.to_string() }),
+            )])
+            .await
+            .unwrap();
+    }
+
+    event_repo
+        .delete_aggregate::<TestAggregate>("a", DeleteOptions::default())
+        .await
+        .unwrap();
+    assert!(view_repo().load("a").await.unwrap().is_none());
+    assert!(view_repo().load("b").await.unwrap().is_some());
+}
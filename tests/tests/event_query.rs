@@ -0,0 +1,123 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use futures::StreamExt;
+use indexdb_es::IndexDbEventRepository;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn events_by_type() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    for id in ["order-2", "order-1"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[
+                test_event_envelope(id, 1, TestEvent::Created(Created { id: id.to_string() })),
+                test_event_envelope(
+                    id,
+                    2,
+                    TestEvent::Tested(Tested {
+                        test_name: id.to_string(),
+                    }),
+                ),
+            ])
+            .await
+            .unwrap();
+    }
+
+    // Events come back in the order they were written, across aggregates
+    let page = event_repo.events_by_type("Tested", None, 1).await.unwrap();
+    assert_eq!(1, page.events.len());
+    assert_eq!("order-2", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_type("Tested", page.next, 1)
+        .await
+        .unwrap();
+    assert_eq!("order-1", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_type("Tested", page.next, 1)
+        .await
+        .unwrap();
+    assert!(page.events.is_empty());
+    assert_eq!(None, page.next);
+
+    let streamed: Vec<_> = event_repo
+        .stream_events_by_type("Created", 1)
+        .collect()
+        .await;
+    assert_eq!(2, streamed.len());
+    assert!(streamed
+        .iter()
+        .all(|e| e.as_ref().unwrap().event_type == "Created"));
+}
+
+/// A database written before the events store tracked global positions, whose events are
+/// numbered by the upgrade so that the queries along the position index find them.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen_test]
+async fn pre_upgrade_database() {
+    use gloo_utils::format::JsValueSerdeExt;
+    use wasm_bindgen::JsValue;
+
+    let db_name = test_db_name().unwrap();
+    let mut open = idb::Factory::new()
+        .unwrap()
+        .open(&db_name, Some(4))
+        .unwrap();
+    open.on_upgrade_needed(|event| {
+        let mut params = idb::ObjectStoreParams::new();
+        params.key_path(Some(idb::KeyPath::new_array([
+            "aggregate_type",
+            "aggregate_id",
+            "sequence",
+        ])));
+        event
+            .database()
+            .unwrap()
+            .create_object_store("events", params)
+            .unwrap();
+    });
+    let database = open.await.unwrap();
+    let transaction = database
+        .transaction(&["events"], idb::TransactionMode::ReadWrite)
+        .unwrap();
+    let store = transaction.object_store("events").unwrap();
+    for id in ["order-2", "order-1"] {
+        let event = serde_json::json!({
+            "aggregate_type": "TestAggregate",
+            "aggregate_id": id,
+            "sequence": 1,
+            "event_type": "Created",
+            "event_version": "1.0",
+            "payload": { "Created": { "id": id } },
+            "metadata": {},
+        });
+        store
+            .add(&JsValue::from_serde(&event).unwrap(), None)
+            .await
+            .unwrap();
+    }
+    transaction.commit().await.unwrap();
+    database.close();
+
+    let event_repo = IndexDbEventRepository::new(Some(db_name), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            "order-3",
+            1,
+            TestEvent::Created(Created {
+                id: "order-3".to_string(),
+            }),
+        )])
+        .await
+        .unwrap();
+    let page = event_repo
+        .events_by_type("Created", None, 10)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = page
+        .events
+        .iter()
+        .map(|event| event.aggregate_id.as_str())
+        .collect();
+    assert_eq!(vec!["order-1", "order-2", "order-3"], ids);
+}
@@ -1,15 +1,17 @@
 use crate::tests::testing::{
-    test_event_envelope, Created, SomethingElse, TestAggregate, TestEvent, Tested,
+    test_db_name, test_event_envelope, Created, SomethingElse, TestAggregate, TestEvent, Tested,
 };
-use cqrs_es::persist::PersistedEventRepository;
+use cqrs_es::persist::{PersistedEventRepository, ReplayStream};
+use indexdb_es::renumber_events;
+use indexdb_es::DeleteOptions;
 use indexdb_es::IndexDbAggregateError;
 use indexdb_es::IndexDbEventRepository;
 use wasm_bindgen_test::*;
 
-#[wasm_bindgen_test]
+#[wasm_bindgen_test(unsupported = tokio::test)]
 async fn event_repositories() {
     let id = uuid::Uuid::new_v4().to_string();
-    let event_repo: IndexDbEventRepository = IndexDbEventRepository::new(None, None);
+    let event_repo: IndexDbEventRepository = IndexDbEventRepository::new(test_db_name(), None);
     let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
     assert!(events.is_empty());
 
@@ -61,6 +63,124 @@ async fn event_repositories() {
     // verify_replay_stream(&id, event_repo).await;
 }
 
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn rebase_on_remote_history() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+            test_event_envelope(
+                &id,
+                2,
+                TestEvent::Tested(Tested {
+                    test_name: "local test".to_string(),
+                }),
+            ),
+        ])
+        .await
+        .unwrap();
+
+    let remote = test_event_envelope(
+        &id,
+        2,
+        TestEvent::Tested(Tested {
+            test_name: "remote test".to_string(),
+        }),
+    );
+    let rebased = event_repo
+        .rebase::<TestAggregate, _, _>(&id, std::slice::from_ref(&remote), renumber_events)
+        .await
+        .unwrap();
+    assert_eq!(1, rebased.len());
+    assert_eq!(3, rebased[0].sequence);
+
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        vec![1, 2, 3],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+    assert_eq!(remote.payload, events[1].payload);
+
+    // A resolver colliding with the remote history leaves the store untouched
+    let result = event_repo
+        .rebase::<TestAggregate, _, _>(&id, &[remote], |local, _| async { local })
+        .await
+        .unwrap_err();
+    match result {
+        IndexDbAggregateError::OptimisticLock => {}
+        _ => panic!("invalid error result found during rebase: {}", result),
+    };
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(3, events.len());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn rebase_rewrites_stream() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    let tested = |sequence, test_name: &str| {
+        test_event_envelope(
+            &id,
+            sequence,
+            TestEvent::Tested(Tested {
+                test_name: test_name.to_string(),
+            }),
+        )
+    };
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+            tested(2, "local"),
+            tested(3, "local"),
+        ])
+        .await
+        .unwrap();
+
+    // Remote events of another stream are rejected
+    let other = test_event_envelope("other", 2, TestEvent::Created(Created { id: id.clone() }));
+    assert!(event_repo
+        .rebase::<TestAggregate, _, _>(&id, &[tested(2, "remote"), other], renumber_events)
+        .await
+        .is_err());
+    assert_eq!(
+        3,
+        event_repo
+            .get_events::<TestAggregate>(&id)
+            .await
+            .unwrap()
+            .len()
+    );
+
+    // The resolver may read the store, e.g. to re-run the commands, and drop the local events
+    let (repo, stream_id) = (&event_repo, &id);
+    let rebased = event_repo
+        .rebase::<TestAggregate, _, _>(&id, &[tested(2, "remote")], |local, _| async move {
+            let events = repo.get_events::<TestAggregate>(stream_id).await.unwrap();
+            assert_eq!(3, events.len());
+            assert_eq!(2, local.len());
+            Vec::new()
+        })
+        .await
+        .unwrap();
+    assert!(rebased.is_empty());
+
+    // The catalog follows the shortened stream
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        vec![1, 2],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+    let summary = event_repo
+        .list_aggregates::<TestAggregate>(None, 100)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|summary| summary.aggregate_id == id)
+        .unwrap();
+    assert_eq!(2, summary.last_sequence);
+}
+
 // TODO
 // async fn verify_replay_stream(id: &str, event_repo: PostgresEventRepository) {
 //     let mut stream = event_repo
@@ -84,113 +204,55 @@ async fn event_repositories() {
 //     assert!(found_in_stream >= 2);
 // }
 
-// TODO
-// #[tokio::test]
-// async fn snapshot_repositories() {
-//     let pool = default_postgress_pool(TEST_CONNECTION_STRING).await;
-//     let id = uuid::Uuid::new_v4().to_string();
-//     let event_repo: PostgresEventRepository = PostgresEventRepository::new(pool.clone());
-//     let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
-//     assert_eq!(None, snapshot);
-
-//     let test_description = "some test snapshot here".to_string();
-//     let test_tests = vec!["testA".to_string(), "testB".to_string()];
-//     event_repo
-//         .insert::<TestAggregate>(
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: test_description.clone(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap(),
-//             id.clone(),
-//             1,
-//             &vec![],
-//         )
-//         .await
-//         .unwrap();
+/// The aggregate ids and sequences of the events left in `stream`.
+pub(crate) async fn replayed(mut stream: ReplayStream) -> Vec<(String, usize)> {
+    let mut events = Vec::new();
+    while let Some(event) = stream.next::<TestAggregate>(&None).await {
+        let event = event.unwrap();
+        events.push((event.aggregate_id, event.sequence));
+    }
+    events
+}
 
-//     let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
-//     assert_eq!(
-//         Some(snapshot_context(
-//             id.clone(),
-//             0,
-//             1,
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: test_description.clone(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap()
-//         )),
-//         snapshot
-//     );
-
-//     // sequence iterated, does update
-//     event_repo
-//         .update::<TestAggregate>(
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: "a test description that should be saved".to_string(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap(),
-//             id.clone(),
-//             2,
-//             &vec![],
-//         )
-//         .await
-//         .unwrap();
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn streamed_events() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    for (id, sequence) in [("a", 1), ("b", 1), ("c", 1), ("a", 2)] {
+        let event = match sequence {
+            1 => TestEvent::Created(Created { id: id.to_string() }),
+            _ => TestEvent::Tested(Tested {
+                test_name: "a test was run".to_string(),
+            }),
+        };
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, sequence, event)])
+            .await
+            .unwrap();
+    }
+    event_repo
+        .delete_aggregate::<TestAggregate>("c", DeleteOptions::default().soft())
+        .await
+        .unwrap();
 
-//     let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
-//     assert_eq!(
-//         Some(snapshot_context(
-//             id.clone(),
-//             0,
-//             2,
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: "a test description that should be saved".to_string(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap()
-//         )),
-//         snapshot
-//     );
-
-//     // sequence out of order or not iterated, does not update
-//     let result = event_repo
-//         .update::<TestAggregate>(
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: "a test description that should not be saved".to_string(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap(),
-//             id.clone(),
-//             2,
-//             &vec![],
-//         )
-//         .await
-//         .unwrap_err();
-//     match result {
-//         PostgresAggregateError::OptimisticLock => {}
-//         _ => panic!("invalid error result found during insert: {}", result),
-//     };
-
-//     let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
-//     assert_eq!(
-//         Some(snapshot_context(
-//             id.clone(),
-//             0,
-//             2,
-//             serde_json::to_value(TestAggregate {
-//                 id: id.clone(),
-//                 description: "a test description that should be saved".to_string(),
-//                 tests: test_tests.clone(),
-//             })
-//             .unwrap()
-//         )),
-//         snapshot
-//     );
-// }
+    let stream = event_repo
+        .stream_events::<TestAggregate>("a")
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![("a".to_string(), 1), ("a".to_string(), 2)],
+        replayed(stream).await
+    );
+    // Every instance in the order the events were written, the deleted ones left out
+    let stream = event_repo
+        .stream_all_events::<TestAggregate>()
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 1),
+            ("a".to_string(), 2)
+        ],
+        replayed(stream).await
+    );
+}
@@ -0,0 +1,95 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use indexdb_es::{ExportHeader, ExportOptions, IndexDbEventRepository, DB_VERSION};
+use serde_json::Value;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn export_ndjson() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            &id,
+            1,
+            TestEvent::Created(Created { id: id.clone() }),
+        )])
+        .await
+        .unwrap();
+
+    let (bytes, header) = event_repo
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+    let dump = String::from_utf8(bytes).unwrap();
+    let mut lines = dump.lines();
+
+    let written: ExportHeader = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!(header, written);
+    assert_eq!(DB_VERSION, header.schema_version);
+    assert_eq!("events", header.sections[0].store);
+
+    let records: Vec<Value> = lines.map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(header.sections[0].count as usize, records.len());
+    assert!(records.iter().any(|r| r["aggregate_id"] == id.as_str()));
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn export_in_pages() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    let events: Vec<_> = (1..=1200)
+        .map(|sequence| {
+            test_event_envelope(
+                &id,
+                sequence,
+                TestEvent::Created(Created { id: id.clone() }),
+            )
+        })
+        .collect();
+    event_repo
+        .insert_events::<TestAggregate>(&events)
+        .await
+        .unwrap();
+
+    // Every page is written, in key order
+    let (bytes, header) = event_repo
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+    let dump = String::from_utf8(bytes).unwrap();
+    let sequences: Vec<u64> = dump
+        .lines()
+        .skip(1)
+        .map(|l| serde_json::from_str::<Value>(l).unwrap())
+        .filter(|r| r["aggregate_id"] == id.as_str())
+        .map(|r| r["sequence"].as_u64().unwrap())
+        .collect();
+    assert_eq!((1..=1200).collect::<Vec<_>>(), sequences);
+    assert_eq!(header.sections[0].count as usize, dump.lines().count() - 1);
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen_test]
+async fn export_blob() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            &id,
+            1,
+            TestEvent::Created(Created { id: id.clone() }),
+        )])
+        .await
+        .unwrap();
+
+    let (bytes, _) = event_repo
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+    let blob = event_repo
+        .export_blob(ExportOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(bytes.len() as f64, blob.size());
+    assert_eq!(indexdb_es::NDJSON_MIME_TYPE, blob.type_());
+}
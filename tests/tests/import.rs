@@ -0,0 +1,220 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use cqrs_es::persist::PersistedEventRepository;
+use indexdb_es::{
+    Clock, ConflictMode, ExportOptions, ImportOptions, ImportResult, IndexDbAggregateError,
+    IndexDbEventRepository,
+};
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn import_ndjson() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let source = IndexDbEventRepository::new(test_db_name(), None);
+    source
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+            test_event_envelope(
+                &id,
+                2,
+                TestEvent::Tested(Tested {
+                    test_name: "a test was run".to_string(),
+                }),
+            ),
+        ])
+        .await
+        .unwrap();
+    let (dump, _) = source
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+
+    // Restore into an empty store
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    let mut reported = Vec::new();
+    let result = target
+        .import(
+            dump.as_slice(),
+            ImportOptions::default().with_batch_size(1),
+            |progress| reported.push(progress.processed),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        ImportResult {
+            inserted: 2,
+            skipped: 0,
+            rejected: 0
+        },
+        result
+    );
+    assert_eq!(vec![1, 2, 2], reported);
+    let events = target.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(2, events.len());
+
+    // Restore again into the now populated store
+    let result = target
+        .import(dump.as_slice(), ImportOptions::default(), |_| {})
+        .await
+        .unwrap_err();
+    match result {
+        IndexDbAggregateError::OptimisticLock => {}
+        _ => panic!("invalid error result found during import: {}", result),
+    };
+    let result = target
+        .import(
+            dump.as_slice(),
+            ImportOptions::default().with_conflict_mode(ConflictMode::OverwriteIdentical),
+            |_| {},
+        )
+        .await
+        .unwrap();
+    assert_eq!(2, result.skipped);
+
+    // Invalid records are rejected
+    let dump = format!(
+        "{}\n{}\n{}\n",
+        r#"{"schema_version":1,"sections":[{"store":"events","count":2}]}"#,
+        r#"{"aggregate_id":"","sequence":1}"#,
+        serde_json::to_string(&serde_json::json!({
+            "aggregate_id": id,
+            "sequence": 3,
+            "aggregate_type": "TestAggregate",
+            "event_type": "Tested",
+            "event_version": "1.0",
+            "payload": {},
+            "metadata": {}
+        }))
+        .unwrap()
+    );
+    let result = target
+        .import(
+            dump.as_bytes(),
+            ImportOptions::default().with_conflict_mode(ConflictMode::Skip),
+            |_| {},
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        ImportResult {
+            inserted: 1,
+            skipped: 0,
+            rejected: 1
+        },
+        result
+    );
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn import_duplicates_within_a_batch() {
+    let event = serde_json::json!({
+        "aggregate_id": "a",
+        "sequence": 1,
+        "aggregate_type": "TestAggregate",
+        "event_type": "Created",
+        "event_version": "1.0",
+        "payload": {},
+        "metadata": {}
+    });
+    let dump = format!(
+        "{}\n{}\n{}\n",
+        r#"{"schema_version":1,"sections":[{"store":"events","count":2}]}"#, event, event
+    );
+
+    // The second record conflicts with the first, although neither is stored yet
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    let result = target
+        .import(dump.as_bytes(), ImportOptions::default(), |_| {})
+        .await
+        .unwrap_err();
+    assert!(matches!(result, IndexDbAggregateError::OptimisticLock));
+    let events = target.get_events::<TestAggregate>("a").await.unwrap();
+    assert!(events.is_empty());
+
+    let result = target
+        .import(
+            dump.as_bytes(),
+            ImportOptions::default().with_conflict_mode(ConflictMode::Skip),
+            |_| {},
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        ImportResult {
+            inserted: 1,
+            skipped: 1,
+            rejected: 0
+        },
+        result
+    );
+    let events = target.get_events::<TestAggregate>("a").await.unwrap();
+    assert_eq!(1, events.len());
+}
+
+/// A clock stuck at the given time.
+struct FixedClock(f64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> f64 {
+        self.0
+    }
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn overwrite_identical_events() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let created = test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() }));
+    let tested = |test_name: &str| {
+        test_event_envelope(
+            &id,
+            2,
+            TestEvent::Tested(Tested {
+                test_name: test_name.to_string(),
+            }),
+        )
+    };
+    let target = IndexDbEventRepository::new(test_db_name(), None).with_clock(FixedClock(1_000.0));
+    target
+        .insert_events::<TestAggregate>(&[created.clone(), tested("a test was run")])
+        .await
+        .unwrap();
+
+    // The same events recorded elsewhere, later and at other positions
+    let source = IndexDbEventRepository::new(test_db_name(), None).with_clock(FixedClock(2_000.0));
+    source
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            "other",
+            1,
+            TestEvent::Created(Created {
+                id: "other".to_string(),
+            }),
+        )])
+        .await
+        .unwrap();
+    source
+        .insert_events::<TestAggregate>(&[created, tested("another test was run")])
+        .await
+        .unwrap();
+    let (dump, _) = source
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+
+    let result = target
+        .import(
+            dump.as_slice(),
+            ImportOptions::default().with_conflict_mode(ConflictMode::OverwriteIdentical),
+            |_| {},
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        ImportResult {
+            inserted: 1,
+            skipped: 1,
+            rejected: 1
+        },
+        result
+    );
+}
@@ -0,0 +1,38 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use cqrs_es::persist::PersistedEventRepository;
+use indexdb_es::{EventRow, ImportOptions, IndexDbEventRepository};
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn event_table_rows() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let source = IndexDbEventRepository::new(test_db_name(), None);
+    source
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            &id,
+            1,
+            TestEvent::Created(Created { id: id.clone() }),
+        )])
+        .await
+        .unwrap();
+
+    let mut rows = source.export_rows().await.unwrap();
+    assert_eq!(1, rows.len());
+    assert_eq!(id, rows[0].aggregate_id);
+    assert_eq!(1, rows[0].sequence);
+
+    rows.push(EventRow {
+        sequence: -1,
+        ..rows[0].clone()
+    });
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    let result = target
+        .import_rows(&rows, ImportOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(1, result.inserted);
+    assert_eq!(1, result.rejected);
+
+    let events = target.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(1, events.len());
+}
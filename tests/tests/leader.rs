@@ -0,0 +1,149 @@
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::oneshot;
+use futures::StreamExt;
+use indexdb_es::LeaderElection;
+use js_sys::{Array, Function, Promise, Reflect};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use wasm_bindgen_test::*;
+
+/// Waits until `is_leader` reports that the tab leads.
+async fn lead(is_leader: &mut UnboundedReceiver<bool>) {
+    while !is_leader.next().await.unwrap() {}
+}
+
+async fn hand_over(first: LeaderElection, second: LeaderElection) {
+    let mut first_leads = first.is_leader();
+    let mut second_leads = second.is_leader();
+    lead(&mut first_leads).await;
+    assert!(first.leading());
+    assert!(!second.leading());
+
+    let (started, on_start) = oneshot::channel();
+    let started = RefCell::new(Some(started));
+    second.spawn_while_leader(move || {
+        let started = started.borrow_mut().take();
+        async move {
+            if let Some(started) = started {
+                let _ = started.send(());
+            }
+        }
+    });
+
+    // Leadership and its jobs move to the other tab once the leader is gone
+    drop(first);
+    assert_eq!(Some(false), first_leads.next().await);
+    assert_eq!(None, first_leads.next().await);
+    lead(&mut second_leads).await;
+    on_start.await.unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn leader_by_web_locks() {
+    let name = uuid::Uuid::new_v4().to_string();
+    let first = LeaderElection::new(&name).unwrap();
+    let second = LeaderElection::new(&name).unwrap();
+    hand_over(first, second).await;
+}
+
+#[wasm_bindgen_test]
+async fn leader_by_heartbeat() {
+    let name = uuid::Uuid::new_v4().to_string();
+    let first = LeaderElection::heartbeat_with_timeout(&name, 3000).unwrap();
+    let mut first_leads = first.is_leader();
+    lead(&mut first_leads).await;
+    let second = LeaderElection::heartbeat_with_timeout(&name, 3000).unwrap();
+    hand_over(first, second).await;
+}
+
+#[wasm_bindgen_test]
+async fn leader_resigns_on_page_hide() {
+    let name = uuid::Uuid::new_v4().to_string();
+    let first = LeaderElection::heartbeat(&name).unwrap();
+    lead(&mut first.is_leader()).await;
+    let second = LeaderElection::heartbeat(&name).unwrap();
+    let mut second_leads = second.is_leader();
+
+    // The other tab takes over well before the timeout of 70 s
+    let page_hide = web_sys::Event::new("pagehide").unwrap();
+    js_sys::global()
+        .unchecked_into::<web_sys::EventTarget>()
+        .dispatch_event(&page_hide)
+        .unwrap();
+    assert!(!first.leading());
+    lead(&mut second_leads).await;
+}
+
+#[wasm_bindgen_test]
+async fn jobs_run_by_leader_only() {
+    let name = uuid::Uuid::new_v4().to_string();
+    let (done, on_done) = oneshot::channel();
+    let done = RefCell::new(Some(done));
+    let first_runs = Rc::new(Cell::new(0));
+    let first = LeaderElection::new(&name).unwrap().with_job(10, {
+        let first_runs = first_runs.clone();
+        move || {
+            first_runs.set(first_runs.get() + 1);
+            if first_runs.get() == 3 {
+                if let Some(done) = done.borrow_mut().take() {
+                    let _ = done.send(());
+                }
+            }
+            async {}
+        }
+    });
+    let second_runs = Rc::new(Cell::new(0));
+    let second = LeaderElection::new(&name).unwrap().with_job(10, {
+        let second_runs = second_runs.clone();
+        move || {
+            second_runs.set(second_runs.get() + 1);
+            async {}
+        }
+    });
+
+    on_done.await.unwrap();
+    assert!(first.leading());
+    assert!(!second.leading());
+    assert_eq!(0, second_runs.get());
+}
+
+/// The number of requests waiting for the lock of the election `name`.
+async fn pending_requests(name: &str) -> usize {
+    let navigator = Reflect::get(&js_sys::global(), &JsValue::from_str("navigator")).unwrap();
+    let locks = Reflect::get(&navigator, &JsValue::from_str("locks")).unwrap();
+    let query: Function = Reflect::get(&locks, &JsValue::from_str("query"))
+        .unwrap()
+        .dyn_into()
+        .unwrap();
+    let snapshot: Promise = query.call0(&locks).unwrap().dyn_into().unwrap();
+    let snapshot = JsFuture::from(snapshot).await.unwrap();
+    let pending: Array = Reflect::get(&snapshot, &JsValue::from_str("pending"))
+        .unwrap()
+        .dyn_into()
+        .unwrap();
+    let lock_name = format!("indexdb-es:{name}");
+    pending
+        .iter()
+        .filter(|request| {
+            Reflect::get(request, &JsValue::from_str("name"))
+                .ok()
+                .and_then(|name| name.as_string())
+                .as_deref()
+                == Some(lock_name.as_str())
+        })
+        .count()
+}
+
+#[wasm_bindgen_test]
+async fn dropped_candidate_leaves_queue() {
+    let name = uuid::Uuid::new_v4().to_string();
+    let leader = LeaderElection::new(&name).unwrap();
+    lead(&mut leader.is_leader()).await;
+    let candidate = LeaderElection::new(&name).unwrap();
+    assert_eq!(1, pending_requests(&name).await);
+
+    drop(candidate);
+    assert_eq!(0, pending_requests(&name).await);
+}
@@ -0,0 +1,102 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use cqrs_es::persist::SerializedEvent;
+use indexdb_es::{IndexDbEventRepository, IndexRange};
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+fn event_by(id: &str, user_id: &str) -> SerializedEvent {
+    SerializedEvent {
+        metadata: json!({ "user_id": user_id }),
+        ..test_event_envelope(id, 1, TestEvent::Created(Created { id: id.to_string() }))
+    }
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn events_by_metadata() {
+    let db_name = test_db_name().unwrap();
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .insert_events::<TestAggregate>(&[event_by("a", "alice"), event_by("b", "bob")])
+        .await
+        .unwrap();
+
+    // Declaring the index later also indexes the existing events
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name), None).with_metadata_index("user_id");
+    event_repo
+        .insert_events::<TestAggregate>(&[event_by("c", "alice"), event_by("d", "carol")])
+        .await
+        .unwrap();
+
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("alice"), None, 1)
+        .await
+        .unwrap();
+    assert_eq!("a", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("alice"), page.next, 1)
+        .await
+        .unwrap();
+    assert_eq!("c", page.events[0].aggregate_id);
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::only("alice"), page.next, 1)
+        .await
+        .unwrap();
+    assert!(page.events.is_empty());
+    assert_eq!(None, page.next);
+
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::between("b", "c"), None, 10)
+        .await
+        .unwrap();
+    let ids: Vec<_> = page
+        .events
+        .iter()
+        .map(|e| e.aggregate_id.as_str())
+        .collect();
+    assert_eq!(vec!["b"], ids);
+
+    assert!(event_repo
+        .events_by_metadata("device_id", IndexRange::all(), None, 10)
+        .await
+        .is_err());
+}
+
+/// The index added by an upgrade, which the connections of the repositories do not block, while
+/// a connection kept open elsewhere fails it.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen_test]
+async fn upgrade_with_open_connections() {
+    let db_name = test_db_name().unwrap();
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .insert_events::<TestAggregate>(&[event_by("a", "alice")])
+        .await
+        .unwrap();
+
+    let open = idb::Factory::new()
+        .unwrap()
+        .open(&db_name, None)
+        .unwrap()
+        .await
+        .unwrap();
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_metadata_index("user_id");
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[event_by("b", "bob")])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        result,
+        indexdb_es::IndexDbAggregateError::ConnectionError(_)
+    ));
+
+    open.close();
+    event_repo
+        .insert_events::<TestAggregate>(&[event_by("b", "bob")])
+        .await
+        .unwrap();
+    let page = event_repo
+        .events_by_metadata("user_id", IndexRange::all(), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(2, page.events.len());
+}
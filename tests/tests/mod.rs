@@ -1,2 +1,28 @@
+mod catalog;
+mod clock;
+mod conformance;
+mod delete;
+mod event_query;
 mod event_repository;
+mod export;
+mod import;
+mod interop;
+#[cfg(target_arch = "wasm32")]
+mod leader;
+mod metadata_index;
+// In the browser the log backend needs a dedicated worker, so its tests are run by the
+// `worker` target instead, which `wasm-pack test` runs along with this one in CI. See
+// `just test-worker-headless` to run it alone.
+#[cfg(not(target_arch = "wasm32"))]
+mod opfs;
+mod outbox;
+mod projection;
+mod projection_runner;
+mod replication;
+#[cfg(target_arch = "wasm32")]
+mod shared_worker;
+mod snapshot;
 mod testing;
+mod view_repository;
+#[cfg(target_arch = "wasm32")]
+mod worker_proxy;
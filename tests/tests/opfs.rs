@@ -0,0 +1,103 @@
+//! The append-only log backend. Synchronous access handles only exist in dedicated workers, so
+//! in the browser these tests are run by the `worker` test target rather than with the others.
+
+use crate::tests::conformance::{conformance, Backend};
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, SomethingElse, TestAggregate, TestEvent,
+};
+use cqrs_es::persist::PersistedEventRepository;
+use indexdb_es::IndexDbEventRepository;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn opfs_backend() {
+    conformance(Backend::opfs()).await;
+}
+
+/// The log keeps working once it grows past a checkpoint of its offset index.
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn opfs_checkpoints() {
+    let event_repo = IndexDbEventRepository::opfs(&test_db_name().unwrap());
+    let id = "x".repeat(100_000);
+    for sequence in 1..=12 {
+        let event = test_event_envelope(
+            &format!("{id}{sequence}"),
+            1,
+            TestEvent::Created(Created { id: id.clone() }),
+        );
+        event_repo
+            .insert_events::<TestAggregate>(&[event])
+            .await
+            .unwrap();
+    }
+
+    let events = event_repo
+        .get_events::<TestAggregate>(&format!("{id}12"))
+        .await
+        .unwrap();
+    assert_eq!(1, events.len());
+    assert_eq!(
+        12,
+        event_repo
+            .count_aggregates::<TestAggregate>()
+            .await
+            .unwrap()
+    );
+}
+
+/// Compaction drops the events truncated from the log and keeps the others.
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn opfs_compaction() {
+    let db_name = test_db_name().unwrap();
+    let event_repo = IndexDbEventRepository::opfs(&db_name);
+    let id = uuid::Uuid::new_v4().to_string();
+    let description = "x".repeat(1000);
+    let events: Vec<_> = (1..=100)
+        .map(|sequence| {
+            test_event_envelope(
+                &id,
+                sequence,
+                TestEvent::SomethingElse(SomethingElse {
+                    description: description.clone(),
+                }),
+            )
+        })
+        .collect();
+    event_repo
+        .insert_events::<TestAggregate>(&events)
+        .await
+        .unwrap();
+    // Nothing to reclaim yet
+    assert!(!event_repo.compact().await.unwrap());
+
+    event_repo
+        .truncate_before::<TestAggregate>(&id, 91)
+        .await
+        .unwrap();
+    #[cfg(not(target_arch = "wasm32"))]
+    let size = std::fs::metadata(format!("{db_name}.log")).unwrap().len();
+    assert!(event_repo.compact().await.unwrap());
+    #[cfg(not(target_arch = "wasm32"))]
+    assert!(std::fs::metadata(format!("{db_name}.log")).unwrap().len() * 4 < size);
+
+    let sequences = |events: Vec<cqrs_es::persist::SerializedEvent>| {
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    };
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!((91..=100).collect::<Vec<_>>(), sequences(events));
+
+    // The compacted log takes new events as before
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope(
+            &id,
+            101,
+            TestEvent::SomethingElse(SomethingElse { description }),
+        )])
+        .await
+        .unwrap();
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 99)
+        .await
+        .unwrap();
+    assert_eq!(vec![100, 101], sequences(events));
+}
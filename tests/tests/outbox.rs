@@ -0,0 +1,102 @@
+use crate::tests::testing::{test_db_name, test_event_envelope, Created, TestAggregate, TestEvent};
+use indexdb_es::{
+    DeleteOptions, IndexDbAggregateError, IndexDbEventRepository, Outbox, ReplicationFilter,
+};
+use std::cell::RefCell;
+use wasm_bindgen_test::*;
+
+fn created(id: &str) -> TestEvent {
+    TestEvent::Created(Created { id: id.to_string() })
+}
+
+/// Flushes `outbox`, recording the ids of the aggregates pushed.
+async fn flush(outbox: &Outbox, pushed: &RefCell<Vec<String>>) -> usize {
+    outbox
+        .flush(|events| {
+            pushed
+                .borrow_mut()
+                .extend(events.into_iter().map(|e| e.aggregate_id));
+            async { Ok(()) }
+        })
+        .await
+        .unwrap()
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn flush_outbox() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    let outbox = Outbox::new(&event_repo, "server").with_batch_size(2);
+    for id in ["a", "b", "c"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, 1, created(id))])
+            .await
+            .unwrap();
+    }
+    assert_eq!(3, outbox.pending().await.unwrap());
+
+    // A failed push leaves its batch pending
+    let pushed = RefCell::new(Vec::new());
+    let result = outbox
+        .flush(|events| {
+            let ids: Vec<String> = events.into_iter().map(|e| e.aggregate_id).collect();
+            let failed = ids.contains(&"c".to_string());
+            if !failed {
+                pushed.borrow_mut().extend(ids);
+            }
+            async move {
+                match failed {
+                    true => Err(IndexDbAggregateError::ConnectionError(
+                        "offline".to_string(),
+                    )),
+                    false => Ok(()),
+                }
+            }
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(vec!["a", "b"], *pushed.borrow());
+    assert_eq!(2, outbox.checkpoint().await.unwrap());
+    assert_eq!(1, outbox.pending().await.unwrap());
+
+    assert_eq!(1, flush(&outbox, &pushed).await);
+    assert_eq!(vec!["a", "b", "c"], *pushed.borrow());
+    assert_eq!(0, outbox.pending().await.unwrap());
+    assert_eq!(0, flush(&outbox, &pushed).await);
+
+    // A new filter starts over from the first event
+    pushed.borrow_mut().clear();
+    let outbox = outbox.with_filter(ReplicationFilter::default().with_aggregate_id_prefix("b"));
+    assert_eq!(0, outbox.checkpoint().await.unwrap());
+    assert_eq!(1, flush(&outbox, &pushed).await);
+    assert_eq!(vec!["b"], *pushed.borrow());
+    assert_eq!(3, outbox.checkpoint().await.unwrap());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn positions_not_reused() {
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    let outbox = Outbox::new(&event_repo, "server");
+    for id in ["a", "b"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, 1, created(id))])
+            .await
+            .unwrap();
+    }
+    let pushed = RefCell::new(Vec::new());
+    assert_eq!(2, flush(&outbox, &pushed).await);
+
+    // The position of the deleted newest event is not handed out again, so the outbox does
+    // not take the next event for one it already pushed
+    event_repo
+        .delete_aggregate::<TestAggregate>("b", DeleteOptions::default())
+        .await
+        .unwrap();
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope("c", 1, created("c"))])
+        .await
+        .unwrap();
+    assert_eq!(1, outbox.pending().await.unwrap());
+    assert_eq!(1, flush(&outbox, &pushed).await);
+    assert_eq!(vec!["a", "b", "c"], *pushed.borrow());
+    assert_eq!(3, outbox.checkpoint().await.unwrap());
+}
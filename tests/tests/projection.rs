@@ -0,0 +1,252 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView, Tested,
+};
+use cqrs_es::persist::{PersistedEventRepository, SerializedEvent, ViewRepository};
+use indexdb_es::{
+    renumber_events, ExportOptions, ImportOptions, IndexDbEventRepository, IndexDbViewRepository,
+    ProjectionRunner,
+};
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn projection_updated_with_events() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    let tested = TestEvent::Tested(Tested {
+        test_name: "a".to_string(),
+    });
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope("a", 1, created.clone()),
+            test_event_envelope("a", 2, tested.clone()),
+        ])
+        .await
+        .unwrap();
+
+    let (view, context) = view_repo().load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(vec![created, tested], view.events);
+    assert_eq!(2, context.version);
+}
+
+fn tested(test_name: &str) -> TestEvent {
+    TestEvent::Tested(Tested {
+        test_name: test_name.to_string(),
+    })
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn projection_updated_with_imported_events() {
+    let source = IndexDbEventRepository::new(test_db_name(), None);
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    source
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope("a", 1, created.clone()),
+            test_event_envelope("a", 2, tested("a")),
+        ])
+        .await
+        .unwrap();
+    let (dump, _) = source
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let target =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    target
+        .import(dump.as_slice(), ImportOptions::default(), |_| {})
+        .await
+        .unwrap();
+    let (view, context) = view_repo().load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(vec![created.clone(), tested("a")], view.events);
+    assert_eq!(2, context.version);
+
+    // So are the views of imported rows
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let target =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    let rows = source.export_rows().await.unwrap();
+    target
+        .import_rows(&rows, ImportOptions::default())
+        .await
+        .unwrap();
+    let view = view_repo().load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created, tested("a")], view.events);
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn projection_rebuilt_on_rebase() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope("a", 1, created.clone()),
+            test_event_envelope("a", 2, tested("local")),
+        ])
+        .await
+        .unwrap();
+
+    event_repo
+        .rebase::<TestAggregate, _, _>(
+            "a",
+            &[test_event_envelope("a", 2, tested("remote"))],
+            renumber_events,
+        )
+        .await
+        .unwrap();
+    let (view, context) = view_repo().load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(
+        vec![created, tested("remote"), tested("local")],
+        view.events
+    );
+    assert_eq!(3, context.version);
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn failed_projection_rolls_back_events() {
+    let db_name = test_db_name().unwrap();
+    let event_repo = IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name), "test_view"),
+    );
+    // An event the view cannot read
+    let unreadable = SerializedEvent {
+        payload: json!({ "Unknown": {} }),
+        ..test_event_envelope("a", 1, tested("a"))
+    };
+    let result = event_repo
+        .insert_events::<TestAggregate>(&[unreadable])
+        .await;
+    assert!(result.is_err());
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert!(events.is_empty());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn projection_caught_up_on_startup() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = |schema_version| {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+            .with_schema_version(schema_version)
+    };
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    // Events appended without the projection
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 1, created.clone())])
+        .await
+        .unwrap();
+
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo(0));
+    assert_eq!(1, event_repo.catch_up_projections().await.unwrap());
+    let view = view_repo(0).load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created.clone()], view.events);
+
+    // A repository catches up on its own before its first write
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 2, tested("a"))])
+        .await
+        .unwrap();
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo(0));
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 3, tested("b"))])
+        .await
+        .unwrap();
+    let (view, context) = view_repo(0).load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(vec![created.clone(), tested("a"), tested("b")], view.events);
+    assert_eq!(3, context.version);
+
+    // And rebuilds the views whose schema version changed
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo(1));
+    assert_eq!(3, event_repo.catch_up_projections().await.unwrap());
+    let (view, context) = view_repo(1).load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(vec![created, tested("a"), tested("b")], view.events);
+    assert_eq!(3, context.version);
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn views_upgraded_on_first_read() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = |schema_version| {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+            .with_schema_version(schema_version)
+    };
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .with_projection(view_repo(0))
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 1, created.clone())])
+        .await
+        .unwrap();
+    // An event the views have not seen yet
+    IndexDbEventRepository::new(Some(db_name.clone()), None)
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 2, tested("a"))])
+        .await
+        .unwrap();
+    let view = view_repo(0).load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created.clone()], view.events);
+
+    // A new schema version rebuilds the views before the first read, without any runner
+    let view = view_repo(1).load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created, tested("a")], view.events);
+    let runner = ProjectionRunner::new(
+        &IndexDbEventRepository::new(Some(db_name.clone()), None),
+        view_repo(1),
+    );
+    assert_eq!(0, runner.catch_up().await.unwrap());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn projection_shares_runner_checkpoint() {
+    let db_name = test_db_name().unwrap();
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let event_repo =
+        IndexDbEventRepository::new(Some(db_name.clone()), None).with_projection(view_repo());
+    let runner = ProjectionRunner::new(&event_repo, view_repo());
+    let created = TestEvent::Created(Created {
+        id: "a".to_string(),
+    });
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope("a", 1, created.clone()),
+            test_event_envelope("a", 2, tested("a")),
+        ])
+        .await
+        .unwrap();
+
+    // The events already applied along with their insert are not applied again
+    assert_eq!(2, runner.checkpoint().await.unwrap());
+    assert_eq!(0, runner.catch_up().await.unwrap());
+    let (view, context) = view_repo().load_with_context("a").await.unwrap().unwrap();
+    assert_eq!(vec![created, tested("a")], view.events);
+    assert_eq!(2, context.version);
+}
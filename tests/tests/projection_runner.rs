@@ -0,0 +1,93 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView, Tested,
+};
+use cqrs_es::persist::ViewRepository;
+use indexdb_es::{IndexDbEventRepository, IndexDbViewRepository, ProjectionRunner};
+use wasm_bindgen_test::*;
+
+fn created(id: &str) -> TestEvent {
+    TestEvent::Created(Created { id: id.to_string() })
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn catch_up_and_rebuild() {
+    let db_name = test_db_name().unwrap();
+    let event_repo = IndexDbEventRepository::new(Some(db_name.clone()), None);
+    let runner = ProjectionRunner::new(
+        &event_repo,
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name), "test_view"),
+    )
+    .with_batch_size(2);
+    for id in ["a", "b", "c"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, 1, created(id))])
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(3, runner.catch_up().await.unwrap());
+    assert_eq!(3, runner.checkpoint().await.unwrap());
+    assert_eq!(3, runner.view_repository().count().await.unwrap());
+
+    // Only the new events are applied on the next run
+    let tested = TestEvent::Tested(Tested {
+        test_name: "a".to_string(),
+    });
+    event_repo
+        .insert_events::<TestAggregate>(&[test_event_envelope("a", 2, tested.clone())])
+        .await
+        .unwrap();
+    assert_eq!(1, runner.catch_up().await.unwrap());
+    let view = runner.view_repository().load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created("a"), tested.clone()], view.events);
+
+    let mut progress = Vec::new();
+    assert_eq!(
+        4,
+        runner
+            .rebuild(|p| progress.push(p.processed))
+            .await
+            .unwrap()
+    );
+    assert_eq!(vec![2, 4], progress);
+    // The views are swapped in a batch at a time
+    assert_eq!(3, runner.view_repository().count().await.unwrap());
+    let view = runner.view_repository().load("a").await.unwrap().unwrap();
+    assert_eq!(vec![created("a"), tested], view.events);
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn rebuild_on_schema_version_change() {
+    let db_name = test_db_name().unwrap();
+    let event_repo = IndexDbEventRepository::new(Some(db_name.clone()), None);
+    let runner = |schema_version| {
+        ProjectionRunner::new(
+            &event_repo,
+            IndexDbViewRepository::<TestView, TestAggregate>::new(
+                Some(db_name.clone()),
+                "test_view",
+            )
+            .with_schema_version(schema_version),
+        )
+    };
+    for id in ["a", "b"] {
+        event_repo
+            .insert_events::<TestAggregate>(&[test_event_envelope(id, 1, created(id))])
+            .await
+            .unwrap();
+    }
+    assert_eq!(2, runner(0).catch_up().await.unwrap());
+    assert_eq!(0, runner(0).catch_up().await.unwrap());
+
+    // A new schema version replays every event once
+    assert_eq!(2, runner(1).catch_up().await.unwrap());
+    assert_eq!(0, runner(1).catch_up().await.unwrap());
+    assert_eq!(2, runner(1).checkpoint().await.unwrap());
+    let view = runner(1)
+        .view_repository()
+        .load("b")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(vec![created("b")], view.events);
+}
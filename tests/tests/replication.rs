@@ -0,0 +1,120 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use cqrs_es::persist::PersistedEventRepository;
+use indexdb_es::{
+    renumber_events, ExportOptions, ImportOptions, IndexDbEventRepository, ReplicationFilter,
+};
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+fn replication_filters() {
+    let created = test_event_envelope(
+        "shop-1/order-1",
+        1,
+        TestEvent::Created(Created {
+            id: "shop-1/order-1".to_string(),
+        }),
+    );
+    let mut tested = test_event_envelope(
+        "shop-2/order-1",
+        2,
+        TestEvent::Tested(Tested {
+            test_name: "a test was run".to_string(),
+        }),
+    );
+    tested.metadata = json!({ "device_id": "laptop" });
+    let events = vec![created, tested];
+
+    assert_eq!(2, ReplicationFilter::default().apply(&events).len());
+    assert_eq!(
+        0,
+        ReplicationFilter::default()
+            .with_aggregate_type("OtherAggregate")
+            .apply(&events)
+            .len()
+    );
+
+    let by_prefix = ReplicationFilter::default().with_aggregate_id_prefix("shop-1/");
+    assert_eq!(vec![1], sequences(&by_prefix, &events));
+
+    let by_event_type = ReplicationFilter::default()
+        .with_event_type("Created")
+        .with_event_type("Tested");
+    assert_eq!(vec![1, 2], sequences(&by_event_type, &events));
+
+    let by_metadata = ReplicationFilter::default()
+        .with_aggregate_type("TestAggregate")
+        .with_metadata("device_id", json!("laptop"));
+    assert_eq!(vec![2], sequences(&by_metadata, &events));
+}
+
+fn created(id: &str) -> cqrs_es::persist::SerializedEvent {
+    test_event_envelope(id, 1, TestEvent::Created(Created { id: id.to_string() }))
+}
+
+/// The aggregate instances of `event_repo` holding events.
+async fn ids(event_repo: &IndexDbEventRepository) -> Vec<&'static str> {
+    let mut ids = Vec::new();
+    for id in ["shop-1/order-1", "shop-2/order-1"] {
+        if !event_repo
+            .get_events::<TestAggregate>(id)
+            .await
+            .unwrap()
+            .is_empty()
+        {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn pull_filtered_events() {
+    let source = IndexDbEventRepository::new(test_db_name(), None);
+    source
+        .insert_events::<TestAggregate>(&[created("shop-1/order-1"), created("shop-2/order-1")])
+        .await
+        .unwrap();
+    let (dump, _) = source
+        .export(ExportOptions::default(), Vec::new())
+        .await
+        .unwrap();
+    let filter = ReplicationFilter::default().with_aggregate_id_prefix("shop-1/");
+
+    // The remote aggregate left out by the filter is not downloaded
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    let options = ImportOptions::default().with_filter(filter.clone());
+    let result = target
+        .import(dump.as_slice(), options.clone(), |_| {})
+        .await
+        .unwrap();
+    assert_eq!((1, 1), (result.inserted, result.skipped));
+    assert_eq!(vec!["shop-1/order-1"], ids(&target).await);
+
+    let target = IndexDbEventRepository::new(test_db_name(), None);
+    let rows = source.export_rows().await.unwrap();
+    let result = target.import_rows(&rows, options).await.unwrap();
+    assert_eq!((1, 1), (result.inserted, result.skipped));
+    assert_eq!(vec!["shop-1/order-1"], ids(&target).await);
+
+    let rebased = target
+        .rebase_with_filter::<TestAggregate, _, _>(
+            "shop-2/order-1",
+            &[created("shop-2/order-1")],
+            &filter,
+            renumber_events,
+        )
+        .await
+        .unwrap();
+    assert!(rebased.is_empty());
+    assert_eq!(vec!["shop-1/order-1"], ids(&target).await);
+}
+
+fn sequences(
+    filter: &ReplicationFilter,
+    events: &[cqrs_es::persist::SerializedEvent],
+) -> Vec<usize> {
+    filter.apply(events).iter().map(|e| e.sequence).collect()
+}
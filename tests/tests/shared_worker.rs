@@ -0,0 +1,70 @@
+use crate::tests::testing::{
+    test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, TestView,
+};
+use cqrs_es::persist::{PersistedEventRepository, PersistenceError};
+use futures::channel::oneshot;
+use indexdb_es::{
+    IndexDbEventRepository, IndexDbViewRepository, ProjectionRunner, SharedWorkerHost,
+    WorkerEventRepository,
+};
+use wasm_bindgen_test::*;
+use web_sys::MessageChannel;
+
+/// Connects a tab to `host` as a shared worker would.
+fn tab(host: &SharedWorkerHost) -> WorkerEventRepository {
+    let channel = MessageChannel::new().unwrap();
+    host.connect(channel.port1());
+    WorkerEventRepository::new(channel.port2())
+}
+
+#[wasm_bindgen_test]
+async fn single_writer_for_all_tabs() {
+    let db_name = test_db_name().unwrap();
+    let event_repo = IndexDbEventRepository::new(Some(db_name.clone()), None);
+    let view_repo = || {
+        IndexDbViewRepository::<TestView, TestAggregate>::new(Some(db_name.clone()), "test_view")
+    };
+    let runner = ProjectionRunner::new(&event_repo, view_repo());
+    let (started, on_start) = oneshot::channel();
+    let host = SharedWorkerHost::new(event_repo)
+        .with_projection_runner(runner)
+        .with_sync_loop(move |_| async move {
+            let _ = started.send(());
+        });
+    on_start.await.unwrap();
+
+    let first = tab(&host);
+    let second = tab(&host);
+    let id = uuid::Uuid::new_v4().to_string();
+    let created = TestEvent::Created(Created { id: id.clone() });
+    first
+        .persist::<TestAggregate>(&[test_event_envelope(&id, 1, created.clone())], None)
+        .await
+        .unwrap();
+
+    // Both tabs see the same store, and their writes conflict as they would without the host
+    let events = second.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(1, events.len());
+    let result = second
+        .persist::<TestAggregate>(&[test_event_envelope(&id, 1, created)], None)
+        .await
+        .unwrap_err();
+    match result {
+        PersistenceError::OptimisticLockError => {}
+        _ => panic!("invalid error result found during insert: {}", result),
+    };
+
+    // The host catches the projection up after the write
+    let reader = ProjectionRunner::new(
+        &IndexDbEventRepository::new(Some(db_name.clone()), None),
+        view_repo(),
+    );
+    let mut checkpoint = 0;
+    for _ in 0..100 {
+        checkpoint = reader.checkpoint().await.unwrap();
+        if checkpoint == 1 {
+            break;
+        }
+    }
+    assert_eq!(1, checkpoint);
+}
@@ -0,0 +1,149 @@
+use crate::tests::testing::{
+    snapshot_context, test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use cqrs_es::persist::{PersistedEventRepository, PersistenceError};
+use indexdb_es::IndexDbEventRepository;
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn snapshots_with_truncation() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None).with_snapshot_truncation(1);
+    let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(None, snapshot);
+
+    let aggregate = serde_json::to_value(TestAggregate {
+        id: id.clone(),
+        description: "some test snapshot here".to_string(),
+        tests: vec!["testA".to_string(), "testB".to_string()],
+    })
+    .unwrap();
+    event_repo
+        .persist::<TestAggregate>(
+            &[
+                test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+                test_event_envelope(&id, 2, tested("testA")),
+                test_event_envelope(&id, 3, tested("testB")),
+            ],
+            Some((id.clone(), aggregate.clone(), 1)),
+        )
+        .await
+        .unwrap();
+
+    let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        Some(snapshot_context(id.clone(), 3, 1, aggregate.clone())),
+        snapshot
+    );
+
+    // Only the last event covered by the snapshot is kept
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        vec![3],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 3)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+
+    // A stale snapshot version is rejected along with its events
+    let result = event_repo
+        .persist::<TestAggregate>(
+            &[test_event_envelope(&id, 4, tested("testC"))],
+            Some((id.clone(), aggregate, 1)),
+        )
+        .await
+        .unwrap_err();
+    match result {
+        PersistenceError::OptimisticLockError => {}
+        _ => panic!("invalid error result found during persist: {}", result),
+    };
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 3)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn snapshot_trailing_events() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None)
+        .with_snapshot_size(2)
+        .with_snapshot_truncation(1);
+
+    // With a snapshot size of 2, cqrs-es only applies the first two events to the snapshot
+    let aggregate = serde_json::to_value(TestAggregate {
+        id: id.clone(),
+        description: "some test snapshot here".to_string(),
+        tests: vec!["testA".to_string()],
+    })
+    .unwrap();
+    event_repo
+        .persist::<TestAggregate>(
+            &[
+                test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+                test_event_envelope(&id, 2, tested("testA")),
+                test_event_envelope(&id, 3, tested("testB")),
+            ],
+            Some((id.clone(), aggregate.clone(), 1)),
+        )
+        .await
+        .unwrap();
+
+    let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        Some(snapshot_context(id.clone(), 2, 1, aggregate)),
+        snapshot
+    );
+
+    // The event after the snapshot is still replayed, and kept by the truncation
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![3],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        vec![2, 3],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn truncate_before() {
+    let id = uuid::Uuid::new_v4().to_string();
+    let event_repo = IndexDbEventRepository::new(test_db_name(), None);
+    event_repo
+        .insert_events::<TestAggregate>(&[
+            test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+            test_event_envelope(&id, 2, tested("testA")),
+            test_event_envelope(&id, 3, tested("testB")),
+        ])
+        .await
+        .unwrap();
+
+    event_repo
+        .truncate_before::<TestAggregate>(&id, 3)
+        .await
+        .unwrap();
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 0)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![3],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+}
+
+fn tested(test_name: &str) -> TestEvent {
+    TestEvent::Tested(Tested {
+        test_name: test_name.to_string(),
+    })
+}
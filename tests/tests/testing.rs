@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use async_trait::async_trait;
 use cqrs_es::persist::{GenericQuery, SerializedEvent, SerializedSnapshot};
 use cqrs_es::{Aggregate, DomainEvent, EventEnvelope, View};
@@ -137,3 +139,11 @@ pub(crate) fn snapshot_context(
         current_snapshot,
     }
 }
+
+/// A fresh database name, in the temporary directory outside the browser.
+pub(crate) fn test_db_name() -> Option<String> {
+    let name = uuid::Uuid::new_v4().to_string();
+    #[cfg(not(target_arch = "wasm32"))]
+    let name = std::env::temp_dir().join(name).display().to_string();
+    Some(name)
+}
@@ -0,0 +1,132 @@
+use crate::tests::testing::{test_db_name, TestAggregate};
+use cqrs_es::persist::{PersistenceError, ViewContext, ViewRepository};
+use cqrs_es::{EventEnvelope, View};
+use indexdb_es::{IndexDbViewRepository, IndexRange};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wasm_bindgen_test::*;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+struct TicketView {
+    id: String,
+    status: String,
+    assignee: String,
+}
+
+impl View<TestAggregate> for TicketView {
+    fn update(&mut self, _event: &EventEnvelope<TestAggregate>) {}
+}
+
+fn ticket(id: &str, status: &str, assignee: &str) -> TicketView {
+    TicketView {
+        id: id.to_string(),
+        status: status.to_string(),
+        assignee: assignee.to_string(),
+    }
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn find_views() {
+    let view_repo =
+        IndexDbViewRepository::<TicketView, TestAggregate>::new(test_db_name(), "tickets")
+            .with_index("status_assignee", &["status", "assignee"])
+            .with_index("assignee", &["assignee"]);
+    for view in [
+        ticket("t3", "open", "me"),
+        ticket("t1", "open", "me"),
+        ticket("t2", "closed", "me"),
+        ticket("t4", "open", "you"),
+    ] {
+        let context = ViewContext::new(view.id.clone(), 0);
+        view_repo.update_view(view, context).await.unwrap();
+    }
+
+    // Open tickets assigned to me, by view id
+    let range = IndexRange::only(json!(["open", "me"]));
+    let page = view_repo
+        .find_views("status_assignee", range.clone(), None, 1)
+        .await
+        .unwrap();
+    assert_eq!(vec![ticket("t1", "open", "me")], page.views);
+    let page = view_repo
+        .find_views("status_assignee", range.clone(), page.next, 1)
+        .await
+        .unwrap();
+    assert_eq!(vec![ticket("t3", "open", "me")], page.views);
+    let page = view_repo
+        .find_views("status_assignee", range, page.next, 1)
+        .await
+        .unwrap();
+    assert!(page.views.is_empty());
+    assert_eq!(None, page.next);
+
+    let page = view_repo
+        .find_views("assignee", IndexRange::at_least("n"), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(vec![ticket("t4", "open", "you")], page.views);
+
+    // Updating a view moves it within its indexes
+    let (_, context) = view_repo.load_with_context("t4").await.unwrap().unwrap();
+    view_repo
+        .update_view(ticket("t4", "open", "me"), context)
+        .await
+        .unwrap();
+    let page = view_repo
+        .find_views("assignee", IndexRange::at_least("n"), None, 10)
+        .await
+        .unwrap();
+    assert!(page.views.is_empty());
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn update_view_optimistic_lock() {
+    let view_repo =
+        IndexDbViewRepository::<TicketView, TestAggregate>::new(test_db_name(), "tickets");
+    let context = ViewContext::new("t1".to_string(), 0);
+    view_repo
+        .update_view(ticket("t1", "open", "me"), context)
+        .await
+        .unwrap();
+
+    let (view, context) = view_repo.load_with_context("t1").await.unwrap().unwrap();
+    assert_eq!(ticket("t1", "open", "me"), view);
+    assert_eq!(1, context.version);
+
+    // A view computed from a stale version is rejected
+    let stale = ViewContext::new("t1".to_string(), 0);
+    let result = view_repo
+        .update_view(ticket("t1", "closed", "me"), stale)
+        .await;
+    assert!(matches!(result, Err(PersistenceError::OptimisticLockError)));
+
+    view_repo
+        .update_view(ticket("t1", "closed", "me"), context)
+        .await
+        .unwrap();
+    let view = view_repo.load("t1").await.unwrap();
+    assert_eq!(Some(ticket("t1", "closed", "me")), view);
+}
+
+#[wasm_bindgen_test(unsupported = tokio::test)]
+async fn list_views() {
+    let view_repo =
+        IndexDbViewRepository::<TicketView, TestAggregate>::new(test_db_name(), "tickets");
+    for id in ["t2", "t3", "t1"] {
+        let context = ViewContext::new(id.to_string(), 0);
+        view_repo
+            .update_view(ticket(id, "open", "me"), context)
+            .await
+            .unwrap();
+    }
+    assert_eq!(3, view_repo.count().await.unwrap());
+
+    let page = view_repo.list(None, 2).await.unwrap();
+    let ids: Vec<_> = page.views.iter().map(|(id, _, _)| id.as_str()).collect();
+    assert_eq!(vec!["t1", "t2"], ids);
+    assert_eq!(1, page.views[0].2.version);
+
+    let page = view_repo.list(page.next, 2).await.unwrap();
+    assert_eq!(ticket("t3", "open", "me"), page.views[0].1);
+    assert!(page.next.is_none());
+}
@@ -0,0 +1,197 @@
+use crate::tests::event_repository::replayed;
+use crate::tests::testing::{
+    snapshot_context, test_db_name, test_event_envelope, Created, TestAggregate, TestEvent, Tested,
+};
+use cqrs_es::persist::{PersistedEventRepository, PersistenceError};
+use futures::channel::oneshot;
+use gloo_utils::format::JsValueSerdeExt;
+use indexdb_es::{IndexDbEventRepository, WorkerEventRepository, WorkerHost};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::*;
+use web_sys::{MessageChannel, MessageEvent};
+
+/// A proxy and the host it talks to, connected by the ports of a message channel as they would
+/// be by a worker.
+fn proxy() -> (WorkerEventRepository, WorkerHost) {
+    let channel = MessageChannel::new().unwrap();
+    let host = WorkerHost::serve_on(
+        IndexDbEventRepository::new(test_db_name(), None),
+        channel.port1(),
+    );
+    (WorkerEventRepository::new(channel.port2()), host)
+}
+
+#[wasm_bindgen_test]
+async fn proxied_events() {
+    let (event_repo, _host) = proxy();
+    let id = uuid::Uuid::new_v4().to_string();
+
+    event_repo
+        .persist::<TestAggregate>(
+            &[
+                test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+                test_event_envelope(&id, 2, tested("testA")),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let events = event_repo.get_events::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(2, events.len());
+    assert_eq!("TestAggregate", events[0].aggregate_type);
+    assert_eq!(
+        serde_json::to_value(tested("testA")).unwrap(),
+        events[1].payload
+    );
+    let events = event_repo
+        .get_last_events::<TestAggregate>(&id, 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![2],
+        events.iter().map(|e| e.sequence).collect::<Vec<_>>()
+    );
+
+    // Conflicting writes fail as they would without the proxy
+    let result = event_repo
+        .persist::<TestAggregate>(&[test_event_envelope(&id, 2, tested("testB"))], None)
+        .await
+        .unwrap_err();
+    match result {
+        PersistenceError::OptimisticLockError => {}
+        _ => panic!("invalid error result found during insert: {}", result),
+    };
+}
+
+#[wasm_bindgen_test]
+async fn proxied_snapshots() {
+    let (event_repo, _host) = proxy();
+    let id = uuid::Uuid::new_v4().to_string();
+    let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(None, snapshot);
+
+    let aggregate = serde_json::to_value(TestAggregate {
+        id: id.clone(),
+        description: "some test snapshot here".to_string(),
+        tests: vec!["testA".to_string()],
+    })
+    .unwrap();
+    event_repo
+        .persist::<TestAggregate>(
+            &[
+                test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+                test_event_envelope(&id, 2, tested("testA")),
+            ],
+            Some((id.clone(), aggregate.clone(), 1)),
+        )
+        .await
+        .unwrap();
+
+    let snapshot = event_repo.get_snapshot::<TestAggregate>(&id).await.unwrap();
+    assert_eq!(
+        Some(snapshot_context(id.clone(), 2, 1, aggregate)),
+        snapshot
+    );
+}
+
+#[wasm_bindgen_test]
+async fn proxied_streams() {
+    let (event_repo, _host) = proxy();
+    let id = uuid::Uuid::new_v4().to_string();
+    event_repo
+        .persist::<TestAggregate>(
+            &[
+                test_event_envelope(&id, 1, TestEvent::Created(Created { id: id.clone() })),
+                test_event_envelope(&id, 2, tested("testA")),
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let stream = event_repo
+        .stream_events::<TestAggregate>(&id)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![(id.clone(), 1), (id.clone(), 2)],
+        replayed(stream).await
+    );
+    let stream = event_repo
+        .stream_all_events::<TestAggregate>()
+        .await
+        .unwrap();
+    assert_eq!(vec![(id.clone(), 1), (id, 2)], replayed(stream).await);
+}
+
+#[wasm_bindgen_test]
+async fn other_handlers_kept() {
+    let channel = MessageChannel::new().unwrap();
+    let (sender, receiver) = oneshot::channel();
+    let mut sender = Some(sender);
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+        if event.data().as_string().as_deref() == Some("ping") {
+            if let Some(sender) = sender.take() {
+                let _ = sender.send(());
+            }
+        }
+    });
+    channel
+        .port2()
+        .set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    let _host = WorkerHost::serve_on(
+        IndexDbEventRepository::new(test_db_name(), None),
+        channel.port1(),
+    );
+    let event_repo = WorkerEventRepository::new(channel.port2());
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert!(events.is_empty());
+
+    // The handler set before still receives the messages, even once the proxy is dropped
+    drop(event_repo);
+    channel
+        .port1()
+        .post_message(&JsValue::from_str("ping"))
+        .unwrap();
+    receiver.await.unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn foreign_envelopes_ignored() {
+    let channel = MessageChannel::new().unwrap();
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+        let received = received.clone();
+        move |event: MessageEvent| received.borrow_mut().push(event.data())
+    });
+    channel
+        .port2()
+        .set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    let _host = WorkerHost::serve_on(
+        IndexDbEventRepository::new(test_db_name(), None),
+        channel.port1(),
+    );
+    let event_repo = WorkerEventRepository::new(channel.port2());
+
+    // A message of the application shaped like a request gets no reply
+    let message = serde_json::json!({"id": 0, "body": {"kind": "chat"}});
+    channel
+        .port2()
+        .post_message(&JsValue::from_serde(&message).unwrap())
+        .unwrap();
+    let events = event_repo.get_events::<TestAggregate>("a").await.unwrap();
+    assert!(events.is_empty());
+    assert_eq!(1, received.borrow().len());
+}
+
+fn tested(test_name: &str) -> TestEvent {
+    TestEvent::Tested(Tested {
+        test_name: test_name.to_string(),
+    })
+}
@@ -0,0 +1,13 @@
+//! The tests that need a dedicated worker, such as those of the OPFS backend.
+
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen_test::wasm_bindgen_test_configure;
+
+mod tests {
+    mod conformance;
+    mod opfs;
+    mod testing;
+}
+
+wasm_bindgen_test_configure!(run_in_dedicated_worker);